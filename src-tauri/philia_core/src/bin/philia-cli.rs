@@ -0,0 +1,533 @@
+use philia_core::{Context, DatasetExporter, DownloadQueue, Event, PhiliaError};
+use std::sync::atomic::{AtomicU32, Ordering};
+use philia::prelude::{Client, Post, SearchOrder};
+use philia_core::download::DownloadStatus;
+use philia_core::dedupe::SIMILARITY_THRESHOLD;
+use philia_core::stats::{DatasetStats, DEFAULT_TOP_TAGS};
+use philia_core::subscriptions::{self, Subscription};
+use philia_core::datasets::TagEdit;
+use philia_core::queue::QueueStatus;
+use philia_core::sources::{self, MergeOrder};
+use philia_core::normalize;
+use std::process::ExitCode;
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+const USAGE: &str = "\
+Usage: philia-cli <command> [arguments]
+
+Commands:
+  sources                                 List the available sources.
+  search <source> [options] [tags...]     Search a source and print the results.
+  download <source> [options] [tags...]   Search a source and download the results.
+  export <dataset> <path>                 Export a dataset by name or index.
+  stats <dataset> [path]                  Print the most frequent tags of a dataset, or write
+                                          its tag statistics as CSV and JSON files to a folder.
+  edit-tags <dataset> add|remove <tag>    Add or remove a tag in the captions of every image.
+  edit-tags <dataset> replace <tag> <new> Replace a tag in the captions of every image.
+  refresh-tags <source>                   Fetch and cache the full tag list of a source.
+  fetch-tag-tables <source>               Fetch the tag aliases and implications of a source.
+  find-duplicates                         List groups of downloaded images that look alike.
+  subscribe <name> <source> [options] [tags...]
+                                          Save a search to be checked for new posts.
+  unsubscribe <name>                      Remove a saved search.
+  subscriptions                           List the saved searches.
+  check-subscriptions                     Queue and download the posts newer than the last ones
+                                          seen.
+
+Search options:
+  --page <n>      Result page, starting from 1. (default: 1)
+  --limit <n>     Posts per page. (default: 32)
+  --order <o>     newest, oldest, most-liked or least-liked. (default: newest)
+  --merge <m>     How results from several sources are merged: interleave or score.
+                  (default: interleave)
+
+Tags prefixed with '-' are excluded from the results.
+Several sources can be searched at once by separating them with commas, e.g. Danbooru,Gelbooru.
+Tag aliases and implications are read from ./tags and applied to the images and exports.
+Subscriptions are always searched by newest and move past their new posts once queued.
+check-subscriptions leaves the application's download queue untouched. Posts that still fail
+after a few attempts are listed and count as seen.
+The working directory can be overridden through the PHILIA_WORK_DIR environment variable.
+
+Exit codes:
+  0  Success.
+  1  The operation failed.
+  2  Invalid arguments.
+  3  Some downloads failed.
+  4  Source or dataset not found.";
+
+const EXIT_FAILURE: u8 = 1;
+const EXIT_USAGE: u8 = 2;
+const EXIT_PARTIAL: u8 = 3;
+const EXIT_NOT_FOUND: u8 = 4;
+
+struct Failure {
+	code: u8,
+	message: String,
+}
+
+impl Failure {
+	fn new(code: u8, message: impl Into<String>) -> Self {
+		Self {
+			code,
+			message: message.into(),
+		}
+	}
+}
+
+impl From<PhiliaError> for Failure {
+	fn from(err: PhiliaError) -> Self {
+		let code = match err {
+			PhiliaError::NotFound(_) => EXIT_NOT_FOUND,
+			PhiliaError::Validation(_) => EXIT_USAGE,
+			_ => EXIT_FAILURE,
+		};
+
+		Self::new(code, err.to_string())
+	}
+}
+
+struct SearchArgs {
+	source: String,
+	page: u32,
+	limit: u32,
+	order: SearchOrder,
+	merge: MergeOrder,
+	tags: Vec<String>,
+}
+
+fn main() -> ExitCode {
+	let args: Vec<String> = std::env::args().skip(1).collect();
+	let Some((command, args)) = args.split_first() else {
+		println!("{USAGE}");
+		return ExitCode::from(EXIT_USAGE);
+	};
+
+	match set_work_dir().and_then(|()| run(command, args)) {
+		Ok(()) => ExitCode::SUCCESS,
+		Err(failure) => {
+			eprintln!("Error: {}", failure.message);
+			if failure.code == EXIT_USAGE {
+				eprintln!("Run 'philia-cli help' for usage.");
+			}
+			ExitCode::from(failure.code)
+		},
+	}
+}
+
+fn set_work_dir() -> Result<(), Failure> {
+	let Ok(value) = std::env::var("PHILIA_WORK_DIR") else {
+		return Ok(());
+	};
+
+	std::env::set_current_dir(&value).map_err(|err| {
+		Failure::new(EXIT_FAILURE, format!("Invalid work directory {value:?}: {err}"))
+	})
+}
+
+fn run(command: &str, args: &[String]) -> Result<(), Failure> {
+	match command {
+		"sources" => list_sources(),
+		"search" => parse_search_args(args).and_then(search),
+		"download" => parse_search_args(args).and_then(download),
+		"export" => match args {
+			[dataset, path] => export(dataset, PathBuf::from(path)),
+			_ => Err(Failure::new(EXIT_USAGE, "Expected a dataset and an output path.")),
+		},
+		"stats" => match args {
+			[dataset] => stats(dataset, None),
+			[dataset, path] => stats(dataset, Some(PathBuf::from(path))),
+			_ => Err(Failure::new(EXIT_USAGE, "Expected a dataset and an optional output path.")),
+		},
+		"edit-tags" => match args {
+			[dataset, op, tag] if op == "add" => edit_tags(dataset, TagEdit::Add(tag.clone())),
+			[dataset, op, tag] if op == "remove" => {
+				edit_tags(dataset, TagEdit::Remove(tag.clone()))
+			},
+			[dataset, op, tag, new] if op == "replace" => {
+				edit_tags(dataset, TagEdit::Replace(tag.clone(), new.clone()))
+			},
+			_ => Err(Failure::new(EXIT_USAGE, "Expected a dataset, an operation and its tags.")),
+		},
+		"refresh-tags" => match args {
+			[source] => refresh_tags(source),
+			_ => Err(Failure::new(EXIT_USAGE, "Expected a single source name.")),
+		},
+		"fetch-tag-tables" => match args {
+			[source] => fetch_tag_tables(source),
+			_ => Err(Failure::new(EXIT_USAGE, "Expected a single source name.")),
+		},
+		"find-duplicates" => match args {
+			[] => find_duplicates(),
+			_ => Err(Failure::new(EXIT_USAGE, "find-duplicates takes no arguments.")),
+		},
+		"subscribe" => match args.split_first() {
+			Some((name, args)) => parse_search_args(args).and_then(|args| subscribe(name, args)),
+			None => Err(Failure::new(EXIT_USAGE, "Expected a subscription name and a source.")),
+		},
+		"unsubscribe" => match args {
+			[name] => unsubscribe(name),
+			_ => Err(Failure::new(EXIT_USAGE, "Expected a single subscription name.")),
+		},
+		"subscriptions" => match args {
+			[] => list_subscriptions(),
+			_ => Err(Failure::new(EXIT_USAGE, "subscriptions takes no arguments.")),
+		},
+		"check-subscriptions" => match args {
+			[] => check_subscriptions(),
+			_ => Err(Failure::new(EXIT_USAGE, "check-subscriptions takes no arguments.")),
+		},
+		"help" | "--help" | "-h" => {
+			println!("{USAGE}");
+			Ok(())
+		},
+		_ => Err(Failure::new(EXIT_USAGE, format!("Unknown command {command:?}."))),
+	}
+}
+
+fn parse_search_args(args: &[String]) -> Result<SearchArgs, Failure> {
+	let Some((source, mut args)) = args.split_first() else {
+		return Err(Failure::new(EXIT_USAGE, "Expected a source name."));
+	};
+
+	let mut search = SearchArgs {
+		source: source.clone(),
+		page: 1,
+		limit: 32,
+		order: SearchOrder::Newest,
+		merge: MergeOrder::Interleave,
+		tags: vec![],
+	};
+
+	while let Some((arg, rest)) = args.split_first() {
+		args = rest;
+		let option = match arg.as_str() {
+			"--page" | "--limit" | "--order" | "--merge" => arg.as_str(),
+			_ => {
+				search.tags.push(arg.clone());
+				continue;
+			},
+		};
+
+		let Some((value, rest)) = args.split_first() else {
+			return Err(Failure::new(EXIT_USAGE, format!("Missing value for {option}.")));
+		};
+
+		args = rest;
+		let invalid = || Failure::new(EXIT_USAGE, format!("Invalid value {value:?} for {option}."));
+		match option {
+			"--page" => search.page = value.parse().map_err(|_| invalid())?,
+			"--limit" => search.limit = value.parse().map_err(|_| invalid())?,
+			"--merge" => {
+				search.merge = match value.as_str() {
+					"interleave" => MergeOrder::Interleave,
+					"score" => MergeOrder::Score,
+					_ => return Err(invalid()),
+				}
+			},
+			_ => {
+				search.order = match value.as_str() {
+					"newest" => SearchOrder::Newest,
+					"oldest" => SearchOrder::Oldest,
+					"most-liked" => SearchOrder::MostLiked,
+					"least-liked" => SearchOrder::LeastLiked,
+					_ => return Err(invalid()),
+				}
+			},
+		}
+	}
+
+	Ok(search)
+}
+
+fn load_context() -> Context {
+	Context::load()
+}
+
+/// Loads the context along with the images of the download folder,
+/// for the commands that read or check against them.
+fn load_library() -> Context {
+	Context::load_or_default()
+}
+
+fn block_on<F: Future>(future: F) -> F::Output {
+	let runtime = tokio::runtime::Runtime::new().expect("Could not start async runtime");
+	runtime.block_on(future)
+}
+
+fn get_client(context: &Context, source: &str) -> Result<Client, Failure> {
+	match context.sources.client(source) {
+		Some(client) => Ok(client),
+		None => Err(Failure::new(EXIT_NOT_FOUND, format!("Source {source:?} not found."))),
+	}
+}
+
+/// Searches the source named in `args`, or every source of a comma-separated list concurrently.
+async fn find_posts(context: &Context, args: SearchArgs) -> Result<Vec<Post>, Failure> {
+	let names: Vec<_> = args.source.split(',').map(str::trim).filter(|s| !s.is_empty()).collect();
+	if let [source] = names[..] {
+		let client = get_client(context, source)?;
+		return Ok(sources::search(&client, args.page, args.limit, args.order, args.tags).await?);
+	}
+
+	let clients = names
+		.iter()
+		.map(|name| Ok((name.to_string(), get_client(context, name)?)))
+		.collect::<Result<Vec<_>, Failure>>()?;
+
+	let results = sources::federated_search(
+		clients, args.page, args.limit, args.order, args.tags, args.merge,
+	)
+	.await;
+
+	for error in &results.errors {
+		eprintln!("Could not search {}: {}", error.source, error.error);
+	}
+
+	match results.posts.is_empty() && !results.errors.is_empty() {
+		true => Err(Failure::new(EXIT_FAILURE, "Every source failed.")),
+		false => Ok(results.posts),
+	}
+}
+
+fn list_sources() -> Result<(), Failure> {
+	let context = load_context();
+	for name in context.sources.names() {
+		println!("{name}");
+	}
+	Ok(())
+}
+
+fn search(args: SearchArgs) -> Result<(), Failure> {
+	let context = load_context();
+	let posts = block_on(find_posts(&context, args))?;
+
+	for post in posts {
+		let url = post.resource_url.as_deref().unwrap_or("-");
+		println!("{}\t{}\t{:?}\t{}", post.id, post.score, post.rating, url);
+	}
+
+	Ok(())
+}
+
+fn download(args: SearchArgs) -> Result<(), Failure> {
+	let context = load_library();
+	let download_folder = context.settings.download_folder.clone();
+	let downloader = context.downloader();
+
+	let reports = block_on(async move {
+		let posts = find_posts(&context, args).await?;
+
+		println!("Downloading {} posts to {:?}...", posts.len(), download_folder);
+
+		let last_progress = AtomicU32::new(u32::MAX);
+		let events = Arc::new(move |event: Event| {
+			if let Event::DownloadProgress(progress) = event {
+				let progress = progress as u32;
+				if last_progress.swap(progress, Ordering::Relaxed) != progress {
+					println!("Progress: {progress}%");
+				}
+			}
+		});
+
+		Ok::<_, Failure>(downloader.download_posts(posts, events).await)
+	})?;
+
+	let mut failed = 0;
+	for report in reports {
+		let key = &report.key;
+		let message = report.message.unwrap_or_default();
+		match report.status {
+			DownloadStatus::Saved => {
+				let path = report.path.unwrap_or_default().display().to_string();
+				let (kib, ms) = (report.bytes / 1024, report.duration_ms);
+				println!("Downloaded {key}: {path} ({kib} KiB, {ms} ms)");
+			},
+			DownloadStatus::Exists | DownloadStatus::Duplicate => {
+				println!("Skipped {key}: {message}");
+			},
+			DownloadStatus::Failed => {
+				failed += 1;
+				let kind = report.error_kind.map(|kind| format!("{kind:?}")).unwrap_or_default();
+				match report.http_status {
+					Some(status) => println!("Failed {key}: [{kind}, HTTP {status}] {message}"),
+					None => println!("Failed {key}: [{kind}] {message}"),
+				}
+			},
+		}
+	}
+
+	match failed {
+		0 => Ok(()),
+		_ => Err(Failure::new(EXIT_PARTIAL, format!("Failed to download {failed} posts."))),
+	}
+}
+
+fn find_duplicates() -> Result<(), Failure> {
+	let mut context = load_library();
+	let groups = context.library.find_duplicates(SIMILARITY_THRESHOLD);
+	for group in &groups {
+		let paths: Vec<_> = group.iter().map(|path| path.to_string_lossy()).collect();
+		println!("{}", paths.join("\t"));
+	}
+
+	println!("Found {} groups of duplicates.", groups.len());
+	Ok(())
+}
+
+fn export(dataset: &str, path: PathBuf) -> Result<(), Failure> {
+	let context = load_library();
+	let Some(dataset) = context.library.find_dataset(dataset) else {
+		return Err(Failure::new(EXIT_NOT_FOUND, format!("Dataset {dataset:?} not found.")));
+	};
+
+	println!("Exporting dataset {:?}...", dataset.name());
+	let path = DatasetExporter::new(&context.library.images).export(dataset, &path)?;
+	println!("Exported dataset to {:?}.", path);
+	Ok(())
+}
+
+fn stats(dataset: &str, path: Option<PathBuf>) -> Result<(), Failure> {
+	let context = load_library();
+	let Some(dataset) = context.library.find_dataset(dataset) else {
+		return Err(Failure::new(EXIT_NOT_FOUND, format!("Dataset {dataset:?} not found.")));
+	};
+
+	let stats = DatasetStats::compute(dataset, &context.library.images, DEFAULT_TOP_TAGS);
+	if let Some(path) = path {
+		stats.write(&path)?;
+		println!("Wrote the tag statistics of {:?} to {:?}.", dataset.name(), path);
+		return Ok(());
+	}
+
+	println!("{} images, {} distinct tags.", stats.images, stats.tags.len());
+	for tag in stats.tags.iter().take(DEFAULT_TOP_TAGS) {
+		let category = tag.category.as_deref().unwrap_or("-");
+		println!("{:>6}  {:>5.1}%  {}  ({})", tag.count, tag.frequency * 100.0, tag.tag, category);
+	}
+
+	Ok(())
+}
+
+fn edit_tags(dataset: &str, edit: TagEdit) -> Result<(), Failure> {
+	let mut context = load_context();
+	let Some(index) = context.library.find_dataset_index(dataset) else {
+		return Err(Failure::new(EXIT_NOT_FOUND, format!("Dataset {dataset:?} not found.")));
+	};
+
+	context.library.edit_dataset_tags(index, &[], &edit)?;
+	println!("Edited the captions of {:?}.", context.library.datasets[index].name());
+	Ok(())
+}
+
+fn refresh_tags(source: &str) -> Result<(), Failure> {
+	let mut context = load_context();
+	let client = get_client(&context, source)?;
+
+	let events = |event: Event| {
+		if let Event::FetchSourceTagsCount(count) = event {
+			println!("Fetched {count} tags...");
+		}
+	};
+
+	let tags = block_on(sources::fetch_tags(&client, &events))?;
+
+	println!("Fetched {} tags from {source}.", tags.len());
+	context.sources.set_tags(source, &tags);
+	Ok(())
+}
+
+fn fetch_tag_tables(source: &str) -> Result<(), Failure> {
+	let context = load_context();
+	let Some(urls) = context.sources.tag_tables(source) else {
+		return Err(Failure::new(EXIT_NOT_FOUND, format!("Source {source:?} not found.")));
+	};
+
+	let table = block_on(normalize::fetch_tag_tables(source, urls, &context.throttle))?;
+
+	println! {
+		"Fetched {} aliases and {} implications from {source}.",
+		table.aliases.len(),
+		table.implications.values().map(Vec::len).sum::<usize>(),
+	};
+	Ok(())
+}
+
+fn subscribe(name: &str, args: SearchArgs) -> Result<(), Failure> {
+	let mut context = load_context();
+	get_client(&context, &args.source)?;
+
+	context.subscriptions.add(Subscription {
+		name: name.to_string(),
+		source: args.source,
+		tags: args.tags,
+		order: args.order,
+		last_seen_id: None,
+	})?;
+
+	println!("Subscribed to {name:?}.");
+	Ok(())
+}
+
+fn unsubscribe(name: &str) -> Result<(), Failure> {
+	let mut context = load_context();
+	context.subscriptions.remove(name)?;
+	println!("Unsubscribed from {name:?}.");
+	Ok(())
+}
+
+fn list_subscriptions() -> Result<(), Failure> {
+	let context = load_context();
+	for subscription in &context.subscriptions.list {
+		let last_seen = subscription.last_seen_id.map_or("-".to_string(), |id| id.to_string());
+		let tags = subscription.tags.join(" ");
+		println!("{}\t{}\t{}\t{}", subscription.name, subscription.source, last_seen, tags);
+	}
+	Ok(())
+}
+
+fn check_subscriptions() -> Result<(), Failure> {
+	let mut context = load_library();
+	let downloader = context.downloader();
+
+	// The application owns the persisted queue, so only the new posts are queued and run here.
+	let queue = DownloadQueue::in_memory();
+	context.queue = queue.clone();
+
+	let context = Mutex::new(context);
+	let (checks, items) = block_on(async {
+		let checks = subscriptions::check_all(&context, &|_: Event| {}).await;
+		let keys: Vec<_> = checks.iter().flat_map(|check| check.keys.clone()).collect();
+		println!("Downloading {} new posts...", keys.len());
+
+		// The workers stop when the runtime shuts down, once the new posts are downloaded.
+		let runner = queue.clone();
+		tokio::spawn(async move { runner.run(downloader, Arc::new(|_: Event| {})).await });
+		let items = queue.wait(&keys, &|_: Event| {}).await;
+		(checks, items)
+	});
+
+	let mut failed = 0;
+	for check in &checks {
+		if let Some(err) = &check.error {
+			failed += 1;
+			eprintln!("Could not check {:?}: {err}", check.name);
+		}
+	}
+
+	let mut failures = 0;
+	for item in items.iter().filter(|item| item.status == QueueStatus::Failed) {
+		failures += 1;
+		let url = item.post.resource_url.as_deref().unwrap_or("-");
+		let message = item.error.as_deref().unwrap_or_default();
+		eprintln!("Failed {}: {url} {message}", item.key);
+	}
+
+	match (failed, failures) {
+		(0, 0) => Ok(()),
+		(0, _) => Err(Failure::new(EXIT_PARTIAL, format!("Failed to download {failures} posts."))),
+		_ => Err(Failure::new(EXIT_PARTIAL, format!("Failed to check {failed} subscriptions."))),
+	}
+}
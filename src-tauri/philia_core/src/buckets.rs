@@ -0,0 +1,217 @@
+use crate::datasets::ExportedFile;
+use serde::{Deserialize, Serialize};
+use crate::error::PhiliaError;
+use std::collections::BTreeMap;
+use itertools::Itertools;
+use std::path::Path;
+
+const DEFAULT_AREA: u32 = 1024 * 1024;
+const DEFAULT_STEP: u32 = 64;
+const DEFAULT_MAX_ASPECT_RATIO: f32 = 2.0;
+const BUCKET_REPORT: &str = "bucket_report.json";
+
+/// Resizes each image to the bucket closest to its aspect ratio, instead of letterboxing it
+/// and resizing it to a single fixed size.
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct BucketSettings {
+	#[serde(default = "Default::default")]
+	pub enabled: bool,
+	/// Maximum number of pixels of a bucket. Zero uses 1024x1024.
+	#[serde(default = "Default::default")]
+	pub area: u32,
+	/// Bucket sides are multiples of `step`. Zero uses 64.
+	#[serde(default = "Default::default")]
+	pub step: u32,
+	/// Maximum ratio between the long and the short side of a bucket. Values below 1 use 2.
+	#[serde(default = "Default::default")]
+	pub max_aspect_ratio: f32,
+}
+
+impl BucketSettings {
+	/// The largest bucket fitting the target area for each height, by increasing aspect ratio.
+	pub fn buckets(&self) -> Vec<Bucket> {
+		if !self.enabled {
+			return vec![];
+		}
+
+		let area = self.area();
+		let step = match self.step {
+			0 => DEFAULT_STEP,
+			step => step,
+		};
+
+		let max_aspect_ratio = match self.max_aspect_ratio {
+			ratio if ratio >= 1.0 => ratio,
+			_ => DEFAULT_MAX_ASPECT_RATIO,
+		};
+
+		let mut buckets: Vec<Bucket> = vec![];
+		for width in (step..=area / step).step_by(step as usize) {
+			let height = area / width / step * step;
+			let bucket = Bucket { width, height };
+			if height == 0 || bucket.long_side_ratio() > max_aspect_ratio {
+				continue;
+			}
+
+			// Widths increase, so the previous bucket with the same height is smaller.
+			match buckets.last_mut() {
+				Some(last) if last.height == height => *last = bucket,
+				_ => buckets.push(bucket),
+			}
+		}
+
+		buckets
+	}
+
+	pub fn side(&self) -> u32 {
+		(self.area() as f64).sqrt() as u32
+	}
+
+	fn area(&self) -> u32 {
+		match self.area {
+			0 => DEFAULT_AREA,
+			area => area,
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Bucket {
+	pub width: u32,
+	pub height: u32,
+}
+
+impl Bucket {
+	pub fn aspect_ratio(&self) -> f32 {
+		self.width as f32 / self.height as f32
+	}
+
+	fn long_side_ratio(&self) -> f32 {
+		self.width.max(self.height) as f32 / self.width.min(self.height).max(1) as f32
+	}
+}
+
+/// Returns the bucket whose aspect ratio is closest to the one of a `width` by `height` image.
+pub fn nearest(buckets: &[Bucket], width: u32, height: u32) -> Option<Bucket> {
+	let ratio = (width as f32 / height.max(1) as f32).ln();
+	let distance = |bucket: &&Bucket| (bucket.aspect_ratio().ln() - ratio).abs();
+	buckets.iter().min_by(|a, b| distance(a).total_cmp(&distance(b))).copied()
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BucketCount {
+	pub width: u32,
+	pub height: u32,
+	pub aspect_ratio: f32,
+	pub count: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BucketReport {
+	pub buckets: Vec<BucketCount>,
+	pub images: BTreeMap<String, Bucket>,
+}
+
+impl BucketReport {
+	pub(crate) fn new(buckets: &[Bucket], files: &[ExportedFile]) -> Self {
+		let images: BTreeMap<_, _> = files
+			.iter()
+			.filter_map(|file| Some((file.file_name.clone(), file.bucket?)))
+			.collect();
+
+		let counts = images.values().copied().counts();
+		let buckets = buckets
+			.iter()
+			.map(|bucket| BucketCount {
+				width: bucket.width,
+				height: bucket.height,
+				aspect_ratio: bucket.aspect_ratio(),
+				count: counts.get(bucket).copied().unwrap_or(0),
+			})
+			.collect();
+
+		Self { buckets, images }
+	}
+
+	/// Writes the report as `bucket_report.json` inside `folder`.
+	pub fn write(&self, folder: &Path) -> Result<(), PhiliaError> {
+		std::fs::write(folder.join(BUCKET_REPORT), serde_json::to_string_pretty(self)?)?;
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn enabled() -> BucketSettings {
+		BucketSettings {
+			enabled: true,
+			..Default::default()
+		}
+	}
+
+	#[test]
+	fn disabled_has_no_buckets() {
+		assert!(BucketSettings::default().buckets().is_empty());
+	}
+
+	#[test]
+	fn default_buckets() {
+		let buckets = enabled().buckets();
+		assert!(buckets.contains(&Bucket { width: 1024, height: 1024 }));
+		assert!(buckets.contains(&Bucket { width: 1408, height: 704 }));
+		assert!(buckets.contains(&Bucket { width: 768, height: 1344 }));
+
+		for bucket in &buckets {
+			assert_eq!(bucket.width % DEFAULT_STEP, 0);
+			assert_eq!(bucket.height % DEFAULT_STEP, 0);
+			assert!(bucket.width * bucket.height <= DEFAULT_AREA);
+			assert!(bucket.long_side_ratio() <= DEFAULT_MAX_ASPECT_RATIO);
+		}
+
+		for pair in buckets.windows(2) {
+			assert!(pair[0].aspect_ratio() < pair[1].aspect_ratio());
+			assert_ne!(pair[0].height, pair[1].height);
+		}
+	}
+
+	#[test]
+	fn custom_buckets() {
+		let settings = BucketSettings {
+			area: 512 * 512,
+			step: 128,
+			max_aspect_ratio: 4.0,
+			..enabled()
+		};
+
+		let expected = [(256, 1024), (384, 640), (512, 512), (640, 384), (1024, 256)];
+		let expected = expected.map(|(width, height)| Bucket { width, height });
+		assert_eq!(settings.buckets(), expected);
+		assert_eq!(settings.side(), 512);
+	}
+
+	#[test]
+	fn invalid_settings_use_defaults() {
+		let settings = BucketSettings {
+			max_aspect_ratio: 0.5,
+			..enabled()
+		};
+
+		assert_eq!(settings.buckets(), enabled().buckets());
+		assert_eq!(settings.side(), 1024);
+	}
+
+	#[test]
+	fn nearest_bucket() {
+		let buckets = enabled().buckets();
+		assert_eq!(nearest(&buckets, 2000, 2000), Some(Bucket { width: 1024, height: 1024 }));
+		assert_eq!(nearest(&buckets, 300, 290), Some(Bucket { width: 1024, height: 1024 }));
+		assert_eq!(nearest(&buckets, 4000, 100), buckets.last().copied());
+		assert_eq!(nearest(&buckets, 100, 4000), buckets.first().copied());
+		assert_eq!(nearest(&[], 100, 100), None);
+
+		let wide = nearest(&buckets, 1920, 1080).unwrap();
+		assert!((wide.aspect_ratio() - 16.0 / 9.0).abs() < 0.1);
+	}
+}
@@ -0,0 +1,168 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use crate::events::{Event, EventSink};
+use philia::prelude::{Client, SearchOrder};
+use serde::{Deserialize, Serialize};
+use crate::queue::DownloadQueue;
+use crate::download::Downloader;
+use crate::error::PhiliaError;
+use fxhash::{FxHashMap, FxHashSet};
+use crate::sources;
+use std::sync::Arc;
+
+const DEFAULT_PAGE_SIZE: u32 = 100;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BulkDownloadRequest {
+	pub source: String,
+	#[serde(default = "Default::default")]
+	pub tags: Vec<String>,
+	pub order: SearchOrder,
+	/// Maximum number of posts to queue. Zero queues every result.
+	#[serde(default = "Default::default")]
+	pub max_posts: usize,
+	/// Maximum number of pages to fetch. Zero fetches pages until the results run out.
+	#[serde(default = "Default::default")]
+	pub max_pages: u32,
+	/// Posts requested per page. Zero uses the default of 100.
+	#[serde(default = "Default::default")]
+	pub page_size: u32,
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct BulkDownloadProgress {
+	pub id: u64,
+	pub pages: u32,
+	pub queued: usize,
+	pub finished: bool,
+	pub cancelled: bool,
+	/// Why the search stopped early, if it did.
+	pub error: Option<PhiliaError>,
+}
+
+#[derive(Debug, Clone)]
+pub struct BulkDownloadHandle {
+	id: u64,
+	cancelled: Arc<AtomicBool>,
+}
+
+impl BulkDownloadHandle {
+	pub fn id(&self) -> u64 {
+		self.id
+	}
+
+	pub fn cancel(&self) {
+		self.cancelled.store(true, Ordering::Relaxed);
+	}
+
+	pub fn is_cancelled(&self) -> bool {
+		self.cancelled.load(Ordering::Relaxed)
+	}
+}
+
+#[derive(Default)]
+pub struct BulkDownloads {
+	next_id: u64,
+	running: FxHashMap<u64, BulkDownloadHandle>,
+}
+
+impl BulkDownloads {
+	pub fn start(&mut self) -> BulkDownloadHandle {
+		self.next_id += 1;
+		let handle = BulkDownloadHandle {
+			id: self.next_id,
+			cancelled: Default::default(),
+		};
+
+		self.running.insert(handle.id, handle.clone());
+		handle
+	}
+
+	pub fn finish(&mut self, id: u64) {
+		self.running.remove(&id);
+	}
+
+	/// Cancels the given bulk download, or every one if `id` is `None`.
+	pub fn cancel(&self, id: Option<u64>) {
+		for (key, handle) in &self.running {
+			if id.map_or(true, |id| *key == id) {
+				handle.cancel();
+			}
+		}
+	}
+}
+
+/// Queues the results of `request` page by page, emitting `Event::BulkDownloadProgress`,
+/// until a page comes back empty or a limit is reached.
+/// When cancelled, the posts it queued that have not been downloaded yet are cancelled too.
+pub async fn bulk_download(
+	client: &Client, request: &BulkDownloadRequest, queue: &DownloadQueue, downloader: &Downloader,
+	handle: &BulkDownloadHandle, events: &dyn EventSink,
+) -> BulkDownloadProgress {
+	let page_size = match request.page_size {
+		0 => DEFAULT_PAGE_SIZE,
+		page_size => page_size,
+	};
+
+	let mut progress = BulkDownloadProgress {
+		id: handle.id,
+		..Default::default()
+	};
+	let mut keys = vec![];
+	let mut seen = FxHashSet::default();
+	let mut page = 1;
+	loop {
+		let max_posts_reached = request.max_posts != 0 && progress.queued >= request.max_posts;
+		let max_pages_reached = request.max_pages != 0 && page > request.max_pages;
+		if max_posts_reached || max_pages_reached || handle.is_cancelled() {
+			break;
+		}
+
+		downloader.throttle().wait_turn(&request.source).await;
+		let (order, tags) = (request.order.clone(), request.tags.clone());
+		let posts = match sources::search(client, page, page_size, order, tags).await {
+			Ok(posts) => posts,
+			Err(error) => {
+				progress.error = Some(error);
+				break;
+			},
+		};
+
+		if posts.is_empty() {
+			break;
+		}
+
+		// Results can shift between pages while new posts are uploaded,
+		// so posts seen on an earlier page are skipped.
+		let mut new_posts = vec![];
+		for post in posts {
+			let queued = progress.queued + new_posts.len();
+			if handle.is_cancelled() || (request.max_posts != 0 && queued >= request.max_posts) {
+				break;
+			}
+
+			if seen.insert(post.id) {
+				new_posts.push(post);
+			}
+		}
+
+		if handle.is_cancelled() {
+			break;
+		}
+
+		progress.pages = page;
+		progress.queued += new_posts.len();
+		let (folder, storage) = (downloader.download_folder(), downloader.storage());
+		keys.extend(queue.enqueue(new_posts, folder, storage));
+		events.emit(Event::BulkDownloadProgress(progress.clone()));
+		page += 1;
+	}
+
+	if handle.is_cancelled() {
+		progress.cancelled = true;
+		queue.cancel(Some(&keys));
+	}
+
+	progress.finished = true;
+	events.emit(Event::BulkDownloadProgress(progress.clone()));
+	progress
+}
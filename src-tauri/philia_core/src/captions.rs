@@ -0,0 +1,307 @@
+use serde::{Deserialize, Serialize};
+use fxhash::{FxHashMap, FxHashSet};
+use std::path::{Path, PathBuf};
+use crate::datasets::{CaptionTag, Dataset};
+use crate::images::Image;
+use philia::prelude::Post;
+use itertools::Itertools;
+use std::cmp::Reverse;
+use std::ops::Range;
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TagOrdering {
+	#[default]
+	Source,
+	/// By category, following `CaptionSettings::category_order`.
+	Category,
+	/// The most frequent tags of the dataset first.
+	Frequency,
+	Alphabetical,
+	/// Shuffled with `CaptionSettings::seed`, differently for each image.
+	Shuffle,
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct CaptionSettings {
+	/// Comma separated parts, such as `"{keyword}, {rating}, {character}, {general}"`.
+	/// `{keyword}` is the training keyword, `{rating}` the rating of the post, `{tags}` every tag
+	/// not placed by another placeholder and any other `{name}` the tags of that category.
+	/// Uncategorized tags belong to `{general}`. An empty template is the same as `"{tags}"`.
+	#[serde(default = "Default::default")]
+	pub template: String,
+	#[serde(default = "Default::default")]
+	pub order: TagOrdering,
+	/// Categories in the order used by `TagOrdering::Category`. Unlisted categories come last.
+	#[serde(default = "Default::default")]
+	pub category_order: Vec<String>,
+	#[serde(default = "Default::default")]
+	pub seed: u64,
+	/// Number of leading tokens of the caption that are neither reordered nor dropped.
+	#[serde(default = "Default::default")]
+	pub keep_tokens: usize,
+	/// Maximum number of tags in a caption, dropping the last ones. Zero keeps every tag.
+	#[serde(default = "Default::default")]
+	pub max_tags: usize,
+}
+
+pub struct CaptionWriter<'l> {
+	dataset: &'l Dataset,
+	frequencies: FxHashMap<String, usize>,
+}
+
+impl<'l> CaptionWriter<'l> {
+	pub fn new(dataset: &'l Dataset, images: &FxHashMap<PathBuf, Image>) -> Self {
+		let mut frequencies = FxHashMap::default();
+		if dataset.settings().caption.order == TagOrdering::Frequency {
+			for image in dataset.images() {
+				let Some(post) = images.get(Path::new(image)) else { continue };
+				for tag in dataset.caption_tags(image, &post.info) {
+					*frequencies.entry(tag.tag).or_default() += 1;
+				}
+			}
+		}
+
+		Self { dataset, frequencies }
+	}
+
+	/// A custom caption set through the image's overrides is returned as is.
+	pub fn caption(&self, image: &str, post: &Post) -> String {
+		if let Some(caption) = self.dataset.custom_caption(image) {
+			return caption.to_string();
+		}
+
+		let settings = self.dataset.settings();
+		let tags = self.dataset.caption_tags(image, post);
+		let keyword = settings.training.keyword.trim();
+		compose(&settings.caption, keyword, &rating(post), &tags, |tokens, categories| {
+			self.order(image, tokens, categories)
+		})
+	}
+
+	fn order(&self, image: &str, tokens: &mut [String], categories: &FxHashMap<&str, &str>) {
+		let settings = &self.dataset.settings().caption;
+		match settings.order {
+			TagOrdering::Source => {},
+			TagOrdering::Alphabetical => tokens.sort(),
+			TagOrdering::Frequency => {
+				tokens.sort_by_key(|tag| Reverse(self.frequencies.get(tag).copied().unwrap_or(0)))
+			},
+			TagOrdering::Shuffle => {
+				tokens.sort_by_cached_key(|tag| fxhash::hash64(&(settings.seed, image, tag)))
+			},
+			TagOrdering::Category => tokens.sort_by_cached_key(|tag| {
+				let category = categories.get(tag.as_str()).copied().unwrap_or("general");
+				let order = &settings.category_order;
+				order.iter().position(|c| c.eq_ignore_ascii_case(category)).unwrap_or(order.len())
+			}),
+		}
+	}
+}
+
+/// Fills the caption template with `tags`, ordering the tags of each placeholder with `order`,
+/// then drops the last tags past `CaptionSettings::max_tags`.
+fn compose(
+	settings: &CaptionSettings, keyword: &str, rating: &str, tags: &[CaptionTag],
+	order: impl Fn(&mut [String], &FxHashMap<&str, &str>),
+) -> String {
+	let template = match settings.template.trim() {
+		"" => "{tags}",
+		template => template,
+	};
+
+	let parts = template.split(',').map(str::trim).filter(|part| !part.is_empty());
+	let parts = parts.collect_vec();
+	let placeholders: FxHashSet<_> =
+		parts.iter().filter_map(|part| placeholder(part)).map(str::to_lowercase).collect();
+
+	let categories: FxHashMap<&str, &str> = tags
+		.iter()
+		.map(|tag| (tag.tag.as_str(), tag.category.unwrap_or("general")))
+		.collect();
+
+	let mut tokens = vec![];
+	let mut groups: Vec<Range<usize>> = vec![];
+	let mut seen = FxHashSet::default();
+	let mut push = |tokens: &mut Vec<String>, token: String| {
+		if !token.is_empty() && seen.insert(token.clone()) {
+			tokens.push(token);
+		}
+	};
+
+	for part in parts {
+		let name = placeholder(part).map(str::to_lowercase);
+		match name.as_deref() {
+			Some("keyword") => push(&mut tokens, keyword.to_string()),
+			Some("rating") => push(&mut tokens, rating.to_string()),
+			Some(name) => {
+				let start = tokens.len();
+				for tag in tags {
+					let category = categories[tag.tag.as_str()].to_lowercase();
+					let placed = match name {
+						"tags" => !placeholders.contains(&category),
+						name => category == name,
+					};
+
+					if placed {
+						push(&mut tokens, tag.tag.clone());
+					}
+				}
+				groups.push(start..tokens.len());
+			},
+			None => {
+				let part = part.replace("{keyword}", keyword).replace("{rating}", rating);
+				push(&mut tokens, part);
+			},
+		}
+	}
+
+	let keep_tokens = settings.keep_tokens;
+	for group in &groups {
+		let start = group.start.max(keep_tokens);
+		if start < group.end {
+			order(&mut tokens[start..group.end], &categories);
+		}
+	}
+
+	let max_tags = settings.max_tags;
+	let mut tag_count = groups.iter().map(|group| group.len()).sum::<usize>();
+	if max_tags != 0 && tag_count > max_tags {
+		let mut dropped = vec![false; tokens.len()];
+		for index in (keep_tokens..tokens.len()).rev() {
+			if tag_count <= max_tags {
+				break;
+			}
+
+			if groups.iter().any(|group| group.contains(&index)) {
+				dropped[index] = true;
+				tag_count -= 1;
+			}
+		}
+
+		let mut dropped = dropped.into_iter();
+		tokens.retain(|_| !dropped.next().unwrap_or_default());
+	}
+
+	tokens.join(", ")
+}
+
+/// Returns the name of a part made of a single `{name}` placeholder.
+fn placeholder(part: &str) -> Option<&str> {
+	let name = part.strip_prefix('{')?.strip_suffix('}')?.trim();
+	match name.contains(|c: char| matches!(c, '{' | '}')) {
+		true => None,
+		false => Some(name),
+	}
+}
+
+pub(crate) fn rating(post: &Post) -> String {
+	match serde_json::to_value(&post.rating) {
+		Ok(serde_json::Value::String(rating)) => rating.to_lowercase(),
+		_ => String::new(),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn tags() -> Vec<CaptionTag<'static>> {
+		let tags = [
+			(Some("character"), "alice"),
+			(Some("general"), "smile"),
+			(None, "outdoors"),
+			(Some("artist"), "bob"),
+		];
+
+		tags.into_iter().map(|(category, tag)| CaptionTag { category, tag: tag.into() }).collect()
+	}
+
+	fn settings(template: &str) -> CaptionSettings {
+		CaptionSettings {
+			template: template.to_string(),
+			..Default::default()
+		}
+	}
+
+	fn caption(settings: &CaptionSettings, keyword: &str) -> String {
+		compose(settings, keyword, "safe", &tags(), |_, _| {})
+	}
+
+	#[test]
+	fn empty_template_lists_every_tag() {
+		assert_eq!(caption(&settings(""), "kw"), "alice, smile, outdoors, bob");
+		assert_eq!(caption(&settings(" {tags} "), "kw"), "alice, smile, outdoors, bob");
+	}
+
+	#[test]
+	fn placeholders() {
+		let template = "{keyword}, {rating}, {character}, {tags}";
+		assert_eq!(caption(&settings(template), "kw"), "kw, safe, alice, smile, outdoors, bob");
+
+		// Uncategorized tags belong to `{general}`.
+		assert_eq!(caption(&settings("{General}, {artist}"), "kw"), "smile, outdoors, bob");
+		assert_eq!(caption(&settings("{copyright}, {tags}"), "kw"), "alice, smile, outdoors, bob");
+	}
+
+	#[test]
+	fn literal_parts() {
+		let template = "photo of {keyword}, {rating} image, , {artist}";
+		assert_eq!(caption(&settings(template), "kw"), "photo of kw, safe image, bob");
+		assert_eq!(caption(&settings("{keyword}, {tags}"), ""), "alice, smile, outdoors, bob");
+	}
+
+	#[test]
+	fn duplicates_are_removed() {
+		let template = "{keyword}, {tags}, {character}";
+		assert_eq!(caption(&settings(template), "alice"), "alice, smile, outdoors, bob");
+	}
+
+	#[test]
+	fn keep_tokens_are_not_reordered() {
+		let settings = CaptionSettings {
+			keep_tokens: 2,
+			..settings("{keyword}, {tags}")
+		};
+
+		let caption = compose(&settings, "kw", "safe", &tags(), |tokens, _| tokens.reverse());
+		assert_eq!(caption, "kw, alice, bob, outdoors, smile");
+	}
+
+	#[test]
+	fn groups_are_ordered_separately() {
+		let settings = settings("{character}, {keyword}, {general}");
+		let caption = compose(&settings, "kw", "safe", &tags(), |tokens, _| tokens.sort());
+		assert_eq!(caption, "alice, kw, outdoors, smile");
+	}
+
+	#[test]
+	fn max_tags_drops_the_last_tags() {
+		let settings = CaptionSettings {
+			max_tags: 2,
+			..settings("{keyword}, {tags}, {rating}")
+		};
+		assert_eq!(caption(&settings, "kw"), "kw, alice, smile, safe");
+
+		let settings = CaptionSettings {
+			max_tags: 1,
+			keep_tokens: 3,
+			..settings
+		};
+		assert_eq!(caption(&settings, "kw"), "kw, alice, smile, safe");
+
+		let settings = CaptionSettings {
+			max_tags: 10,
+			..settings
+		};
+		assert_eq!(caption(&settings, "kw"), "kw, alice, smile, outdoors, bob, safe");
+	}
+
+	#[test]
+	fn placeholder_names() {
+		assert_eq!(placeholder("{tags}"), Some("tags"));
+		assert_eq!(placeholder("{ General }"), Some("General"));
+		assert_eq!(placeholder("photo of {keyword}"), None);
+		assert_eq!(placeholder("{a}{b}"), None);
+		assert_eq!(placeholder("tags"), None);
+	}
+}
@@ -0,0 +1,111 @@
+use crate::subscriptions::Subscriptions;
+use crate::bulk::BulkDownloads;
+use crate::sources::SourceRegistry;
+use crate::normalize::TagNormalizer;
+use crate::dedupe::KnownHashes;
+use crate::queue::DownloadQueue;
+use crate::download::Downloader;
+use crate::settings::Settings;
+use crate::throttle::Throttle;
+use crate::library::{ImagesChanged, Library};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+pub struct Context {
+	pub settings: Settings,
+	pub library: Library,
+	pub sources: SourceRegistry,
+	pub queue: DownloadQueue,
+	pub subscriptions: Subscriptions,
+	pub bulk_downloads: BulkDownloads,
+	pub throttle: Arc<Throttle>,
+	pub known_hashes: Arc<KnownHashes>,
+}
+
+impl Context {
+	/// Loads everything but the images of the download folder, left empty until `refresh_images`.
+	pub fn load() -> Self {
+		let mut ctx = Self {
+			settings: Default::default(),
+			library: Library::new(),
+			sources: Default::default(),
+			queue: DownloadQueue::load(),
+			subscriptions: Subscriptions::load(),
+			bulk_downloads: Default::default(),
+			throttle: Arc::new(Throttle::new(0)),
+			known_hashes: Default::default(),
+		};
+
+		ctx.refresh_settings();
+		ctx.library.refresh_datasets();
+		ctx.sources.refresh();
+
+		ctx.throttle = Arc::new(Throttle::new(ctx.settings.download_concurrency as usize));
+		ctx.update_throttle();
+		ctx.known_hashes.set_enabled(ctx.settings.skip_duplicate_downloads);
+		ctx
+	}
+
+	/// Loads the context and reads the images of the download folder.
+	pub fn load_or_default() -> Self {
+		let mut ctx = Self::load();
+		ctx.refresh_images();
+		ctx
+	}
+
+	pub fn downloader(&self) -> Downloader {
+		Downloader::new(
+			self.settings.download_folder.clone(),
+			self.settings.storage_mode,
+			self.throttle.clone(),
+		)
+		.with_known_hashes(self.known_hashes.clone())
+	}
+
+	pub fn update_throttle(&self) {
+		let mut limits = self.sources.rate_limits().clone();
+		for (source, limit) in &self.settings.source_requests_per_second {
+			limits.insert(source.clone(), *limit);
+		}
+
+		self.throttle.configure(self.settings.requests_per_second, limits);
+	}
+
+	/// The images are only hashed while duplicate downloads are skipped.
+	pub fn update_known_hashes(&mut self) {
+		let enabled = self.settings.skip_duplicate_downloads;
+		self.known_hashes.set_enabled(enabled);
+		if enabled {
+			self.library.update_hashes();
+		}
+
+		self.known_hashes.set_md5(self.library.md5_hashes());
+		self.known_hashes.set_perceptual(self.library.perceptual_hashes());
+	}
+
+	pub fn refresh_images(&mut self) -> ImagesChanged {
+		let changes = self.library.refresh_images(&self.settings.download_folder);
+		self.update_known_hashes();
+		changes
+	}
+
+	/// Reads the given files again, after they were changed outside of the application.
+	pub fn update_images(&mut self, paths: Vec<PathBuf>) -> ImagesChanged {
+		let changes = self.library.update_images(paths);
+		if !changes.is_empty() {
+			self.update_known_hashes();
+		}
+		changes
+	}
+
+	pub fn refresh_tag_tables(&mut self) {
+		self.library.set_tag_normalizer(TagNormalizer::load());
+		self.refresh_images();
+	}
+
+	pub fn refresh_settings(&mut self) {
+		if let Some(settings) = Settings::load() {
+			self.settings = settings;
+		}
+	}
+}
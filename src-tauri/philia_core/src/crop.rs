@@ -0,0 +1,314 @@
+use image::{DynamicImage, GenericImageView, ImageBuffer, Rgba};
+use serde::{Deserialize, Serialize};
+use philia::prelude::{Post, Tags};
+use image::imageops::FilterType;
+
+const DEFAULT_TOP_TAGS: &[&str] =
+	&["portrait", "upper_body", "face", "close-up", "headshot", "bust", "cowboy_shot"];
+const DEFAULT_BOTTOM_TAGS: &[&str] = &["lower_body", "feet", "foot_focus", "shoes", "legs"];
+
+/// Long side of the grayscale copy used to measure entropy.
+const ENTROPY_SAMPLE_SIZE: u32 = 256;
+/// Number of crop positions compared by `CropAnchor::Entropy`, besides the first.
+const ENTROPY_STEPS: u32 = 16;
+/// Long side of the copy blurred by `LetterboxFill::BlurredEdges`.
+const BLUR_SAMPLE_SIZE: u32 = 128;
+const BLUR_SIGMA: f32 = 6.0;
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CropMode {
+	/// Stretches the image. Bucketed images are cropped at their center instead.
+	#[default]
+	Stretch,
+	Center,
+	/// Keeps the part of the image with the most detail.
+	Entropy,
+	/// Keeps the top or the bottom of tall images when they have one of the focus tags,
+	/// the part with the most detail otherwise.
+	Tags,
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct CropSettings {
+	#[serde(default = "Default::default")]
+	pub mode: CropMode,
+	/// Tags placing the crop at the top of tall images, for `CropMode::Tags`.
+	/// Empty uses tags such as `portrait`, `upper_body` and `face`.
+	#[serde(default = "Default::default")]
+	pub top_tags: Vec<String>,
+	/// Tags placing the crop at the bottom of tall images, for `CropMode::Tags`.
+	/// Empty uses tags such as `lower_body` and `feet`.
+	#[serde(default = "Default::default")]
+	pub bottom_tags: Vec<String>,
+}
+
+impl CropSettings {
+	/// Returns where images of `post` are cropped, or `None` if they are stretched.
+	pub fn anchor(&self, post: &Post) -> Option<CropAnchor> {
+		match self.mode {
+			CropMode::Stretch => None,
+			CropMode::Center => Some(CropAnchor::Center),
+			CropMode::Entropy => Some(CropAnchor::Entropy),
+			CropMode::Tags => {
+				let tags = post_tags(post);
+				let matches = |settings: &[String], defaults: &[&str]| match settings.is_empty() {
+					true => defaults.iter().any(|tag| tags.contains(&tag.to_string())),
+					false => settings.iter().any(|tag| tags.contains(&normalize_tag(tag))),
+				};
+
+				let top = matches(&self.top_tags, DEFAULT_TOP_TAGS);
+				let bottom = matches(&self.bottom_tags, DEFAULT_BOTTOM_TAGS);
+				match (top, bottom) {
+					(true, false) => Some(CropAnchor::Top),
+					(false, true) => Some(CropAnchor::Bottom),
+					_ => Some(CropAnchor::Entropy),
+				}
+			},
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CropAnchor {
+	Center,
+	Entropy,
+	/// The top of tall images. Wide images are cropped as with `Entropy`.
+	Top,
+	/// The bottom of tall images. Wide images are cropped as with `Entropy`.
+	Bottom,
+}
+
+/// Scales `image` to cover `width` by `height`, then crops the overflow around `anchor`.
+pub fn fill(image: &DynamicImage, width: u32, height: u32, anchor: CropAnchor) -> DynamicImage {
+	let scale_x = width as f32 / image.width() as f32;
+	let scale_y = height as f32 / image.height() as f32;
+	let scale = scale_x.max(scale_y);
+
+	let scaled_width = ((image.width() as f32 * scale).round() as u32).max(width);
+	let scaled_height = ((image.height() as f32 * scale).round() as u32).max(height);
+	let image = image.resize_exact(scaled_width, scaled_height, FilterType::Lanczos3);
+
+	let (overflow_x, overflow_y) = (scaled_width - width, scaled_height - height);
+	let position = match anchor {
+		CropAnchor::Center => 0.5,
+		CropAnchor::Top if overflow_y > 0 => 0.0,
+		CropAnchor::Bottom if overflow_y > 0 => 1.0,
+		_ => entropy_position(&image, width, height),
+	};
+
+	let x = (overflow_x as f32 * position).round() as u32;
+	let y = (overflow_y as f32 * position).round() as u32;
+	image.crop_imm(x, y, width, height)
+}
+
+/// Returns the position, from 0 to 1 along the overflowing axis, of the `width` by `height`
+/// window of `image` whose luminance has the highest entropy.
+fn entropy_position(image: &DynamicImage, width: u32, height: u32) -> f32 {
+	let scale = (ENTROPY_SAMPLE_SIZE as f32 / image.width().max(image.height()) as f32).min(1.0);
+	let sample_width = ((image.width() as f32 * scale).round() as u32).max(1);
+	let sample_height = ((image.height() as f32 * scale).round() as u32).max(1);
+	let sample = image.resize_exact(sample_width, sample_height, FilterType::Triangle).into_luma8();
+
+	let window_width = ((width as f32 * scale).round() as u32).clamp(1, sample_width);
+	let window_height = ((height as f32 * scale).round() as u32).clamp(1, sample_height);
+	let entropy = |position: f32| {
+		let x = ((sample_width - window_width) as f32 * position).round() as u32;
+		let y = ((sample_height - window_height) as f32 * position).round() as u32;
+
+		let mut histogram = [0u32; 256];
+		for (_, _, pixel) in sample.view(x, y, window_width, window_height).pixels() {
+			histogram[pixel.0[0] as usize] += 1;
+		}
+
+		let total = (window_width * window_height) as f32;
+		let counts = histogram.iter().filter(|count| **count != 0);
+		counts.map(|count| *count as f32 / total).map(|p| -p * p.log2()).sum::<f32>()
+	};
+
+	let positions = (0..=ENTROPY_STEPS).map(|step| step as f32 / ENTROPY_STEPS as f32);
+	positions.max_by(|a, b| entropy(*a).total_cmp(&entropy(*b))).unwrap_or(0.5)
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LetterboxFill {
+	#[default]
+	Solid,
+	BlurredEdges,
+	/// Transparent pixels, for formats with an alpha channel.
+	Transparent,
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct LetterboxSettings {
+	#[serde(default = "Default::default")]
+	pub fill: LetterboxFill,
+	#[serde(default = "Default::default")]
+	pub color: [u8; 3],
+}
+
+/// Pads `image` to a square, centering it, and fills the bars according to `settings`.
+pub fn apply_letterboxing(image: &DynamicImage, settings: &LetterboxSettings) -> DynamicImage {
+	let dimensions = image.width().max(image.height());
+	let x_offset = (dimensions - image.width()) / 2;
+	let y_offset = (dimensions - image.height()) / 2;
+
+	let mut output = match settings.fill {
+		LetterboxFill::Solid => {
+			let [r, g, b] = settings.color;
+			ImageBuffer::from_pixel(dimensions, dimensions, Rgba([r, g, b, 255]))
+		},
+		LetterboxFill::Transparent => {
+			ImageBuffer::from_pixel(dimensions, dimensions, Rgba([0, 0, 0, 0]))
+		},
+		LetterboxFill::BlurredEdges => {
+			// Blurring a small copy is much faster and looks the same once scaled back up.
+			let scale = (BLUR_SAMPLE_SIZE as f32 / dimensions as f32).min(1.0);
+			let size = ((dimensions as f32 * scale).round() as u32).max(1);
+			let small = image.resize_exact(
+				((image.width() as f32 * scale).round() as u32).clamp(1, size),
+				((image.height() as f32 * scale).round() as u32).clamp(1, size),
+				FilterType::Triangle,
+			);
+
+			let small = small.to_rgba8();
+			let (small_x, small_y) = ((size - small.width()) / 2, (size - small.height()) / 2);
+			let extended = ImageBuffer::from_fn(size, size, |x, y| {
+				let x = x.saturating_sub(small_x).min(small.width() - 1);
+				let y = y.saturating_sub(small_y).min(small.height() - 1);
+				*small.get_pixel(x, y)
+			});
+
+			let blurred = DynamicImage::from(image::imageops::blur(&extended, BLUR_SIGMA));
+			blurred.resize_exact(dimensions, dimensions, FilterType::Triangle).to_rgba8()
+		},
+	};
+
+	image::imageops::overlay(&mut output, &image.to_rgba8(), x_offset as i64, y_offset as i64);
+	DynamicImage::from(output)
+}
+
+fn post_tags(post: &Post) -> Vec<String> {
+	match &post.tags {
+		Tags::All(tags) => tags.iter().map(|tag| normalize_tag(tag)).collect(),
+		Tags::Categorized(categories) => {
+			categories.values().flatten().map(|tag| normalize_tag(tag)).collect()
+		},
+	}
+}
+
+fn normalize_tag(tag: &str) -> String {
+	tag.trim().to_lowercase().replace(' ', "_")
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::ops::Range;
+
+	const WHITE: Rgba<u8> = Rgba([255, 255, 255, 255]);
+	const BLACK: Rgba<u8> = Rgba([0, 0, 0, 255]);
+
+	/// A black image with a white and black checkerboard over the rows in `textured`.
+	fn checkerboard(width: u32, height: u32, textured: Range<u32>) -> DynamicImage {
+		DynamicImage::from(ImageBuffer::from_fn(width, height, |x, y| {
+			match textured.contains(&y) && (x / 10 + y / 10) % 2 == 0 {
+				true => WHITE,
+				false => BLACK,
+			}
+		}))
+	}
+
+	/// A white top half over a black bottom half.
+	fn halves(width: u32, height: u32) -> DynamicImage {
+		let top = height / 2;
+		DynamicImage::from(ImageBuffer::from_fn(width, height, |_, y| match y < top {
+			true => WHITE,
+			false => BLACK,
+		}))
+	}
+
+	fn brightness(image: &DynamicImage, x: u32, y: u32) -> u8 {
+		image.get_pixel(x, y).0[0]
+	}
+
+	#[test]
+	fn fill_has_the_requested_size() {
+		let sizes = [(300, 200), (200, 300), (64, 64), (1000, 10), (10, 1000)];
+		for (width, height) in sizes {
+			let image = halves(width, height);
+			for anchor in [CropAnchor::Center, CropAnchor::Entropy, CropAnchor::Top] {
+				for (target_width, target_height) in [(64, 128), (128, 64), (100, 100)] {
+					let output = fill(&image, target_width, target_height, anchor);
+					assert_eq!(output.dimensions(), (target_width, target_height));
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn fill_anchors() {
+		let image = halves(100, 200);
+
+		let top = fill(&image, 100, 100, CropAnchor::Top);
+		assert!(brightness(&top, 50, 5) > 200);
+		assert!(brightness(&top, 50, 95) > 200);
+
+		let bottom = fill(&image, 100, 100, CropAnchor::Bottom);
+		assert!(brightness(&bottom, 50, 5) < 50);
+		assert!(brightness(&bottom, 50, 95) < 50);
+
+		let center = fill(&image, 100, 100, CropAnchor::Center);
+		assert!(brightness(&center, 50, 5) > 200);
+		assert!(brightness(&center, 50, 95) < 50);
+	}
+
+	#[test]
+	fn fill_scales_before_cropping() {
+		let image = halves(200, 400);
+		let output = fill(&image, 50, 50, CropAnchor::Top);
+		assert!(brightness(&output, 25, 45) > 200);
+
+		let output = fill(&image, 50, 50, CropAnchor::Bottom);
+		assert!(brightness(&output, 25, 5) < 50);
+	}
+
+	#[test]
+	fn entropy_keeps_the_detailed_part() {
+		let top = checkerboard(100, 300, 0..100);
+		assert!(entropy_position(&top, 100, 100) < 0.25);
+
+		let bottom = checkerboard(100, 300, 200..300);
+		assert!(entropy_position(&bottom, 100, 100) > 0.75);
+	}
+
+	#[test]
+	fn letterboxing() {
+		let image = DynamicImage::from(ImageBuffer::from_pixel(100, 50, WHITE));
+
+		let solid = LetterboxSettings {
+			fill: LetterboxFill::Solid,
+			color: [10, 20, 30],
+		};
+		let output = apply_letterboxing(&image, &solid);
+		assert_eq!(output.dimensions(), (100, 100));
+		assert_eq!(output.get_pixel(0, 0), Rgba([10, 20, 30, 255]));
+		assert_eq!(output.get_pixel(50, 50), WHITE);
+
+		let transparent = LetterboxSettings {
+			fill: LetterboxFill::Transparent,
+			..solid
+		};
+		let output = apply_letterboxing(&image, &transparent);
+		assert_eq!(output.get_pixel(0, 0).0[3], 0);
+		assert_eq!(output.get_pixel(99, 99).0[3], 0);
+
+		let blurred = LetterboxSettings {
+			fill: LetterboxFill::BlurredEdges,
+			..solid
+		};
+		let output = apply_letterboxing(&image, &blurred);
+		assert_eq!(output.dimensions(), (100, 100));
+		assert_eq!(output.get_pixel(50, 5).0[3], 255);
+		assert!(output.get_pixel(50, 5).0[0] > 200);
+	}
+}
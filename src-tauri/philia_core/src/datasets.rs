@@ -0,0 +1,481 @@
+use rayon::iter::{ParallelIterator, IntoParallelRefIterator};
+use serde::{Deserialize, Serialize};
+use philia::prelude::{Post, Tags};
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageFormat};
+use std::path::{Path, PathBuf};
+use std::collections::{HashMap, HashSet};
+use crate::buckets::{self, Bucket, BucketReport, BucketSettings};
+use crate::captions::{CaptionSettings, CaptionWriter};
+use crate::media::{self, MediaKind};
+use crate::crop::{self, CropAnchor, CropSettings, LetterboxSettings};
+use crate::kohya::{self, KohyaSettings};
+use crate::hub;
+use crate::stats::{DatasetStats, DEFAULT_TOP_TAGS};
+use crate::error::PhiliaError;
+use crate::images::Image;
+use itertools::Itertools;
+use fxhash::FxHashMap;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Dataset {
+	name: String,
+	#[serde(default = "Default::default")]
+	images: HashSet<String>,
+	#[serde(default = "Default::default")]
+	thumbnail: Option<PathBuf>,
+	#[serde(default = "Default::default")]
+	settings: Settings,
+	/// Caption edits, by image path.
+	#[serde(default = "Default::default")]
+	overrides: HashMap<String, CaptionOverride>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct CaptionOverride {
+	#[serde(default = "Default::default")]
+	pub added_tags: Vec<String>,
+	#[serde(default = "Default::default")]
+	pub removed_tags: HashSet<String>,
+	#[serde(default = "Default::default")]
+	pub replaced_tags: HashMap<String, String>,
+	/// Written verbatim instead of the generated caption when set.
+	#[serde(default = "Default::default")]
+	pub caption: Option<String>,
+}
+
+impl CaptionOverride {
+	pub fn is_empty(&self) -> bool {
+		self.added_tags.is_empty()
+			&& self.removed_tags.is_empty()
+			&& self.replaced_tags.is_empty()
+			&& self.caption.is_none()
+	}
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum TagEdit {
+	Add(String),
+	Remove(String),
+	/// Replaces the first tag with the second one, on the images that have it.
+	Replace(String, String),
+}
+
+impl Dataset {
+	pub fn new(name: String) -> Self {
+		Self {
+			name,
+			images: Default::default(),
+			settings: Default::default(),
+			thumbnail: Default::default(),
+			overrides: Default::default(),
+		}
+	}
+
+	pub fn name(&self) -> &str {
+		&self.name
+	}
+
+	pub fn images(&self) -> &HashSet<String> {
+		&self.images
+	}
+
+	pub fn settings(&self) -> &Settings {
+		&self.settings
+	}
+
+	pub fn prune_overrides(&mut self) {
+		let images = &self.images;
+		self.overrides.retain(|image, _| images.contains(image));
+	}
+
+	/// Applies `edit` to the overrides of the given images, or of every image if none are given.
+	pub fn edit_tags(&mut self, images: &[String], edit: &TagEdit) {
+		let images = match images.is_empty() {
+			true => self.images.iter().cloned().collect_vec(),
+			false => images.iter().filter(|i| self.images.contains(*i)).cloned().collect_vec(),
+		};
+
+		for image in images {
+			let edits = self.overrides.entry(image.clone()).or_default();
+			match edit {
+				TagEdit::Add(tag) => {
+					edits.removed_tags.remove(tag);
+					if !edits.added_tags.contains(tag) {
+						edits.added_tags.push(tag.clone());
+					}
+				},
+				TagEdit::Remove(tag) => {
+					edits.added_tags.retain(|added| added != tag);
+					edits.removed_tags.insert(tag.clone());
+				},
+				TagEdit::Replace(from, to) => {
+					edits.removed_tags.remove(to);
+					edits.replaced_tags.insert(from.clone(), to.clone());
+					for added in edits.added_tags.iter_mut().filter(|added| *added == from) {
+						*added = to.clone();
+					}
+				},
+			}
+
+			if edits.is_empty() {
+				self.overrides.remove(&image);
+			}
+		}
+	}
+
+	/// Applies the overrides of `image` to `caption_tags`. A custom caption is split on commas.
+	pub fn caption_tags<'l>(&self, image: &str, post: &'l Post) -> Vec<CaptionTag<'l>> {
+		let settings = &self.settings.tags;
+		let tags = caption_tags(post, settings);
+		let Some(edits) = self.overrides.get(image) else {
+			return tags;
+		};
+
+		if let Some(caption) = &edits.caption {
+			return caption
+				.split(',')
+				.map(str::trim)
+				.filter(|tag| !tag.is_empty())
+				.map(|tag| CaptionTag { category: None, tag: tag.to_string() })
+				.collect();
+		}
+
+		let format = |tag: &String| format_tag(tag, settings);
+		let removed: HashSet<_> = edits.removed_tags.iter().map(format).collect();
+		let replaced: HashMap<_, _> =
+			edits.replaced_tags.iter().map(|(from, to)| (format(from), format(to))).collect();
+
+		let added = edits.added_tags.iter().map(|tag| CaptionTag {
+			category: None,
+			tag: format(tag),
+		});
+
+		let mut seen = HashSet::new();
+		tags.into_iter()
+			.map(|mut tag| {
+				if let Some(replacement) = replaced.get(&tag.tag) {
+					tag.tag = replacement.clone();
+				}
+				tag
+			})
+			.filter(|tag| !removed.contains(&tag.tag))
+			.chain(added)
+			.filter(|tag| seen.insert(tag.tag.clone()))
+			.collect()
+	}
+
+	pub fn custom_caption(&self, image: &str) -> Option<&str> {
+		self.overrides.get(image)?.caption.as_deref()
+	}
+}
+
+pub struct DatasetExporter<'l> {
+	images: &'l FxHashMap<PathBuf, Image>,
+}
+
+impl<'l> DatasetExporter<'l> {
+	pub fn new(images: &'l FxHashMap<PathBuf, Image>) -> Self {
+		Self { images }
+	}
+
+	/// Exports the images and captions into a `{repetitions}_{keyword}` folder inside `path`,
+	/// and returns it. Tag statistics and `ExportSettings::format` files are written to `path`.
+	pub fn export(&self, dataset: &Dataset, path: &Path) -> Result<PathBuf, PhiliaError> {
+		if !path.exists() {
+			return Err(PhiliaError::NotFound(format!("Path {:?} does not exist", path)));
+		}
+
+		if dataset.settings.export.tag_stats {
+			DatasetStats::compute(dataset, self.images, DEFAULT_TOP_TAGS).write(path)?;
+		}
+
+		let folder = path.join(format! {
+			"{}_{}",
+			dataset.settings.training.repetitions,
+			dataset.settings.training.keyword,
+		});
+
+		std::fs::create_dir_all(&folder)?;
+		let buckets = dataset.settings.image.bucketing.buckets();
+		let files = self.export_images(dataset, &folder, &buckets);
+		if !buckets.is_empty() {
+			BucketReport::new(&buckets, &files).write(path)?;
+		}
+
+		match dataset.settings.export.format {
+			ExportFormat::Folder => {},
+			ExportFormat::Kohya => kohya::write(dataset, path, &folder, &files)?,
+			ExportFormat::ImageFolder => hub::write_image_folder(dataset, &folder, &files)?,
+			ExportFormat::WebDataset => hub::write_shards(dataset, path, &folder, &files)?,
+		}
+
+		Ok(folder)
+	}
+
+	/// Images that cannot be exported are logged and skipped.
+	fn export_images<'d>(
+		&'d self, dataset: &'d Dataset, folder: &Path, buckets: &[Bucket],
+	) -> Vec<ExportedFile<'d>> {
+		let captions = CaptionWriter::new(dataset, self.images);
+		let images = dataset.images.par_iter();
+		let images = images.filter_map(|key| Some((key, self.images.get(Path::new(key))?)));
+		let files: Vec<Vec<_>> = images
+			.map(|(key, image)| {
+				let mut files = vec![];
+				let Some(file_stem) = image.file_path.file_stem() else { return files };
+				let file_stem = file_stem.to_string_lossy();
+
+				let frame_interval = dataset.settings.image.frame_interval;
+				let frames = match (image.media, frame_interval) {
+					(MediaKind::Image, _) | (_, 0) => media::first_frame(&image.file_path)
+						.map(|frame| -> media::Frames { Box::new(std::iter::once(Ok(frame))) }),
+					(_, interval) => media::extract_frames(&image.file_path, interval),
+				};
+
+				let mut frames = match frames {
+					Ok(frames) => frames.peekable(),
+					Err(err) => {
+						eprintln!("{:?}", err);
+						return files;
+					},
+				};
+
+				let caption = captions.caption(key, &image.info);
+				let anchor = dataset.settings.image.crop.anchor(&image.info);
+				let Some(first) = frames.next() else { return files };
+				let multiple_frames = frames.peek().is_some();
+				for (i, frame) in std::iter::once(first).chain(frames).enumerate() {
+					let frame = match frame {
+						Ok(frame) => frame,
+						Err(err) => {
+							eprintln!("{:?}", err);
+							return files;
+						},
+					};
+
+					let name = match multiple_frames {
+						false => file_stem.to_string(),
+						true => format!("{}_{:05}", file_stem, i * frame_interval as usize),
+					};
+
+					let settings = &dataset.settings.image;
+					let exported = export_image(frame, folder, &name, settings, buckets, anchor);
+					let (file_name, bucket) = match exported {
+						Ok(exported) => exported,
+						Err(err) => {
+							eprintln!("{:?}", err);
+							return files;
+						},
+					};
+
+					let caption_destination = folder.join(format!("{}.txt", name));
+					if let Err(err) = std::fs::write(caption_destination, &caption) {
+						eprintln!("{:?}", err);
+						return files;
+					}
+
+					files.push(ExportedFile {
+						file_name,
+						caption: caption.clone(),
+						key,
+						image,
+						bucket,
+					});
+				}
+
+				files
+			})
+			.collect();
+
+		files.into_iter().flatten().collect()
+	}
+}
+
+pub(crate) struct ExportedFile<'l> {
+	pub file_name: String,
+	pub caption: String,
+	/// Path of the source image, as stored in the dataset.
+	pub key: &'l str,
+	pub image: &'l Image,
+	pub bucket: Option<Bucket>,
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct Settings {
+	#[serde(default = "Default::default")]
+	pub tags: TagSettings,
+	#[serde(default = "Default::default")]
+	pub image: ImageSettings,
+	#[serde(default = "Default::default")]
+	pub training: TrainingSettings,
+	#[serde(default = "Default::default")]
+	pub caption: CaptionSettings,
+	#[serde(default = "Default::default")]
+	pub export: ExportSettings,
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct TagSettings {
+	#[serde(default = "Default::default")]
+	pub remove_underscores: bool,
+	#[serde(default = "Default::default")]
+	pub escape_parentheses: bool,
+	#[serde(default = "Default::default")]
+	pub ignore_categories: HashSet<String>,
+	#[serde(default = "Default::default")]
+	pub ignore_tags: HashSet<String>,
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct ImageSettings {
+	#[serde(default = "Default::default")]
+	pub apply_letterboxing: bool,
+	#[serde(default = "Default::default")]
+	pub letterbox: LetterboxSettings,
+	#[serde(default = "Default::default")]
+	pub resize: (u32, u32),
+	#[serde(default = "Default::default")]
+	pub target_format: TargetImageFormat,
+	/// When non-zero, animations and videos are exported as every Nth frame instead of just the first.
+	#[serde(default = "Default::default")]
+	pub frame_interval: u32,
+	/// Replaces letterboxing and resizing when enabled.
+	#[serde(default = "Default::default")]
+	pub bucketing: BucketSettings,
+	#[serde(default = "Default::default")]
+	pub crop: CropSettings,
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct ExportSettings {
+	#[serde(default = "Default::default")]
+	pub tag_stats: bool,
+	#[serde(default = "Default::default")]
+	pub format: ExportFormat,
+	#[serde(default = "Default::default")]
+	pub kohya: KohyaSettings,
+	/// Number of images of each `ExportFormat::WebDataset` shard. Zero uses the default of 1000.
+	#[serde(default = "Default::default")]
+	pub shard_size: usize,
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ExportFormat {
+	/// A `{repetitions}_{keyword}` folder with a `.txt` caption next to each image.
+	#[default]
+	Folder,
+	/// The folder, plus a captions-only kohya-ss sd-scripts dataset config and `meta_cap.json`.
+	Kohya,
+	/// The folder, plus the `metadata.jsonl` of a Hugging Face `imagefolder` dataset.
+	ImageFolder,
+	/// The folder, plus WebDataset tar shards of its images, captions and metadata.
+	WebDataset,
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub enum TargetImageFormat {
+	#[default]
+	Png, 
+	Jpg, 
+	Bmp,
+	Gif, 
+	Qoi,
+	WebP,
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct TrainingSettings {
+	#[serde(default = "Default::default")]
+	pub keyword: String,
+	#[serde(default = "Default::default")]
+	pub repetitions: u32,
+}
+
+/// Applies the image settings and saves the image as `{name}.{extension}` inside `folder`.
+/// Images are resized to their nearest bucket, or letterboxed and resized if there are no buckets.
+/// Fixed sizes with a different aspect ratio are cropped around `anchor`, or stretched without one.
+/// Returns the name of the written file and the bucket used.
+fn export_image(
+	mut image: DynamicImage, folder: &Path, name: &str, settings: &ImageSettings,
+	buckets: &[Bucket], anchor: Option<CropAnchor>,
+) -> Result<(String, Option<Bucket>), PhiliaError> {
+	let bucket = buckets::nearest(buckets, image.width(), image.height());
+	match bucket {
+		Some(bucket) => {
+			let anchor = anchor.unwrap_or(CropAnchor::Center);
+			image = crop::fill(&image, bucket.width, bucket.height, anchor);
+		},
+		None => {
+			if settings.apply_letterboxing {
+				image = crop::apply_letterboxing(&image, &settings.letterbox);
+			}
+
+			let filter = FilterType::Lanczos3;
+			match (settings.resize, anchor) {
+				((0, 0), _) => {},
+				((width, 0), _) => image = image.resize(width, image.height(), filter),
+				((0, height), _) => image = image.resize(image.width(), height, filter),
+				((width, height), None) => image = image.resize_exact(width, height, filter),
+				((width, height), Some(anchor)) => {
+					image = crop::fill(&image, width, height, anchor)
+				},
+			}
+		},
+	}
+
+	let (target_format, extension) = match settings.target_format {
+		TargetImageFormat::Png =>  (ImageFormat::Png, "png"),
+		TargetImageFormat::Bmp =>  (ImageFormat::Bmp, "bmp"),
+		TargetImageFormat::Gif =>  (ImageFormat::Gif, "gif"),
+		TargetImageFormat::Qoi =>  (ImageFormat::Qoi, "qoi"),
+		TargetImageFormat::Jpg =>  (ImageFormat::Jpeg, "jpeg"),
+		TargetImageFormat::WebP => (ImageFormat::WebP, "webp"),
+	};
+
+	let file_name = format!("{}.{}", name, extension);
+	image.save_with_format(folder.join(&file_name), target_format)?;
+	Ok((file_name, bucket))
+}
+
+#[derive(Debug, Clone)]
+pub struct CaptionTag<'l> {
+	pub category: Option<&'l str>,
+	pub tag: String,
+}
+
+/// Returns the tags of `post` that are not ignored, with underscores and parentheses replaced
+/// according to `settings`. Ignored tags and categories only apply to categorized posts.
+pub fn caption_tags<'l>(post: &'l Post, settings: &TagSettings) -> Vec<CaptionTag<'l>> {
+	let tags: Vec<(Option<&str>, &String)> = match &post.tags {
+		Tags::All(tags) => tags.iter().map(|tag| (None, tag)).collect(),
+		Tags::Categorized(cats) => cats
+			.iter()
+			.filter(|(category, _)| {
+				!settings.ignore_categories.contains(category.to_lowercase().as_str())
+			})
+			.flat_map(|(category, tags)| tags.iter().map(move |tag| (Some(category.as_str()), tag)))
+			.filter(|(_, tag)| !settings.ignore_tags.contains(*tag))
+			.collect(),
+	};
+
+	tags.into_iter()
+		.map(|(category, tag)| CaptionTag { category, tag: format_tag(tag, settings) })
+		.collect()
+}
+
+pub fn format_tag(tag: &str, settings: &TagSettings) -> String {
+	let tag = match settings.remove_underscores {
+		false => tag.to_string(),
+		true => tag.replace('_', " "),
+	};
+
+	match settings.escape_parentheses {
+		false => tag,
+		true => tag.replace('(', "\\(").replace(')', "\\)"),
+	}
+}
+
+pub fn get_tag_string(post: &Post, settings: &TagSettings) -> String {
+	caption_tags(post, settings).into_iter().map(|tag| tag.tag).join(", ")
+}
@@ -0,0 +1,283 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use image::imageops::FilterType;
+use std::path::{Path, PathBuf};
+use image::DynamicImage;
+use std::sync::RwLock;
+use fxhash::FxHashMap;
+use crate::media;
+
+/// Maximum number of differing bits for two perceptual hashes to be considered the same image.
+pub const SIMILARITY_THRESHOLD: u32 = 6;
+
+/// The 64-bit difference hash of an image: one bit per horizontally adjacent pixel pair
+/// of a 9x8 grayscale thumbnail, set when the left pixel is brighter.
+/// Resistant to rescaling, re-encoding and small color adjustments.
+pub fn perceptual_hash(image: &DynamicImage) -> u64 {
+	let small = image.resize_exact(9, 8, FilterType::Triangle).into_luma8();
+
+	let mut hash = 0u64;
+	for y in 0..8 {
+		for x in 0..8 {
+			let left = small.get_pixel(x, y).0[0];
+			let right = small.get_pixel(x + 1, y).0[0];
+			hash = (hash << 1) | u64::from(left > right);
+		}
+	}
+
+	hash
+}
+
+pub fn perceptual_hash_of(path: &Path) -> Option<u64> {
+	media::first_frame(path).ok().map(|image| perceptual_hash(&image))
+}
+
+pub fn distance(a: u64, b: u64) -> u32 {
+	(a ^ b).count_ones()
+}
+
+/// Groups images whose hashes are within `threshold` bits of each other, transitively.
+/// Only groups with more than one image are returned.
+pub fn group_similar(hashes: &[(PathBuf, u64)], threshold: u32) -> Vec<Vec<PathBuf>> {
+	let mut tree = BkTree::default();
+	for (i, (_, hash)) in hashes.iter().enumerate() {
+		tree.insert(*hash, i);
+	}
+
+	let mut parents: Vec<_> = (0..hashes.len()).collect();
+	for (i, (_, hash)) in hashes.iter().enumerate() {
+		for j in tree.find(*hash, threshold) {
+			let (a, b) = (root(&mut parents, i), root(&mut parents, j));
+			parents[a.max(b)] = a.min(b);
+		}
+	}
+
+	let mut groups = vec![vec![]; hashes.len()];
+	for (i, (path, _)) in hashes.iter().enumerate() {
+		groups[root(&mut parents, i)].push(path.clone());
+	}
+
+	groups.retain(|group| group.len() > 1);
+	for group in &mut groups {
+		group.sort();
+	}
+
+	groups
+}
+
+/// Union-find lookup with path halving.
+fn root(parents: &mut [usize], mut i: usize) -> usize {
+	while parents[i] != i {
+		parents[i] = parents[parents[i]];
+		i = parents[i];
+	}
+	i
+}
+
+/// Hashes of the images already in the library, used to skip downloading duplicates.
+/// Exact MD5 matches are always skipped, perceptual matches only while enabled.
+#[derive(Default)]
+pub struct KnownHashes {
+	enabled: AtomicBool,
+	md5: RwLock<FxHashMap<String, PathBuf>>,
+	perceptual: RwLock<Vec<(PathBuf, u64)>>,
+}
+
+impl KnownHashes {
+	pub fn is_enabled(&self) -> bool {
+		self.enabled.load(Ordering::Relaxed)
+	}
+
+	pub fn set_enabled(&self, enabled: bool) {
+		self.enabled.store(enabled, Ordering::Relaxed);
+	}
+
+	/// Returns the image whose original file has the given MD5 hash.
+	/// Images removed outside the application are forgotten instead.
+	pub fn find_md5(&self, hash: &str) -> Option<PathBuf> {
+		let hash = hash.to_lowercase();
+		let path = self.md5.read().unwrap().get(&hash).cloned()?;
+		if path.exists() {
+			return Some(path);
+		}
+
+		let mut md5 = self.md5.write().unwrap();
+		if md5.get(&hash) == Some(&path) {
+			md5.remove(&hash);
+		}
+
+		None
+	}
+
+	pub fn insert_md5(&self, hash: &str, path: PathBuf) {
+		self.md5.write().unwrap().insert(hash.to_lowercase(), path);
+	}
+
+	pub fn set_md5(&self, hashes: Vec<(PathBuf, String)>) {
+		let hashes = hashes.into_iter().map(|(path, hash)| (hash.to_lowercase(), path)).collect();
+		*self.md5.write().unwrap() = hashes;
+	}
+
+	/// Returns an image that looks the same as the one with the given hash.
+	/// Images removed outside the application are forgotten instead.
+	pub fn find_similar(&self, hash: u64) -> Option<PathBuf> {
+		let mut removed = vec![];
+		let found = self
+			.perceptual
+			.read()
+			.unwrap()
+			.iter()
+			.filter(|(_, known)| distance(*known, hash) <= SIMILARITY_THRESHOLD)
+			.find(|(path, _)| match path.exists() {
+				true => true,
+				false => {
+					removed.push(path.clone());
+					false
+				},
+			})
+			.map(|(path, _)| path.clone());
+
+		if !removed.is_empty() {
+			self.perceptual.write().unwrap().retain(|(path, _)| !removed.contains(path));
+		}
+
+		found
+	}
+
+	pub fn insert_perceptual(&self, path: PathBuf, hash: u64) {
+		self.perceptual.write().unwrap().push((path, hash));
+	}
+
+	pub fn set_perceptual(&self, hashes: Vec<(PathBuf, u64)>) {
+		*self.perceptual.write().unwrap() = hashes;
+	}
+}
+
+/// A Burkhard-Keller tree over Hamming distances, so that near-identical hashes
+/// can be found without comparing every pair.
+#[derive(Default)]
+struct BkTree {
+	nodes: Vec<(u64, usize, Vec<(u32, usize)>)>,
+}
+
+impl BkTree {
+	fn insert(&mut self, hash: u64, value: usize) {
+		if self.nodes.is_empty() {
+			self.nodes.push((hash, value, vec![]));
+			return;
+		}
+
+		let mut node = 0;
+		loop {
+			let d = distance(self.nodes[node].0, hash);
+			match self.nodes[node].2.iter().find(|(distance, _)| *distance == d) {
+				Some(&(_, child)) => node = child,
+				None => {
+					let child = self.nodes.len();
+					self.nodes.push((hash, value, vec![]));
+					self.nodes[node].2.push((d, child));
+					return;
+				},
+			}
+		}
+	}
+
+	fn find(&self, hash: u64, threshold: u32) -> Vec<usize> {
+		let mut found = vec![];
+		let mut pending = match self.nodes.is_empty() {
+			true => vec![],
+			false => vec![0],
+		};
+
+		while let Some(node) = pending.pop() {
+			let (node_hash, value, children) = &self.nodes[node];
+			let d = distance(*node_hash, hash);
+			if d <= threshold {
+				found.push(*value);
+			}
+
+			let range = d.saturating_sub(threshold)..=d + threshold;
+			pending.extend(children.iter().filter(|(d, _)| range.contains(d)).map(|(_, c)| *c));
+		}
+
+		found
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Pseudo-random hashes, each followed by copies with a few bits flipped.
+	fn hashes() -> Vec<u64> {
+		let mut state = 0x2545_F491_4F6C_DD1Du64;
+		let mut hashes = vec![];
+		for _ in 0..200 {
+			state ^= state << 13;
+			state ^= state >> 7;
+			state ^= state << 17;
+			hashes.push(state);
+			hashes.push(state ^ 0b1);
+			hashes.push(state ^ (0b1011 << (state % 60)));
+		}
+		hashes
+	}
+
+	#[test]
+	fn bk_tree_finds_every_hash_within_threshold() {
+		let hashes = hashes();
+		let mut tree = BkTree::default();
+		for (i, hash) in hashes.iter().enumerate() {
+			tree.insert(*hash, i);
+		}
+
+		for threshold in [0, 1, 3, SIMILARITY_THRESHOLD, 32] {
+			for hash in hashes.iter().step_by(7) {
+				let mut found = tree.find(*hash, threshold);
+				found.sort();
+				let expected: Vec<_> = (0..hashes.len())
+					.filter(|i| distance(hashes[*i], *hash) <= threshold)
+					.collect();
+				assert_eq!(found, expected);
+			}
+		}
+	}
+
+	#[test]
+	fn bk_tree_keeps_identical_hashes() {
+		let mut tree = BkTree::default();
+		assert!(tree.find(0, 64).is_empty());
+
+		tree.insert(42, 0);
+		tree.insert(42, 1);
+		let mut found = tree.find(42, 0);
+		found.sort();
+		assert_eq!(found, [0, 1]);
+	}
+
+	#[test]
+	fn groups_are_transitive() {
+		let hashes = [
+			(PathBuf::from("a"), 0),
+			(PathBuf::from("b"), 0b111),
+			(PathBuf::from("c"), 0b111_111),
+			(PathBuf::from("d"), u64::MAX),
+			(PathBuf::from("e"), u64::MAX ^ 0b1),
+		];
+
+		let mut groups = group_similar(&hashes, 3);
+		groups.sort();
+		let expected: Vec<Vec<PathBuf>> =
+			vec![vec!["a".into(), "b".into(), "c".into()], vec!["d".into(), "e".into()]];
+		assert_eq!(groups, expected);
+
+		assert_eq!(group_similar(&hashes, 1), vec![vec![PathBuf::from("d"), "e".into()]]);
+		assert!(group_similar(&hashes, 0).is_empty());
+	}
+
+	#[test]
+	fn distances() {
+		assert_eq!(distance(0, 0), 0);
+		assert_eq!(distance(0, u64::MAX), 64);
+		assert_eq!(distance(0b1010, 0b0110), 2);
+	}
+}
@@ -0,0 +1,437 @@
+use png::{BitDepth, ColorType, Compression, Encoder};
+use serde::{Deserialize, Serialize};
+use crate::events::{Event, EventSink};
+use reqwest::{Response, StatusCode};
+use std::io::{BufWriter, Cursor};
+use reqwest::header::RETRY_AFTER;
+use crate::settings::StorageMode;
+use crate::error::PhiliaError;
+use crate::dedupe::{self, KnownHashes};
+use crate::media::MediaKind;
+use std::path::{Path, PathBuf};
+use crate::throttle::Throttle;
+use std::sync::{Arc, Mutex};
+use tokio::sync::SemaphorePermit;
+use philia::prelude::Post;
+use std::time::{Duration, Instant};
+use image::ImageFormat;
+use crate::metadata;
+use std::fs::File;
+
+/// Number of attempts made before giving up on a rate limited or unavailable server.
+const MAX_ATTEMPTS: u32 = 5;
+const BASE_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DownloadStatus {
+	Saved,
+	/// The post had already been downloaded.
+	Exists,
+	/// The library already has the same image, downloaded from another post.
+	Duplicate,
+	Failed,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DownloadErrorKind {
+	MissingUrl,
+	/// The request could not be sent or the response body could not be read.
+	Network,
+	HttpStatus,
+	/// The server kept answering with 429 or 5xx.
+	ServerUnavailable,
+	Decode,
+	Io,
+	/// The download task panicked or was aborted.
+	Internal,
+}
+
+#[derive(Debug, Clone)]
+pub struct DownloadError {
+	pub kind: DownloadErrorKind,
+	pub message: String,
+	pub http_status: Option<u16>,
+}
+
+impl DownloadError {
+	fn new(kind: DownloadErrorKind, message: impl ToString) -> Self {
+		Self {
+			kind,
+			message: message.to_string(),
+			http_status: None,
+		}
+	}
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DownloadReport {
+	/// `{source}_{id}`.
+	pub key: String,
+	pub status: DownloadStatus,
+	#[serde(default = "Default::default")]
+	pub error_kind: Option<DownloadErrorKind>,
+	/// Why the post was skipped or failed.
+	#[serde(default = "Default::default")]
+	pub message: Option<String>,
+	/// Status of the last response received for the post's file.
+	#[serde(default = "Default::default")]
+	pub http_status: Option<u16>,
+	#[serde(default = "Default::default")]
+	pub bytes: u64,
+	#[serde(default = "Default::default")]
+	pub duration_ms: u64,
+	/// The saved file, the existing one or the one the post duplicates.
+	#[serde(default = "Default::default")]
+	pub path: Option<PathBuf>,
+}
+
+impl DownloadReport {
+	fn new(post: &Post) -> Self {
+		Self {
+			key: format!("{}_{}", post.source, post.id),
+			status: DownloadStatus::Failed,
+			error_kind: None,
+			message: None,
+			http_status: None,
+			bytes: 0,
+			duration_ms: 0,
+			path: None,
+		}
+	}
+
+	fn fail(&mut self, error: DownloadError) {
+		self.status = DownloadStatus::Failed;
+		self.error_kind = Some(error.kind);
+		self.message = Some(error.message);
+		self.http_status = error.http_status.or(self.http_status);
+	}
+
+	fn skip(&mut self, status: DownloadStatus, path: PathBuf) {
+		self.message = Some(match status {
+			DownloadStatus::Exists => "skipped: file exists".into(),
+			_ => format!("skipped: duplicate of {}", path.display()),
+		});
+		self.status = status;
+		self.path = Some(path);
+	}
+}
+
+#[derive(Clone)]
+pub struct Downloader {
+	download_folder: PathBuf,
+	storage: StorageMode,
+	throttle: Arc<Throttle>,
+	known_hashes: Arc<KnownHashes>,
+}
+
+impl Downloader {
+	pub fn new(download_folder: PathBuf, storage: StorageMode, throttle: Arc<Throttle>) -> Self {
+		Self {
+			download_folder,
+			storage,
+			throttle,
+			known_hashes: Default::default(),
+		}
+	}
+
+	/// Skips posts that look the same as a known image, while `known_hashes` is enabled.
+	pub fn with_known_hashes(mut self, known_hashes: Arc<KnownHashes>) -> Self {
+		self.known_hashes = known_hashes;
+		self
+	}
+
+	pub fn with_target(&self, download_folder: PathBuf, storage: StorageMode) -> Self {
+		Self {
+			download_folder,
+			storage,
+			throttle: self.throttle.clone(),
+			known_hashes: self.known_hashes.clone(),
+		}
+	}
+
+	pub fn throttle(&self) -> &Throttle {
+		&self.throttle
+	}
+
+	pub fn download_folder(&self) -> &Path {
+		&self.download_folder
+	}
+
+	pub fn storage(&self) -> StorageMode {
+		self.storage
+	}
+
+	/// Downloads every post, up to the throttle's concurrency limit at a time.
+	/// Emits `Event::DownloadProgress` and `Event::DownloadItemFinished` as posts are processed.
+	pub async fn download_posts(
+		&self, posts: Vec<Post>, events: Arc<dyn EventSink>,
+	) -> Vec<DownloadReport> {
+		let count = posts.len();
+		let progress = Arc::new(Mutex::new(0f32));
+
+		let promises: Vec<_> = posts
+			.into_iter()
+			.map(|post| {
+				let events = events.clone();
+				let progress = progress.clone();
+				let downloader = self.clone();
+				let report = DownloadReport::new(&post);
+				let handle = tokio::spawn(async move {
+					let report = downloader.download_post(&post).await;
+					events.emit(Event::DownloadItemFinished(report.clone()));
+					let mut progress = progress.lock().unwrap();
+					*progress += 1.0;
+					let percentage = ((*progress / count as f32) * 100.0).trunc();
+					events.emit(Event::DownloadProgress(percentage));
+					report
+				});
+				(report, handle)
+			})
+			.collect();
+
+		let mut reports = Vec::with_capacity(promises.len());
+		for (mut report, handle) in promises {
+			match handle.await {
+				Ok(report) => reports.push(report),
+				Err(err) => {
+					report.fail(DownloadError::new(DownloadErrorKind::Internal, err));
+					reports.push(report);
+				},
+			}
+		}
+
+		reports
+	}
+
+	/// Animations and videos always keep their original format.
+	pub fn output_path(&self, post: &Post) -> PathBuf {
+		let extension = post.resource_url.as_deref().and_then(resource_extension);
+		let extension = match (self.storage, extension) {
+			(_, Some(extension)) if MediaKind::from_extension(&extension) != MediaKind::Image => {
+				extension
+			},
+			(StorageMode::ConvertToPng, _) | (_, None) => "png".into(),
+			(_, Some(extension)) => extension,
+		};
+
+		self.download_folder.join(format!("{}_{}.{}", post.source, post.id, extension))
+	}
+
+	/// The path of the post's file, if it has already been downloaded with any storage mode.
+	pub fn existing_path(&self, post: &Post) -> Option<PathBuf> {
+		let png = self.download_folder.join(format!("{}_{}.png", post.source, post.id));
+		[self.output_path(post), png].into_iter().find(|path| path.exists())
+	}
+
+	pub async fn download_post(&self, post: &Post) -> DownloadReport {
+		let start = Instant::now();
+		let mut report = DownloadReport::new(post);
+		if let Err(err) = self.try_download_post(post, &mut report).await {
+			eprintln!("Could not download {}: {}", report.key, err.message);
+			report.fail(err);
+		}
+
+		report.duration_ms = start.elapsed().as_millis() as u64;
+		report
+	}
+
+	async fn try_download_post(
+		&self, post: &Post, report: &mut DownloadReport,
+	) -> Result<(), DownloadError> {
+		if let Some(path) = self.existing_path(post) {
+			report.skip(DownloadStatus::Exists, path);
+			return Ok(());
+		}
+
+		// Most sources report the MD5 of the original file, so duplicates can be skipped
+		// before downloading anything.
+		if let Some(duplicate) = self.known_hashes.find_md5(&post.hash) {
+			report.skip(DownloadStatus::Duplicate, duplicate);
+			return Ok(());
+		}
+
+		let Some(url) = post.resource_url.as_deref() else {
+			return Err(DownloadError::new(DownloadErrorKind::MissingUrl, "Missing resource url"));
+		};
+
+		let extension = resource_extension(url);
+		let media = extension.as_deref().map_or(MediaKind::Image, MediaKind::from_extension);
+
+		let data = {
+			let (response, _permit) = self.fetch(&post.source, url).await?;
+			report.http_status = Some(response.status().as_u16());
+			match response.bytes().await {
+				Ok(data) => data.to_vec(),
+				Err(err) => return Err(DownloadError::new(DownloadErrorKind::Network, err)),
+			}
+		};
+
+		report.bytes = data.len() as u64;
+		let md5 = format!("{:x}", md5::compute(&data));
+		if let Some(duplicate) = self.known_hashes.find_md5(&md5) {
+			report.skip(DownloadStatus::Duplicate, duplicate);
+			return Ok(());
+		}
+
+		let perceptual_hash = match (media, self.known_hashes.is_enabled()) {
+			(MediaKind::Image, true) => {
+				image::load_from_memory(&data).ok().map(|image| dedupe::perceptual_hash(&image))
+			},
+			_ => None,
+		};
+
+		if let Some(duplicate) = perceptual_hash.and_then(|h| self.known_hashes.find_similar(h)) {
+			report.skip(DownloadStatus::Duplicate, duplicate);
+			return Ok(());
+		}
+
+		let filepath = self.output_path(post);
+		let post_metadata = serde_json::to_string(&post)
+			.map_err(|err| DownloadError::new(DownloadErrorKind::Internal, err))?;
+		match (self.storage, extension) {
+			_ if media != MediaKind::Image => {
+				save_with_sidecar(&filepath, &data, &post_metadata)?;
+			},
+			(StorageMode::ConvertToPng, _) | (_, None) => {
+				save_as_png(&filepath, data, post_metadata)?;
+			},
+			(StorageMode::OriginalWithSidecar, Some(_)) => {
+				save_with_sidecar(&filepath, &data, &post_metadata)?;
+			},
+			(StorageMode::OriginalEmbedded, Some(extension)) => {
+				match metadata::embed(&data, &extension, &post_metadata) {
+					Some(data) => save_original(&filepath, &data)?,
+					None => save_with_sidecar(&filepath, &data, &post_metadata)?,
+				}
+			},
+		}
+
+		self.known_hashes.insert_md5(&md5, filepath.clone());
+		if !post.hash.is_empty() {
+			self.known_hashes.insert_md5(&post.hash, filepath.clone());
+		}
+
+		if let Some(hash) = perceptual_hash {
+			self.known_hashes.insert_perceptual(filepath.clone(), hash);
+		}
+
+		report.status = DownloadStatus::Saved;
+		report.path = Some(filepath);
+		Ok(())
+	}
+
+	/// Requests `url`, backing off exponentially while the server answers with 429 or 5xx.
+	/// Returns the response with the concurrency permit it was requested with,
+	/// which must be held until the body has been read.
+	async fn fetch(
+		&self, source: &str, url: &str,
+	) -> Result<(Response, SemaphorePermit<'_>), DownloadError> {
+		let mut attempt = 0;
+		loop {
+			// The permit is taken after the source's turn and released during back-offs,
+			// so a throttled source does not hold slots that other sources could use.
+			self.throttle.wait_turn(source).await;
+			let permit = self.throttle.acquire().await;
+			let response = match self.throttle.client().get(url).send().await {
+				Ok(response) => response,
+				Err(err) => return Err(DownloadError::new(DownloadErrorKind::Network, err)),
+			};
+
+			let status = response.status();
+			if status.is_success() {
+				return Ok((response, permit));
+			}
+
+			if status != StatusCode::TOO_MANY_REQUESTS && !status.is_server_error() {
+				let mut error = DownloadError::new(
+					DownloadErrorKind::HttpStatus,
+					format!("Unexpected status {status} for {url}"),
+				);
+				error.http_status = Some(status.as_u16());
+				return Err(error);
+			}
+
+			attempt += 1;
+			if attempt >= MAX_ATTEMPTS {
+				let mut error = DownloadError::new(
+					DownloadErrorKind::ServerUnavailable,
+					format!("Giving up on {url} after {attempt} attempts: {status}"),
+				);
+				error.http_status = Some(status.as_u16());
+				return Err(error);
+			}
+
+			let delay = retry_after(&response)
+				.unwrap_or(BASE_BACKOFF * 2u32.pow(attempt - 1))
+				.min(MAX_BACKOFF);
+
+			eprintln!("{source} responded with {status}, retrying in {delay:?}...");
+			drop(permit);
+			self.throttle.back_off(source, delay);
+		}
+	}
+}
+
+/// Re-encodes the image as an RGBA PNG with the metadata stored in an iTXt chunk.
+fn save_as_png(
+	filepath: &Path, mut data: Vec<u8>, post_metadata: String,
+) -> Result<(), DownloadError> {
+	use DownloadErrorKind::{Decode, Io};
+
+	convert_to_png(&mut data).map_err(|err| DownloadError::new(Decode, err))?;
+	let image = image::load_from_memory(&data).map_err(|err| DownloadError::new(Decode, err))?;
+	let file = File::create(filepath).map_err(|err| DownloadError::new(Io, err))?;
+
+	let buf_writer = BufWriter::new(file);
+	let mut encoder = Encoder::new(buf_writer, image.width(), image.height());
+	encoder.set_depth(BitDepth::Eight);
+	encoder.set_color(ColorType::Rgba);
+	encoder.set_compression(Compression::Best);
+
+	encoder
+		.add_itxt_chunk(metadata::PNG_KEYWORD.into(), post_metadata)
+		.map_err(|err| DownloadError::new(Io, format!("Could not write tags: {err}")))?;
+
+	let mut writer = encoder.write_header().map_err(|err| DownloadError::new(Io, err))?;
+	writer.write_image_data(image.as_bytes()).map_err(|err| DownloadError::new(Io, err))
+}
+
+fn save_original(filepath: &Path, data: &[u8]) -> Result<(), DownloadError> {
+	std::fs::write(filepath, data).map_err(|err| DownloadError::new(DownloadErrorKind::Io, err))
+}
+
+fn save_with_sidecar(
+	filepath: &Path, data: &[u8], post_metadata: &str,
+) -> Result<(), DownloadError> {
+	// The sidecar goes first, so the file is never observed without its metadata.
+	std::fs::write(metadata::sidecar_path(filepath), post_metadata).map_err(|err| {
+		DownloadError::new(DownloadErrorKind::Io, format!("Could not write tags: {err}"))
+	})?;
+
+	save_original(filepath, data)
+}
+
+/// The lowercase file extension of a resource url, ignoring any query string.
+pub fn resource_extension(url: &str) -> Option<String> {
+	let path = url.split(['?', '#']).next()?;
+	let name = path.rsplit('/').next()?;
+	let (_, extension) = name.rsplit_once('.')?;
+	match !extension.is_empty() && extension.chars().all(|c| c.is_ascii_alphanumeric()) {
+		true => Some(extension.to_lowercase()),
+		false => None,
+	}
+}
+
+fn retry_after(response: &Response) -> Option<Duration> {
+	let value = response.headers().get(RETRY_AFTER)?.to_str().ok()?;
+	value.trim().parse().ok().map(Duration::from_secs)
+}
+
+pub fn convert_to_png(buffer: &mut Vec<u8>) -> Result<(), PhiliaError> {
+	let mut new_buffer = vec![];
+	let image = image::load_from_memory(buffer)?.to_rgba8();
+	image.write_to(&mut Cursor::new(&mut new_buffer), ImageFormat::Png)?;
+	*buffer = new_buffer;
+	Ok(())
+}
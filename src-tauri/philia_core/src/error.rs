@@ -0,0 +1,82 @@
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+use image::ImageError;
+
+/// Serializes as `{ "kind": "NotFound", "message": "..." }` so the UI can react per kind.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "message")]
+pub enum PhiliaError {
+	Io(String),
+	Network(String),
+	/// Data could not be decoded or encoded, e.g. an image or a JSON file.
+	Decode(String),
+	SourceScript(String),
+	NotFound(String),
+	Validation(String),
+}
+
+impl PhiliaError {
+	pub fn message(&self) -> &str {
+		match self {
+			PhiliaError::Io(message)
+			| PhiliaError::Network(message)
+			| PhiliaError::Decode(message)
+			| PhiliaError::SourceScript(message)
+			| PhiliaError::NotFound(message)
+			| PhiliaError::Validation(message) => message,
+		}
+	}
+}
+
+impl Display for PhiliaError {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		let kind = match self {
+			PhiliaError::Io(_) => "IO error",
+			PhiliaError::Network(_) => "Network error",
+			PhiliaError::Decode(_) => "Decode error",
+			PhiliaError::SourceScript(_) => "Source script error",
+			PhiliaError::NotFound(_) => "Not found",
+			PhiliaError::Validation(_) => "Invalid input",
+		};
+
+		write!(f, "{}: {}", kind, self.message())
+	}
+}
+
+impl std::error::Error for PhiliaError {}
+
+impl From<std::io::Error> for PhiliaError {
+	fn from(err: std::io::Error) -> Self {
+		PhiliaError::Io(err.to_string())
+	}
+}
+
+impl From<serde_json::Error> for PhiliaError {
+	fn from(err: serde_json::Error) -> Self {
+		match err.is_io() {
+			true => PhiliaError::Io(err.to_string()),
+			false => PhiliaError::Decode(err.to_string()),
+		}
+	}
+}
+
+impl From<ImageError> for PhiliaError {
+	fn from(err: ImageError) -> Self {
+		match err {
+			ImageError::IoError(err) => PhiliaError::Io(err.to_string()),
+			err => PhiliaError::Decode(err.to_string()),
+		}
+	}
+}
+
+impl From<reqwest::Error> for PhiliaError {
+	fn from(err: reqwest::Error) -> Self {
+		PhiliaError::Network(err.to_string())
+	}
+}
+
+impl From<rusqlite::Error> for PhiliaError {
+	fn from(err: rusqlite::Error) -> Self {
+		PhiliaError::Io(err.to_string())
+	}
+}
@@ -0,0 +1,46 @@
+use crate::library::ImagesChanged;
+use crate::subscriptions::SubscriptionCheck;
+use crate::bulk::BulkDownloadProgress;
+use crate::download::DownloadReport;
+use crate::queue::QueueItem;
+use serde::Serialize;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub enum Event {
+	/// Completion percentage of a `Downloader::download_posts` batch.
+	DownloadProgress(f32),
+	/// Number of tags fetched so far by `sources::fetch_tags`.
+	FetchSourceTagsCount(usize),
+	QueueItemChanged(QueueItem),
+	DownloadItemFinished(DownloadReport),
+	ImagesChanged(ImagesChanged),
+	SubscriptionChecked(SubscriptionCheck),
+	/// A page of a bulk download has been queued, or the bulk download has finished.
+	BulkDownloadProgress(BulkDownloadProgress),
+}
+
+impl Event {
+	pub fn name(&self) -> &'static str {
+		match self {
+			Event::DownloadProgress(_) => "download_progress",
+			Event::FetchSourceTagsCount(_) => "fetch_source_tags_count",
+			Event::QueueItemChanged(_) => "download_queue_item_changed",
+			Event::DownloadItemFinished(_) => "download_item_finished",
+			Event::ImagesChanged(_) => "images_changed",
+			Event::SubscriptionChecked(_) => "subscription_checked",
+			Event::BulkDownloadProgress(_) => "bulk_download_progress",
+		}
+	}
+}
+
+/// Implemented for any `Fn(Event)` closure.
+pub trait EventSink: Send + Sync {
+	fn emit(&self, event: Event);
+}
+
+impl<F: Fn(Event) + Send + Sync> EventSink for F {
+	fn emit(&self, event: Event) {
+		self(event)
+	}
+}
@@ -0,0 +1,110 @@
+use crate::datasets::{Dataset, ExportedFile};
+use crate::error::PhiliaError;
+use serde_json::{json, Value};
+use std::io::{BufWriter, Write};
+use itertools::Itertools;
+use fxhash::FxHashSet;
+use tar::{Builder, Header};
+use crate::captions;
+use std::path::Path;
+use std::fs::File;
+
+const METADATA: &str = "metadata.jsonl";
+const DEFAULT_SHARD_SIZE: usize = 1000;
+
+/// Writes `metadata.jsonl` inside `folder`, making it loadable as an `imagefolder` dataset.
+pub(crate) fn write_image_folder(
+	dataset: &Dataset, folder: &Path, files: &[ExportedFile],
+) -> Result<(), PhiliaError> {
+	let mut writer = BufWriter::new(File::create(folder.join(METADATA))?);
+	for file in files {
+		writeln!(writer, "{}", metadata(dataset, file))?;
+	}
+
+	writer.flush()?;
+	Ok(())
+}
+
+/// Packs the exported images into WebDataset tar shards inside `root`,
+/// named after `folder` as `{name}-000000.tar`, `{name}-000001.tar` and so on.
+/// Each sample is made of the image, its `.txt` caption and its `.json` metadata.
+pub(crate) fn write_shards(
+	dataset: &Dataset, root: &Path, folder: &Path, files: &[ExportedFile],
+) -> Result<(), PhiliaError> {
+	let shard_size = match dataset.settings().export.shard_size {
+		0 => DEFAULT_SHARD_SIZE,
+		shard_size => shard_size,
+	};
+
+	let name = folder.file_name().unwrap_or_default().to_string_lossy();
+	let mut keys = FxHashSet::default();
+	for (index, shard) in files.chunks(shard_size).enumerate() {
+		let path = root.join(format!("{}-{:06}.tar", name, index));
+		let mut builder = Builder::new(BufWriter::new(File::create(path)?));
+		for file in shard {
+			let image = Path::new(&file.file_name);
+			let key = sample_key(image, &mut keys);
+			let extension = image.extension().unwrap_or_default().to_string_lossy();
+
+			let image = folder.join(image);
+			builder.append_path_with_name(image, format!("{}.{}", key, extension))?;
+			append(&mut builder, &format!("{}.txt", key), file.caption.as_bytes())?;
+			let metadata = metadata(dataset, file).to_string();
+			append(&mut builder, &format!("{}.json", key), metadata.as_bytes())?;
+		}
+
+		builder.into_inner()?.flush()?;
+	}
+
+	Ok(())
+}
+
+/// WebDataset groups the files of a sample by the name before the first dot, so dots are
+/// replaced and a counter is appended to names that would otherwise merge two samples.
+fn sample_key(image: &Path, keys: &mut FxHashSet<String>) -> String {
+	let stem = image.file_stem().unwrap_or_default().to_string_lossy().replace('.', "_");
+	let mut key = stem.clone();
+	let mut counter = 1;
+	while !keys.insert(key.clone()) {
+		key = format!("{}_{}", stem, counter);
+		counter += 1;
+	}
+
+	key
+}
+
+fn append<W: Write>(builder: &mut Builder<W>, name: &str, data: &[u8]) -> Result<(), PhiliaError> {
+	let mut header = Header::new_gnu();
+	header.set_size(data.len() as u64);
+	header.set_mode(0o644);
+	header.set_cksum();
+	builder.append_data(&mut header, name, data)?;
+	Ok(())
+}
+
+fn metadata(dataset: &Dataset, file: &ExportedFile) -> Value {
+	let post = &file.image.info;
+	let tags = dataset.caption_tags(file.key, post).into_iter().map(|tag| tag.tag).collect_vec();
+	json!({
+		"file_name": file.file_name,
+		"text": file.caption,
+		"tags": tags,
+		"source": post.source,
+		"rating": captions::rating(post),
+		"score": post.score,
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn sample_keys_do_not_collide() {
+		let mut keys = FxHashSet::default();
+		assert_eq!(sample_key(Path::new("a.b.png"), &mut keys), "a_b");
+		assert_eq!(sample_key(Path::new("a_b.png"), &mut keys), "a_b_1");
+		assert_eq!(sample_key(Path::new("a_b.jpg"), &mut keys), "a_b_2");
+		assert_eq!(sample_key(Path::new("c.png"), &mut keys), "c");
+	}
+}
@@ -0,0 +1,203 @@
+use image::codecs::jpeg::JpegEncoder;
+use serde::{Deserialize, Serialize};
+use crate::media::{self, MediaKind};
+use std::sync::{Arc, Mutex, RwLock};
+use crate::library::normalize_path;
+use crate::error::PhiliaError;
+use image::imageops::FilterType;
+use std::path::{Path, PathBuf};
+use crate::index::ImageIndex;
+use philia::prelude::Post;
+use fxhash::FxHashMap;
+
+const THUMBNAILS_PATH: &str = "./cache/thumbs";
+const THUMBNAIL_QUALITY: u8 = 85;
+const MIN_THUMBNAIL_SIZE: u32 = 64;
+const MAX_THUMBNAIL_SIZE: u32 = 2048;
+
+pub const THUMBNAIL_PROTOCOL: &str = "thumbnail";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Image {
+	pub info: Post,
+	pub file_path: PathBuf,
+	/// Thumbnail url; append `?size=N` to pick the thumbnail size.
+	pub thumbnail: String,
+	#[serde(default = "Default::default")]
+	pub media: MediaKind,
+}
+
+impl Image {
+	pub fn new(info: Post, file_path: PathBuf) -> Self {
+		Self {
+			media: MediaKind::of(&file_path),
+			thumbnail: thumbnail_url(&file_path),
+			file_path,
+			info,
+		}
+	}
+}
+
+/// JPEG thumbnails stored in `./cache/thumbs`, named after the content hash of their image
+/// so that they survive renames and are shared by identical files.
+///
+/// The store has its own connection to the image index, so that thumbnails can be served
+/// while the library is busy with a refresh or an export.
+pub struct ThumbnailStore {
+	folder: PathBuf,
+	index: Mutex<ImageIndex>,
+	library_folder: RwLock<Option<PathBuf>>,
+	/// Thumbnails being generated, and whether their generation failed.
+	jobs: Mutex<FxHashMap<(PathBuf, u32), bool>>,
+}
+
+pub enum Thumbnail {
+	Ready(Vec<u8>),
+	Pending,
+	Failed,
+}
+
+impl Default for ThumbnailStore {
+	fn default() -> Self {
+		Self {
+			folder: PathBuf::from(THUMBNAILS_PATH),
+			index: Mutex::new(ImageIndex::open()),
+			library_folder: Default::default(),
+			jobs: Default::default(),
+		}
+	}
+}
+
+impl ThumbnailStore {
+	/// Sets the folder whose images may be served.
+	pub fn set_library_folder(&self, folder: &Path) {
+		*self.library_folder.write().unwrap() = dunce::canonicalize(folder).ok();
+	}
+
+	pub fn is_in_library(&self, path: &Path) -> bool {
+		let library_folder = self.library_folder.read().unwrap();
+		match (library_folder.as_deref(), dunce::canonicalize(path)) {
+			(Some(folder), Ok(path)) => path.starts_with(folder),
+			_ => false,
+		}
+	}
+
+	/// Returns the stored thumbnail of `source`.
+	/// Otherwise the thumbnail is generated on the rayon thread pool and `Pending` is returned
+	/// until it is ready, so that callers on the UI thread never decode images.
+	pub fn request(self: &Arc<Self>, source: &Path, size: u32) -> Thumbnail {
+		let size = size.clamp(MIN_THUMBNAIL_SIZE, MAX_THUMBNAIL_SIZE);
+		let hash = self.index.lock().unwrap().content_hash(&normalize_path(source));
+		if let Some(data) = hash.and_then(|hash| std::fs::read(self.path(&hash, size)).ok()) {
+			return Thumbnail::Ready(data);
+		}
+
+		let key = (source.to_path_buf(), size);
+		let mut jobs = self.jobs.lock().unwrap();
+		match jobs.get(&key) {
+			Some(false) => return Thumbnail::Pending,
+			Some(true) => {
+				// Forgetting the failure lets the next request try again.
+				jobs.remove(&key);
+				return Thumbnail::Failed;
+			},
+			None => jobs.insert(key.clone(), false),
+		};
+
+		let store = self.clone();
+		rayon::spawn(move || {
+			let result = store.generate(&key.0, key.1);
+			let mut jobs = store.jobs.lock().unwrap();
+			match result {
+				Ok(()) => {
+					jobs.remove(&key);
+				},
+				Err(err) => {
+					eprintln!("Could not generate thumbnail for {:?}: {}", key.0, err);
+					jobs.insert(key, true);
+				},
+			}
+		});
+
+		Thumbnail::Pending
+	}
+
+	fn generate(&self, source: &Path, size: u32) -> Result<(), PhiliaError> {
+		let key = normalize_path(source);
+		let hash = self.index.lock().unwrap().content_hash(&key);
+		let hash = match hash {
+			Some(hash) => hash,
+			None => {
+				let hash = content_hash(source)?;
+				if let Err(err) = self.index.lock().unwrap().set_content_hash(&key, &hash) {
+					eprintln!("Could not update the image index: {:?}", err);
+				}
+				hash
+			},
+		};
+
+		let image = media::first_frame(source)?;
+		let image = image.resize(size, size, FilterType::Triangle).into_rgb8();
+
+		let mut data = vec![];
+		JpegEncoder::new_with_quality(&mut data, THUMBNAIL_QUALITY).encode_image(&image)?;
+
+		std::fs::create_dir_all(&self.folder)?;
+		std::fs::write(self.path(&hash, size), &data)?;
+		Ok(())
+	}
+
+	fn path(&self, hash: &str, size: u32) -> PathBuf {
+		self.folder.join(format!("{}_{}.jpg", hash, size))
+	}
+}
+
+pub fn content_hash(path: &Path) -> Result<String, PhiliaError> {
+	let data = std::fs::read(path)?;
+	Ok(format!("{:x}", md5::compute(data)))
+}
+
+pub fn thumbnail_url(path: &Path) -> String {
+	// Webviews on Windows only accept custom protocols disguised as https hosts.
+	let prefix = match cfg!(windows) {
+		true => format!("https://{}.localhost/", THUMBNAIL_PROTOCOL),
+		false => format!("{}://localhost/", THUMBNAIL_PROTOCOL),
+	};
+
+	let mut url = prefix;
+	for byte in path.to_string_lossy().bytes() {
+		match byte {
+			b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+				url.push(byte as char)
+			},
+			_ => url.push_str(&format!("%{:02X}", byte)),
+		}
+	}
+
+	url
+}
+
+pub fn parse_thumbnail_url(url: &str) -> Option<(PathBuf, u32)> {
+	let (_, rest) = url.split_once("localhost/")?;
+	let (path, query) = rest.split_once('?').unwrap_or((rest, ""));
+
+	let size = query
+		.split('&')
+		.find_map(|pair| pair.strip_prefix("size="))
+		.and_then(|size| size.parse().ok())
+		.unwrap_or(256);
+
+	let mut bytes = vec![];
+	let mut encoded = path.bytes();
+	while let Some(byte) = encoded.next() {
+		match byte {
+			b'%' => {
+				let hex = [encoded.next()?, encoded.next()?];
+				bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+			},
+			_ => bytes.push(byte),
+		}
+	}
+
+	Some((PathBuf::from(String::from_utf8(bytes).ok()?), size))
+}
@@ -0,0 +1,245 @@
+use rusqlite::{params, Connection};
+use std::time::UNIX_EPOCH;
+use philia::prelude::Post;
+use philia::data::Tags;
+use fxhash::FxHashMap;
+use std::path::Path;
+
+const INDEX_PATH: &str = "./cache/library.db";
+/// Version of the data read from each file. Rows written by older versions are read again,
+/// whether or not the file changed. Bump it whenever `FileRecord` gains a field.
+const RECORD_VERSION: i64 = 1;
+
+const SCHEMA: &str = "
+	CREATE TABLE IF NOT EXISTS images (
+		path TEXT PRIMARY KEY,
+		modified INTEGER NOT NULL,
+		size INTEGER NOT NULL,
+		post TEXT,
+		md5 TEXT,
+		phash INTEGER,
+		version INTEGER NOT NULL DEFAULT 0,
+		hashed INTEGER NOT NULL DEFAULT 0
+	);
+
+	CREATE TABLE IF NOT EXISTS image_tags (
+		path TEXT NOT NULL REFERENCES images(path) ON DELETE CASCADE,
+		category TEXT,
+		tag TEXT NOT NULL
+	);
+
+	CREATE INDEX IF NOT EXISTS image_tags_path ON image_tags(path);
+	CREATE INDEX IF NOT EXISTS image_tags_tag ON image_tags(tag);
+";
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct FileStamp {
+	pub modified: i64,
+	pub size: i64,
+}
+
+impl FileStamp {
+	pub fn of(path: &Path) -> Option<Self> {
+		let metadata = std::fs::metadata(path).ok()?;
+		let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+		Some(Self {
+			modified: modified.as_millis() as i64,
+			size: metadata.len() as i64,
+		})
+	}
+}
+
+/// Writing a record clears the file's hashes, which `ImageIndex::set_hashes` fills in later.
+pub struct FileRecord {
+	pub path: String,
+	pub stamp: FileStamp,
+	pub post: Option<Post>,
+}
+
+pub struct FileHashes {
+	pub path: String,
+	pub content_hash: Option<String>,
+	pub perceptual_hash: Option<u64>,
+}
+
+pub struct IndexEntry {
+	pub stamp: FileStamp,
+	/// The serialized post, or `None` if the file carries no post metadata.
+	pub post: Option<String>,
+	pub perceptual_hash: Option<u64>,
+	/// Whether the row was written by the current version of the reader.
+	pub up_to_date: bool,
+}
+
+/// A persistent index of the download folder stored in `./cache/library.db`,
+/// so that only new or modified files need to be read on refresh.
+pub struct ImageIndex {
+	connection: Connection,
+}
+
+impl ImageIndex {
+	/// Opens the index, falling back to an in-memory one if the database cannot be opened.
+	pub fn open() -> Self {
+		let _ = std::fs::create_dir_all("./cache");
+		let connection = Connection::open(INDEX_PATH)
+			.and_then(|connection| {
+				connection.execute_batch(SCHEMA)?;
+				Ok(connection)
+			})
+			.or_else(|err| {
+				eprintln!("Could not open '{}': {:?}", INDEX_PATH, err);
+				let connection = Connection::open_in_memory()?;
+				connection.execute_batch(SCHEMA)?;
+				Ok::<_, rusqlite::Error>(connection)
+			})
+			.expect("Could not create an in-memory image index");
+
+		// Indexes created by older versions lack the hash columns.
+		let _ = connection.execute("ALTER TABLE images ADD COLUMN md5 TEXT", []);
+		let _ = connection.execute("ALTER TABLE images ADD COLUMN phash INTEGER", []);
+		let version = "ALTER TABLE images ADD COLUMN version INTEGER NOT NULL DEFAULT 0";
+		let _ = connection.execute(version, []);
+		let hashed = "ALTER TABLE images ADD COLUMN hashed INTEGER NOT NULL DEFAULT 0";
+		if connection.execute(hashed, []).is_ok() {
+			// Older versions hashed every file as they read it.
+			let hashed = "UPDATE images SET hashed = 1 WHERE md5 IS NOT NULL OR phash IS NOT NULL";
+			let _ = connection.execute(hashed, []);
+		}
+
+		let _ = connection.execute_batch("PRAGMA foreign_keys = ON; PRAGMA journal_mode = WAL;");
+		Self { connection }
+	}
+
+	pub fn entries(&self) -> Result<FxHashMap<String, IndexEntry>, rusqlite::Error> {
+		let query = "SELECT path, modified, size, post, phash, version FROM images";
+		let mut statement = self.connection.prepare(query)?;
+
+		let rows = statement.query_map([], |row| {
+			let entry = IndexEntry {
+				stamp: FileStamp {
+					modified: row.get(1)?,
+					size: row.get(2)?,
+				},
+				post: row.get(3)?,
+				perceptual_hash: row.get::<_, Option<i64>>(4)?.map(|hash| hash as u64),
+				up_to_date: row.get::<_, i64>(5)? >= RECORD_VERSION,
+			};
+
+			Ok((row.get(0)?, entry))
+		})?;
+
+		rows.collect()
+	}
+
+	pub fn update(&mut self, files: &[FileRecord]) -> Result<(), rusqlite::Error> {
+		let transaction = self.connection.transaction()?;
+		{
+			let mut delete_tags = transaction.prepare("DELETE FROM image_tags WHERE path = ?1")?;
+			let mut insert_image = transaction.prepare(
+				"INSERT OR REPLACE INTO images (path, modified, size, post, version) \
+				 VALUES (?1, ?2, ?3, ?4, ?5)",
+			)?;
+			let mut insert_tag = transaction.prepare(
+				"INSERT INTO image_tags (path, category, tag) VALUES (?1, ?2, ?3)",
+			)?;
+
+			for file in files {
+				let FileRecord { path, stamp, post } = file;
+				let json = post.as_ref().and_then(|post| serde_json::to_string(post).ok());
+				delete_tags.execute([path])?;
+				insert_image.execute(params![path, stamp.modified, stamp.size, json, RECORD_VERSION])?;
+
+				match post.as_ref().map(|post| &post.tags) {
+					None => {},
+					Some(Tags::All(tags)) => {
+						for tag in tags {
+							insert_tag.execute(params![path, None::<String>, tag])?;
+						}
+					},
+					Some(Tags::Categorized(categories)) => {
+						for (category, tags) in categories {
+							for tag in tags {
+								insert_tag.execute(params![path, category, tag])?;
+							}
+						}
+					},
+				}
+			}
+		}
+
+		transaction.commit()
+	}
+
+	/// The MD5 hash of the file's content, if it has been computed since the file last changed.
+	pub fn content_hash(&self, path: &str) -> Option<String> {
+		self.connection
+			.query_row("SELECT md5 FROM images WHERE path = ?1", [path], |row| row.get(0))
+			.ok()
+			.flatten()
+	}
+
+	pub fn set_content_hash(&self, path: &str, hash: &str) -> Result<(), rusqlite::Error> {
+		self.connection.execute("UPDATE images SET md5 = ?2 WHERE path = ?1", [path, hash])?;
+		Ok(())
+	}
+
+	pub fn content_hashes(&self) -> Result<Vec<(String, String)>, rusqlite::Error> {
+		let mut statement = self.connection.prepare(
+			"SELECT path, md5 FROM images WHERE md5 IS NOT NULL AND post IS NOT NULL",
+		)?;
+
+		let rows = statement.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+		rows.collect()
+	}
+
+	pub fn perceptual_hashes(&self) -> Result<Vec<(String, u64)>, rusqlite::Error> {
+		let mut statement = self.connection.prepare(
+			"SELECT path, phash FROM images WHERE phash IS NOT NULL AND post IS NOT NULL",
+		)?;
+
+		let rows = statement.query_map([], |row| {
+			let hash: i64 = row.get(1)?;
+			Ok((row.get(0)?, hash as u64))
+		})?;
+
+		rows.collect()
+	}
+
+	/// Files with post metadata whose hashes have not been computed since they last changed.
+	pub fn unhashed(&self) -> Result<Vec<String>, rusqlite::Error> {
+		let mut statement = self
+			.connection
+			.prepare("SELECT path FROM images WHERE hashed = 0 AND post IS NOT NULL")?;
+
+		let rows = statement.query_map([], |row| row.get(0))?;
+		rows.collect()
+	}
+
+	pub fn set_hashes(&mut self, files: &[FileHashes]) -> Result<(), rusqlite::Error> {
+		let transaction = self.connection.transaction()?;
+		{
+			let mut update = transaction.prepare(
+				"UPDATE images SET md5 = ?2, phash = ?3, hashed = 1 WHERE path = ?1",
+			)?;
+
+			for file in files {
+				let phash = file.perceptual_hash.map(|hash| hash as i64);
+				update.execute(params![file.path, file.content_hash, phash])?;
+			}
+		}
+
+		transaction.commit()
+	}
+
+	pub fn remove(&mut self, paths: &[String]) -> Result<(), rusqlite::Error> {
+		let transaction = self.connection.transaction()?;
+		{
+			let mut delete = transaction.prepare("DELETE FROM images WHERE path = ?1")?;
+			for path in paths {
+				delete.execute([path])?;
+			}
+		}
+
+		transaction.commit()
+	}
+}
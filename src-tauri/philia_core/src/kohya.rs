@@ -0,0 +1,107 @@
+use crate::datasets::{Dataset, ExportedFile};
+use serde::{Deserialize, Serialize};
+use crate::error::PhiliaError;
+use std::collections::BTreeMap;
+use itertools::Itertools;
+use std::path::Path;
+
+const DATASET_CONFIG: &str = "dataset_config.toml";
+const METADATA: &str = "meta_cap.json";
+const DEFAULT_RESOLUTION: u32 = 512;
+
+/// Options of the kohya-ss sd-scripts dataset config.
+/// Zero values are left out of the config, so the trainer uses its own defaults.
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct KohyaSettings {
+	/// Zero uses the bucketing area or the resize setting,
+	/// or 512 when the images are not resized to a fixed size.
+	#[serde(default = "Default::default")]
+	pub resolution: u32,
+	#[serde(default = "Default::default")]
+	pub batch_size: u32,
+	#[serde(default = "Default::default")]
+	pub enable_bucket: bool,
+	#[serde(default = "Default::default")]
+	pub min_bucket_reso: u32,
+	#[serde(default = "Default::default")]
+	pub max_bucket_reso: u32,
+	#[serde(default = "Default::default")]
+	pub bucket_reso_steps: u32,
+	#[serde(default = "Default::default")]
+	pub shuffle_caption: bool,
+}
+
+#[derive(Serialize)]
+struct MetaCaption<'l> {
+	caption: &'l str,
+	tags: String,
+}
+
+/// Writes `dataset_config.toml`, describing `image_dir` as a subset with `.txt` captions,
+/// and `meta_cap.json`, the caption and tags of every exported image, inside `root`.
+///
+/// The config is captions-only and loads as is: it does not reference `meta_cap.json`,
+/// which is only an input for sd-scripts' fine-tuning workflow. No `meta_lat.json` is written,
+/// since `prepare_buckets_latents.py` has to compute it with the model being trained.
+pub(crate) fn write(
+	dataset: &Dataset, root: &Path, image_dir: &Path, files: &[ExportedFile],
+) -> Result<(), PhiliaError> {
+	let settings = dataset.settings();
+	let kohya = &settings.export.kohya;
+	let image_dir = dunce::canonicalize(image_dir).unwrap_or_else(|_| image_dir.to_path_buf());
+
+	let bucketing = &settings.image.bucketing;
+	let resolution = match (kohya.resolution, settings.image.resize) {
+		(0, _) if bucketing.enabled => bucketing.side().to_string(),
+		(0, (width, height)) if width != 0 && height != 0 => format!("[{}, {}]", width, height),
+		(0, _) => DEFAULT_RESOLUTION.to_string(),
+		(resolution, _) => resolution.to_string(),
+	};
+
+	let mut config = String::from("[general]\n");
+	config += &format!("shuffle_caption = {}\n", kohya.shuffle_caption);
+	config += "caption_extension = \".txt\"\n";
+	config += &format!("keep_tokens = {}\n", settings.caption.keep_tokens);
+
+	config += "\n[[datasets]]\n";
+	config += &format!("resolution = {}\n", resolution);
+	config += &format!("enable_bucket = {}\n", kohya.enable_bucket);
+	let optional = [
+		("batch_size", kohya.batch_size),
+		("min_bucket_reso", kohya.min_bucket_reso),
+		("max_bucket_reso", kohya.max_bucket_reso),
+		("bucket_reso_steps", kohya.bucket_reso_steps),
+	];
+
+	for (key, value) in optional.into_iter().filter(|(_, value)| *value != 0) {
+		config += &format!("{} = {}\n", key, value);
+	}
+
+	config += "\n  [[datasets.subsets]]\n";
+	config += &format!("  image_dir = {}\n", toml_string(&image_dir.to_string_lossy()));
+	config += &format!("  num_repeats = {}\n", settings.training.repetitions.max(1));
+	let keyword = settings.training.keyword.trim();
+	if !keyword.is_empty() {
+		config += &format!("  class_tokens = {}\n", toml_string(keyword));
+	}
+
+	std::fs::write(root.join(DATASET_CONFIG), config)?;
+
+	// Keyed by file stem, as done by sd-scripts' `merge_captions_to_metadata.py`.
+	let metadata: BTreeMap<_, _> = files
+		.iter()
+		.map(|file| {
+			let key = Path::new(&file.file_name).file_stem().unwrap_or_default();
+			let tags = dataset.caption_tags(file.key, &file.image.info);
+			let tags = tags.into_iter().map(|tag| tag.tag).join(", ");
+			(key.to_string_lossy(), MetaCaption { caption: &file.caption, tags })
+		})
+		.collect();
+
+	std::fs::write(root.join(METADATA), serde_json::to_string_pretty(&metadata)?)?;
+	Ok(())
+}
+
+fn toml_string(value: &str) -> String {
+	format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
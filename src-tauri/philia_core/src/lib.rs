@@ -0,0 +1,35 @@
+pub mod sources;
+pub mod download;
+pub mod queue;
+pub mod throttle;
+pub mod settings;
+pub mod datasets;
+pub mod library;
+pub mod index;
+pub mod query;
+pub mod images;
+pub mod metadata;
+pub mod media;
+pub mod dedupe;
+pub mod events;
+pub mod context;
+pub mod watcher;
+pub mod error;
+pub mod subscriptions;
+pub mod bulk;
+pub mod normalize;
+pub mod stats;
+pub mod captions;
+pub mod kohya;
+
+pub use library::Library;
+pub use error::PhiliaError;
+pub use context::Context;
+pub use download::Downloader;
+pub use queue::DownloadQueue;
+pub use sources::SourceRegistry;
+pub use events::{Event, EventSink};
+pub use datasets::{Dataset, DatasetExporter};
+pub mod hub;
+pub mod buckets;
+pub mod crop;
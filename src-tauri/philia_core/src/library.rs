@@ -0,0 +1,433 @@
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use crate::index::{FileHashes, FileRecord, FileStamp, ImageIndex};
+use crate::images::{self, Image};
+use crate::normalize::TagNormalizer;
+use fxhash::{FxHashMap, FxHashSet};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use crate::datasets::{Dataset, TagEdit};
+use crate::error::PhiliaError;
+use std::time::SystemTime;
+use itertools::Itertools;
+use serde::Serialize;
+use philia::data::Tags;
+use crate::{dedupe, metadata};
+
+/// Images added to or removed from the library. Modified images are reported as added.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct ImagesChanged {
+	pub added: Vec<PathBuf>,
+	pub removed: Vec<PathBuf>,
+}
+
+impl ImagesChanged {
+	pub fn is_empty(&self) -> bool {
+		self.added.is_empty() && self.removed.is_empty()
+	}
+}
+
+pub struct Library {
+	pub datasets: Vec<Dataset>,
+	pub images: FxHashMap<PathBuf, Image>,
+	index: ImageIndex,
+	/// Applied to the metadata of every image, so the index keeps the tags as downloaded.
+	normalizer: TagNormalizer,
+}
+
+impl Default for Library {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl Library {
+	pub fn new() -> Self {
+		Self {
+			datasets: vec![],
+			images: Default::default(),
+			index: ImageIndex::open(),
+			normalizer: TagNormalizer::load(),
+		}
+	}
+
+	/// The images are normalized again by the next `refresh_images`.
+	pub fn set_tag_normalizer(&mut self, normalizer: TagNormalizer) {
+		self.normalizer = normalizer;
+		self.images.clear();
+	}
+
+	pub fn sorted_images(&self) -> Vec<Image> {
+		let mut images = self.images.values().cloned().collect_vec();
+		images.sort_by(|a, b| a.file_path.cmp(&b.file_path));
+		images
+	}
+
+	/// Only files that are new or changed since they were last indexed are read from disk.
+	pub fn refresh_images(&mut self, download_folder: &Path) -> ImagesChanged {
+		let start = SystemTime::now();
+		println!("Refreshing images...");
+		let Ok(read_dir) = std::fs::read_dir(download_folder) else {
+			return ImagesChanged::default();
+		};
+
+		let previous: FxHashSet<_> = self.images.keys().cloned().collect();
+
+		let mut indexed = match self.index.entries() {
+			Ok(entries) => entries,
+			Err(err) => {
+				eprintln!("Could not read the image index: {:?}", err);
+				Default::default()
+			},
+		};
+
+		let files = read_dir
+			.filter_map(Result::ok)
+			.map(|entry| entry.path())
+			.filter(|path| !metadata::is_sidecar(path))
+			.filter_map(|path| Some((normalize_path(&path), FileStamp::of(&path)?)))
+			.collect_vec();
+
+		let (unchanged, changed): (Vec<_>, Vec<_>) = files.into_iter().partition(|(path, stamp)| {
+			let entry = indexed.get(path);
+			entry.map_or(false, |entry| entry.stamp == *stamp && entry.up_to_date)
+		});
+
+		let mut images = FxHashMap::default();
+		// Unchanged files that were not in the library yet.
+		let mut loaded = vec![];
+		for (path, _) in unchanged {
+			let file_path = PathBuf::from(&path);
+			let image = match self.images.remove(&file_path) {
+				Some(image) => image,
+				None => {
+					let Some(entry) = indexed.remove(&path) else { continue };
+					let Some(post) = entry.post.and_then(|json| serde_json::from_str(&json).ok())
+					else {
+						continue;
+					};
+					loaded.push(file_path.clone());
+					Image::new(self.normalizer.normalize(post), file_path.clone())
+				},
+			};
+
+			indexed.remove(&path);
+			images.insert(file_path, image);
+		}
+
+		let read = changed
+			.into_par_iter()
+			.map(|(path, stamp)| read_file(path, stamp))
+			.collect::<Vec<_>>();
+
+		for record in &read {
+			indexed.remove(&record.path);
+		}
+
+		if let Err(err) = self.index.update(&read) {
+			eprintln!("Could not update the image index: {:?}", err);
+		}
+
+		let removed = indexed.into_keys().collect_vec();
+		if let Err(err) = self.index.remove(&removed) {
+			eprintln!("Could not update the image index: {:?}", err);
+		}
+
+		let mut changes = ImagesChanged::default();
+		let read_count = read.len();
+		for record in read {
+			let Some(post) = record.post else { continue };
+			let file_path = PathBuf::from(record.path);
+			let post = self.normalizer.normalize(post);
+			images.insert(file_path.clone(), Image::new(post, file_path.clone()));
+			changes.added.push(file_path);
+		}
+
+		changes.added.extend(loaded);
+		changes.removed = previous.into_iter().filter(|path| !images.contains_key(path)).collect();
+
+		self.images = images;
+		println! {
+			"Loaded {} images in {:?}. Read {} new or modified files, removed {}.",
+			self.images.len(),
+			start.elapsed().unwrap_or_default(),
+			read_count,
+			removed.len(),
+		};
+
+		changes
+	}
+
+	pub fn update_images(&mut self, paths: Vec<PathBuf>) -> ImagesChanged {
+		let mut changes = ImagesChanged::default();
+
+		// A sidecar change affects the file it describes.
+		let mut files = FxHashSet::default();
+		for path in paths {
+			if !metadata::is_sidecar(&path) {
+				files.insert(path);
+				continue;
+			}
+
+			let sidecar = normalize_path(&path);
+			let described = self
+				.images
+				.keys()
+				.filter(|file| normalize_path(&metadata::sidecar_path(file)) == sidecar)
+				.cloned();
+
+			files.extend(described);
+		}
+
+		let mut updated = vec![];
+		let mut removed = vec![];
+		for path in files {
+			let key = normalize_path(&path);
+			let file_path = PathBuf::from(&key);
+			let Some(stamp) = FileStamp::of(&path) else {
+				removed.push(key);
+				if self.images.remove(&file_path).is_some() {
+					changes.removed.push(file_path);
+				}
+				continue;
+			};
+
+			let record = read_file(key, stamp);
+			match record.post.clone() {
+				Some(post) => {
+					let image = Image::new(self.normalizer.normalize(post), file_path.clone());
+					self.images.insert(file_path.clone(), image);
+					changes.added.push(file_path);
+				},
+				None => {
+					if self.images.remove(&file_path).is_some() {
+						changes.removed.push(file_path);
+					}
+				},
+			}
+
+			updated.push(record);
+		}
+
+		if let Err(err) = self.index.update(&updated) {
+			eprintln!("Could not update the image index: {:?}", err);
+		}
+
+		if let Err(err) = self.index.remove(&removed) {
+			eprintln!("Could not update the image index: {:?}", err);
+		}
+
+		changes
+	}
+
+	pub fn find_duplicates(&mut self, threshold: u32) -> Vec<Vec<PathBuf>> {
+		self.update_hashes();
+		dedupe::group_similar(&self.perceptual_hashes(), threshold)
+	}
+
+	/// Computes the hashes of the images that were added or modified since they were last hashed.
+	/// Perceptual hashing decodes every image and video, so it is left out of `refresh_images`.
+	pub fn update_hashes(&mut self) {
+		let paths = match self.index.unhashed() {
+			Ok(paths) => paths,
+			Err(err) => {
+				eprintln!("Could not read the image index: {:?}", err);
+				return;
+			},
+		};
+
+		if paths.is_empty() {
+			return;
+		}
+
+		let start = SystemTime::now();
+		let hashes = paths
+			.into_par_iter()
+			.map(|path| FileHashes {
+				content_hash: images::content_hash(Path::new(&path)).ok(),
+				perceptual_hash: dedupe::perceptual_hash_of(Path::new(&path)),
+				path,
+			})
+			.collect::<Vec<_>>();
+
+		if let Err(err) = self.index.set_hashes(&hashes) {
+			eprintln!("Could not update the image index: {:?}", err);
+		}
+
+		println!("Hashed {} images in {:?}.", hashes.len(), start.elapsed().unwrap_or_default());
+	}
+
+	pub fn perceptual_hashes(&self) -> Vec<(PathBuf, u64)> {
+		let hashes = match self.index.perceptual_hashes() {
+			Ok(hashes) => hashes,
+			Err(err) => {
+				eprintln!("Could not read the image index: {:?}", err);
+				return vec![];
+			},
+		};
+
+		hashes
+			.into_iter()
+			.map(|(path, hash)| (PathBuf::from(path), hash))
+			.filter(|(path, _)| self.images.contains_key(path))
+			.collect()
+	}
+
+	/// The MD5 hashes known for the images in the library: the ones reported by their source,
+	/// which match the original files, and the ones of the files as stored.
+	pub fn md5_hashes(&self) -> Vec<(PathBuf, String)> {
+		let mut hashes: Vec<_> = self
+			.images
+			.values()
+			.filter(|image| !image.info.hash.is_empty())
+			.map(|image| (image.file_path.clone(), image.info.hash.clone()))
+			.collect();
+
+		match self.index.content_hashes() {
+			Ok(content) => hashes.extend(
+				content
+					.into_iter()
+					.map(|(path, hash)| (PathBuf::from(path), hash))
+					.filter(|(path, _)| self.images.contains_key(path)),
+			),
+			Err(err) => eprintln!("Could not read the image index: {:?}", err),
+		}
+
+		hashes
+	}
+
+	pub fn refresh_datasets(&mut self) {
+		let Ok(json) = std::fs::read("./datasets.json") else {
+			eprintln!("Could not read 'datasets.json'.");
+			return;
+		};
+
+		let Ok(datasets) = serde_json::from_slice(&json) else {
+			eprintln!("Could not deserialize 'datasets.json'.");
+			return;
+		};
+
+		self.datasets = datasets;
+	}
+
+	pub fn save_datasets(&self) -> Result<(), PhiliaError> {
+		let json = serde_json::to_string_pretty(&self.datasets)?;
+		std::fs::write("datasets.json", json)?;
+		Ok(())
+	}
+
+	/// Finds a dataset by name, falling back to interpreting `key` as an index.
+	pub fn find_dataset(&self, key: &str) -> Option<&Dataset> {
+		self.datasets.get(self.find_dataset_index(key)?)
+	}
+
+	pub fn find_dataset_index(&self, key: &str) -> Option<usize> {
+		self.datasets.iter().position(|d| d.name() == key).or_else(|| {
+			let index = key.parse::<usize>().ok()?;
+			(index < self.datasets.len()).then_some(index)
+		})
+	}
+
+	pub fn new_dataset(&mut self, name: String) -> Result<(), PhiliaError> {
+		self.datasets.push(Dataset::new(name));
+		self.save_datasets()
+	}
+
+	pub fn del_dataset(&mut self, index: usize) -> Result<(), PhiliaError> {
+		if index >= self.datasets.len() {
+			return Err(dataset_not_found(index));
+		}
+
+		self.datasets.remove(index);
+		self.save_datasets()
+	}
+
+	pub fn set_dataset(&mut self, index: usize, dataset: Dataset) -> Result<(), PhiliaError> {
+		let Some(existing) = self.datasets.get_mut(index) else {
+			return Err(dataset_not_found(index));
+		};
+
+		*existing = dataset;
+		existing.prune_overrides();
+		self.save_datasets()
+	}
+
+	pub fn edit_dataset_tags(
+		&mut self, index: usize, images: &[String], edit: &TagEdit,
+	) -> Result<(), PhiliaError> {
+		let Some(dataset) = self.datasets.get_mut(index) else {
+			return Err(dataset_not_found(index));
+		};
+
+		dataset.edit_tags(images, edit);
+		self.save_datasets()
+	}
+
+	/// Returns the sorted union of the tags of the given images,
+	/// skipping the categories listed in `ignored_categories`.
+	pub fn image_tags(
+		&self, image_paths: Vec<PathBuf>, ignored_categories: Option<HashSet<String>>,
+	) -> Vec<String> {
+		let tags: HashSet<String> = image_paths
+			.into_iter()
+			.filter_map(|image| {
+				let post = self.images.get(&image)?;
+
+				if let Some(ignored_categories) = &ignored_categories {
+					match &post.info.tags {
+						Tags::All(a) => Some(a.clone()),
+						Tags::Categorized(c) => {
+							let mut tags = vec![];
+							for (key, value) in c.iter() {
+								if !ignored_categories.contains(key) {
+									tags.extend_from_slice(&value);
+								}
+							}
+
+							Some(tags)
+						},
+					}
+				} else {
+					let tags = post.info.tags.iter().map(str::to_string).collect_vec();
+					Some(tags)
+				}
+			})
+			.flatten()
+			.collect();
+
+		let mut tags = Vec::from_iter(tags);
+		tags.sort();
+		tags
+	}
+
+	pub fn image_categories(&self, image_paths: Vec<String>) -> Vec<String> {
+		let categories: HashSet<String> = image_paths
+			.into_iter()
+			.filter_map(|image| {
+				let post = self.images.get(Path::new(&image))?;
+				match &post.info.tags {
+					Tags::All(_) => None,
+					Tags::Categorized(c) => Some(c.keys().cloned()),
+				}
+			})
+			.flatten()
+			.collect();
+
+		let mut categories = Vec::from_iter(categories);
+		categories.sort();
+		categories
+	}
+}
+
+fn read_file(path: String, stamp: FileStamp) -> FileRecord {
+	let post = metadata::read_post(Path::new(&path));
+	FileRecord { path, stamp, post }
+}
+
+/// Paths are always stored with forward slashes, as the frontend expects.
+pub(crate) fn normalize_path(path: &Path) -> String {
+	path.to_string_lossy().replace('\\', "/")
+}
+
+fn dataset_not_found(index: usize) -> PhiliaError {
+	PhiliaError::NotFound(format!("Dataset {} does not exist", index))
+}
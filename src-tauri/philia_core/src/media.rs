@@ -0,0 +1,120 @@
+use image::{AnimationDecoder, DynamicImage};
+use image::codecs::gif::GifDecoder;
+use serde::{Deserialize, Serialize};
+use crate::error::PhiliaError;
+use std::process::Command;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+use std::fs::File;
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MediaKind {
+	#[default]
+	Image,
+	Animation,
+	Video,
+}
+
+impl MediaKind {
+	pub fn from_extension(extension: &str) -> Self {
+		match extension.to_lowercase().as_str() {
+			"gif" => MediaKind::Animation,
+			"mp4" | "webm" | "flv" | "ogg" | "ogv" | "mov" | "mkv" | "avi" => MediaKind::Video,
+			_ => MediaKind::Image,
+		}
+	}
+
+	pub fn of(path: &Path) -> Self {
+		path.extension()
+			.and_then(|extension| extension.to_str())
+			.map_or(MediaKind::Image, Self::from_extension)
+	}
+}
+
+/// Frames decoded one at a time, so that long videos and animations are never held in memory.
+pub type Frames = Box<dyn Iterator<Item = Result<DynamicImage, PhiliaError>>>;
+
+/// Decodes a still image, or the first frame of an animation or video.
+pub fn first_frame(path: &Path) -> Result<DynamicImage, PhiliaError> {
+	match MediaKind::of(path) {
+		MediaKind::Video => {
+			let mut frames = extract_video_frames(path, None)?;
+			let error = || PhiliaError::Decode("Video has no frames".into());
+			frames.next().ok_or_else(error)?
+		},
+		_ => Ok(image::open(path)?),
+	}
+}
+
+/// Decodes every `interval`-th frame of an animation or video, starting from the first one.
+/// Still images yield a single frame.
+pub fn extract_frames(path: &Path, interval: u32) -> Result<Frames, PhiliaError> {
+	let interval = interval.max(1) as usize;
+	match MediaKind::of(path) {
+		MediaKind::Image => {
+			let image = image::open(path)?;
+			Ok(Box::new(std::iter::once(Ok::<_, PhiliaError>(image))))
+		},
+		MediaKind::Video => Ok(Box::new(extract_video_frames(path, Some(interval))?)),
+		MediaKind::Animation => {
+			let file = BufReader::new(File::open(path)?);
+			let frames = GifDecoder::new(file)?.into_frames().step_by(interval);
+			let frames = frames
+				.map(|frame| Ok::<_, PhiliaError>(DynamicImage::ImageRgba8(frame?.into_buffer())));
+			Ok(Box::new(frames))
+		},
+	}
+}
+
+/// Frames written by ffmpeg to a temporary folder, which is deleted once they are dropped.
+struct VideoFrames {
+	paths: std::vec::IntoIter<PathBuf>,
+	_dir: TempDir,
+}
+
+impl Iterator for VideoFrames {
+	type Item = Result<DynamicImage, PhiliaError>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		let path = self.paths.next()?;
+		Some(image::open(path).map_err(PhiliaError::from))
+	}
+}
+
+/// Videos are decoded through `ffmpeg`, which can be overridden with the `PHILIA_FFMPEG` environment variable.
+fn extract_video_frames(path: &Path, interval: Option<usize>) -> Result<VideoFrames, PhiliaError> {
+	let dir = tempfile::tempdir()?;
+	let ffmpeg = std::env::var("PHILIA_FFMPEG").unwrap_or_else(|_| "ffmpeg".into());
+
+	let mut command = Command::new(ffmpeg);
+	command.args(["-v", "error", "-i"]).arg(path);
+	match interval {
+		None => command.args(["-frames:v", "1"]),
+		Some(interval) => command
+			.arg("-vf")
+			.arg(format!("select=not(mod(n\\,{interval}))"))
+			.args(["-vsync", "vfr"]),
+	};
+
+	let output = command
+		.arg(dir.path().join("%06d.png"))
+		.output()
+		.map_err(|e| PhiliaError::Io(format!("Could not run ffmpeg: {e}")))?;
+
+	if !output.status.success() {
+		let message = String::from_utf8_lossy(&output.stderr).trim().to_string();
+		return Err(PhiliaError::Decode(message));
+	}
+
+	let mut paths: Vec<_> = std::fs::read_dir(dir.path())?
+		.filter_map(Result::ok)
+		.map(|entry| entry.path())
+		.collect();
+
+	paths.sort();
+	Ok(VideoFrames {
+		paths: paths.into_iter(),
+		_dir: dir,
+	})
+}
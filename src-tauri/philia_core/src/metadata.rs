@@ -0,0 +1,401 @@
+use std::io::{BufReader, Cursor, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use philia::prelude::Post;
+use std::fs::File;
+use png::Decoder;
+
+pub const PNG_KEYWORD: &str = "post_metadata";
+
+const XMP_HEADER: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+const XMP_NAMESPACE: &str = "https://github.com/MaximumOverflow/Philia/ns/1.0/";
+const XMP_ATTRIBUTE: &str = "philia:post_metadata";
+
+const WEBP_XMP_FLAG: u8 = 0x04;
+const WEBP_ALPHA_FLAG: u8 = 0x10;
+
+/// The JSON file storing the metadata of files that cannot embed it.
+pub fn sidecar_path(path: &Path) -> PathBuf {
+	path.with_extension("json")
+}
+
+pub fn is_sidecar(path: &Path) -> bool {
+	extension(path).as_deref() == Some("json")
+}
+
+/// Reads the post metadata embedded in a PNG, JPEG or WebP file, or stored in its sidecar.
+pub fn read_post(path: &Path) -> Option<Post> {
+	let json = match read_embedded(path) {
+		Some(json) => json,
+		None => std::fs::read_to_string(sidecar_path(path)).ok()?,
+	};
+
+	serde_json::from_str(&json).ok()
+}
+
+/// Embeds the metadata into the file's own container without touching the image data.
+/// Returns `None` if the format does not support it.
+pub fn embed(data: &[u8], extension: &str, post_metadata: &str) -> Option<Vec<u8>> {
+	match extension {
+		"png" => embed_png(data, post_metadata),
+		"jpg" | "jpeg" => embed_jpeg(data, post_metadata),
+		"webp" => embed_webp(data, post_metadata),
+		_ => None,
+	}
+}
+
+fn read_embedded(path: &Path) -> Option<String> {
+	match extension(path)?.as_str() {
+		"png" => read_png_text(path),
+		"jpg" | "jpeg" => read_jpeg_xmp(path),
+		"webp" => read_webp_xmp(path),
+		_ => None,
+	}
+}
+
+fn extension(path: &Path) -> Option<String> {
+	Some(path.extension()?.to_str()?.to_lowercase())
+}
+
+fn read_png_text(path: &Path) -> Option<String> {
+	let file = File::open(path).ok()?;
+	let reader = Decoder::new(file).read_info().ok()?;
+	let chunk = reader.info().utf8_text.iter().find(|chunk| chunk.keyword == PNG_KEYWORD)?;
+	chunk.get_text().ok()
+}
+
+fn embed_png(data: &[u8], post_metadata: &str) -> Option<Vec<u8>> {
+	const SIGNATURE: &[u8] = &[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+	if !data.starts_with(SIGNATURE) {
+		return None;
+	}
+
+	// The text chunk is placed right before the image data, like `png::Encoder` does.
+	let mut offset = SIGNATURE.len();
+	loop {
+		let length = u32::from_be_bytes(data.get(offset..offset + 4)?.try_into().ok()?) as usize;
+		let kind = data.get(offset + 4..offset + 8)?;
+		if kind == b"IDAT" {
+			break;
+		}
+		offset += 12 + length;
+	}
+
+	let mut chunk = Vec::with_capacity(post_metadata.len() + PNG_KEYWORD.len() + 5);
+	chunk.extend_from_slice(PNG_KEYWORD.as_bytes());
+	// Null separator, uncompressed, compression method, empty language tag and translated keyword.
+	chunk.extend_from_slice(&[0, 0, 0, 0, 0]);
+	chunk.extend_from_slice(post_metadata.as_bytes());
+
+	let mut crc = crc32fast::Hasher::new();
+	crc.update(b"iTXt");
+	crc.update(&chunk);
+
+	let mut output = Vec::with_capacity(data.len() + chunk.len() + 12);
+	output.extend_from_slice(&data[..offset]);
+	output.extend_from_slice(&(chunk.len() as u32).to_be_bytes());
+	output.extend_from_slice(b"iTXt");
+	output.extend_from_slice(&chunk);
+	output.extend_from_slice(&crc.finalize().to_be_bytes());
+	output.extend_from_slice(&data[offset..]);
+	Some(output)
+}
+
+fn read_jpeg_xmp(path: &Path) -> Option<String> {
+	let mut file = BufReader::new(File::open(path).ok()?);
+	let mut marker = [0u8; 2];
+	file.read_exact(&mut marker).ok()?;
+	if marker != [0xFF, 0xD8] {
+		return None;
+	}
+
+	loop {
+		file.read_exact(&mut marker).ok()?;
+		while marker == [0xFF, 0xFF] {
+			file.read_exact(&mut marker[1..]).ok()?;
+		}
+
+		match marker {
+			[0xFF, 0xD9 | 0xDA] => return None,
+			[0xFF, 0x01 | 0xD0..=0xD7] => continue,
+			[0xFF, _] => {},
+			_ => return None,
+		}
+
+		let mut length = [0u8; 2];
+		file.read_exact(&mut length).ok()?;
+		let length = (u16::from_be_bytes(length) as usize).checked_sub(2)?;
+
+		if marker[1] != 0xE1 {
+			file.seek(SeekFrom::Current(length as i64)).ok()?;
+			continue;
+		}
+
+		let mut payload = vec![0; length];
+		file.read_exact(&mut payload).ok()?;
+		if let Some(packet) = payload.strip_prefix(XMP_HEADER) {
+			if let Some(json) = parse_xmp(packet) {
+				return Some(json);
+			}
+		}
+	}
+}
+
+fn embed_jpeg(data: &[u8], post_metadata: &str) -> Option<Vec<u8>> {
+	if !data.starts_with(&[0xFF, 0xD8]) {
+		return None;
+	}
+
+	let packet = make_xmp(post_metadata);
+	let length = u16::try_from(2 + XMP_HEADER.len() + packet.len()).ok()?;
+
+	// Keep the JFIF and Exif headers first, as some readers expect them right after the start.
+	let mut offset = 2;
+	loop {
+		match data.get(offset..offset + 2)? {
+			[0xFF, 0xE0] => {},
+			[0xFF, 0xE1] if data.get(offset + 4..offset + 10) == Some(&b"Exif\0\0"[..]) => {},
+			_ => break,
+		}
+		let length = u16::from_be_bytes(data.get(offset + 2..offset + 4)?.try_into().ok()?);
+		offset += 2 + length as usize;
+	}
+
+	let mut output = Vec::with_capacity(data.len() + length as usize + 2);
+	output.extend_from_slice(data.get(..offset)?);
+	output.extend_from_slice(&[0xFF, 0xE1]);
+	output.extend_from_slice(&length.to_be_bytes());
+	output.extend_from_slice(XMP_HEADER);
+	output.extend_from_slice(packet.as_bytes());
+	output.extend_from_slice(&data[offset..]);
+	Some(output)
+}
+
+fn read_webp_xmp(path: &Path) -> Option<String> {
+	let mut file = BufReader::new(File::open(path).ok()?);
+	let mut header = [0u8; 12];
+	file.read_exact(&mut header).ok()?;
+	if &header[0..4] != b"RIFF" || &header[8..12] != b"WEBP" {
+		return None;
+	}
+
+	loop {
+		let mut chunk_header = [0u8; 8];
+		file.read_exact(&mut chunk_header).ok()?;
+		let length = u32::from_le_bytes(chunk_header[4..8].try_into().ok()?) as usize;
+		let padded = length + (length & 1);
+
+		if &chunk_header[0..4] != b"XMP " {
+			file.seek(SeekFrom::Current(padded as i64)).ok()?;
+			continue;
+		}
+
+		let mut packet = vec![0; length];
+		file.read_exact(&mut packet).ok()?;
+		return parse_xmp(&packet);
+	}
+}
+
+fn embed_webp(data: &[u8], post_metadata: &str) -> Option<Vec<u8>> {
+	if data.len() < 12 || &data[0..4] != b"RIFF" || &data[8..12] != b"WEBP" {
+		return None;
+	}
+
+	let mut chunks = vec![];
+	let mut offset = 12;
+	while offset + 8 <= data.len() {
+		let kind: [u8; 4] = data[offset..offset + 4].try_into().ok()?;
+		let length = u32::from_le_bytes(data[offset + 4..offset + 8].try_into().ok()?) as usize;
+		let payload = data.get(offset + 8..offset + 8 + length)?;
+		chunks.push((kind, payload.to_vec()));
+		offset += 8 + length + (length & 1);
+	}
+
+	// Existing XMP packets are left alone rather than overwritten.
+	if chunks.iter().any(|(kind, _)| kind == b"XMP ") {
+		return None;
+	}
+
+	match chunks.first_mut() {
+		Some((kind, payload)) if kind == b"VP8X" => {
+			*payload.first_mut()? |= WEBP_XMP_FLAG;
+		},
+		_ => {
+			// Simple WebP files need an extended header before they can carry metadata.
+			let (width, height) = image::io::Reader::new(Cursor::new(data))
+				.with_guessed_format()
+				.ok()?
+				.into_dimensions()
+				.ok()?;
+
+			let has_alpha = chunks.iter().any(|(kind, _)| kind == b"ALPH" || kind == b"VP8L");
+			let mut flags = WEBP_XMP_FLAG;
+			if has_alpha {
+				flags |= WEBP_ALPHA_FLAG;
+			}
+
+			let mut header = vec![flags, 0, 0, 0];
+			header.extend_from_slice(&(width - 1).to_le_bytes()[..3]);
+			header.extend_from_slice(&(height - 1).to_le_bytes()[..3]);
+			chunks.insert(0, (*b"VP8X", header));
+		},
+	}
+
+	chunks.push((*b"XMP ", make_xmp(post_metadata).into_bytes()));
+
+	let mut body = b"WEBP".to_vec();
+	for (kind, payload) in chunks {
+		body.extend_from_slice(&kind);
+		body.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+		body.extend_from_slice(&payload);
+		if payload.len() & 1 == 1 {
+			body.push(0);
+		}
+	}
+
+	let mut output = b"RIFF".to_vec();
+	output.extend_from_slice(&u32::try_from(body.len()).ok()?.to_le_bytes());
+	output.extend_from_slice(&body);
+	Some(output)
+}
+
+fn make_xmp(post_metadata: &str) -> String {
+	let escaped = post_metadata
+		.replace('&', "&amp;")
+		.replace('"', "&quot;")
+		.replace('<', "&lt;")
+		.replace('>', "&gt;");
+
+	format! {
+		concat!(
+			"<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">",
+			"<rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">",
+			"<rdf:Description rdf:about=\"\" xmlns:philia=\"{}\" {}=\"{}\"/>",
+			"</rdf:RDF>",
+			"</x:xmpmeta>",
+		),
+		XMP_NAMESPACE, XMP_ATTRIBUTE, escaped,
+	}
+}
+
+fn parse_xmp(packet: &[u8]) -> Option<String> {
+	let packet = std::str::from_utf8(packet).ok()?;
+	let start = packet.find(&format!("{XMP_ATTRIBUTE}=\""))? + XMP_ATTRIBUTE.len() + 2;
+	let end = start + packet[start..].find('"')?;
+	let json = packet[start..end]
+		.replace("&quot;", "\"")
+		.replace("&apos;", "'")
+		.replace("&lt;", "<")
+		.replace("&gt;", ">")
+		.replace("&amp;", "&");
+
+	Some(json)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use image::{DynamicImage, ImageFormat};
+
+	const METADATA: &str = r#"{"id":1,"tags":["a&b","<c>","\"d\""]}"#;
+
+	/// A 1x1 lossy WebP.
+	const WEBP_LOSSY: &[u8] = b"RIFF\x22\x00\x00\x00WEBPVP8 \x16\x00\x00\x00\x30\x01\x00\x9D\
+		\x01\x2A\x01\x00\x01\x00\x0E\xC0\xFE\x25\xA4\x00\x03\x70\x00\x00\x00\x00";
+	/// A 1x1 lossless WebP.
+	const WEBP_LOSSLESS: &[u8] = b"RIFF\x1A\x00\x00\x00WEBPVP8L\x0D\x00\x00\x00\x2F\x00\x00\x00\
+		\x10\x07\x10\x11\x11\x88\x88\xFE\x07\x00";
+	/// A 1x1 lossy WebP with an alpha channel, which already has an extended header.
+	const WEBP_EXTENDED: &[u8] = b"RIFF\x4A\x00\x00\x00WEBPVP8X\x0A\x00\x00\x00\x10\x00\x00\x00\
+		\x00\x00\x00\x00\x00\x00ALPH\x0C\x00\x00\x00\x11\x07\x10\x11\xFD\x0F\x44\x44\xFF\x03\
+		\x00\x00VP8 \x18\x00\x00\x00\x14\x01\x00\x9D\x01\x2A\x01\x00\x01\x00\x00\x00\xFE\x00\
+		\x00\x0D\xC0\x00\xFE\xE6\xB5\x00\x00\x00";
+
+	fn encode(format: ImageFormat) -> Vec<u8> {
+		let mut data = vec![];
+		DynamicImage::new_rgb8(4, 4).write_to(&mut Cursor::new(&mut data), format).unwrap();
+		data
+	}
+
+	/// Embeds the metadata into `data`, writes it to a file and reads the metadata back.
+	fn round_trip(data: &[u8], extension: &str) -> (Vec<u8>, Option<String>) {
+		let output = embed(data, extension, METADATA).unwrap();
+		let folder = tempfile::tempdir().unwrap();
+		let path = folder.path().join(format!("image.{extension}"));
+		std::fs::write(&path, &output).unwrap();
+
+		image::load_from_memory(&output).unwrap();
+		let json = read_embedded(&path);
+		(output, json)
+	}
+
+	/// Returns the kinds of the chunks of a WebP file.
+	fn webp_chunks(data: &[u8]) -> Vec<[u8; 4]> {
+		let mut chunks = vec![];
+		let mut offset = 12;
+		while offset + 8 <= data.len() {
+			chunks.push(data[offset..offset + 4].try_into().unwrap());
+			let length = u32::from_le_bytes(data[offset + 4..offset + 8].try_into().unwrap());
+			offset += 8 + length as usize + (length as usize & 1);
+		}
+		chunks
+	}
+
+	#[test]
+	fn png_round_trip() {
+		let (_, json) = round_trip(&encode(ImageFormat::Png), "png");
+		assert_eq!(json.as_deref(), Some(METADATA));
+	}
+
+	#[test]
+	fn jpeg_round_trip() {
+		let (_, json) = round_trip(&encode(ImageFormat::Jpeg), "jpg");
+		assert_eq!(json.as_deref(), Some(METADATA));
+	}
+
+	#[test]
+	fn jpeg_xmp_follows_jfif_and_exif() {
+		let data = encode(ImageFormat::Jpeg);
+		assert_eq!(data[2..4], [0xFF, 0xE0]);
+		let jfif_end = 4 + u16::from_be_bytes([data[4], data[5]]) as usize;
+
+		let exif = b"Exif\0\0MM\0\x2A\0\0\0\x08\0\0";
+		let mut with_exif = data[..jfif_end].to_vec();
+		with_exif.extend_from_slice(&[0xFF, 0xE1]);
+		with_exif.extend_from_slice(&(exif.len() as u16 + 2).to_be_bytes());
+		with_exif.extend_from_slice(exif);
+		with_exif.extend_from_slice(&data[jfif_end..]);
+
+		let (output, json) = round_trip(&with_exif, "jpeg");
+		assert_eq!(json.as_deref(), Some(METADATA));
+
+		let xmp_start = jfif_end + 4 + exif.len();
+		assert_eq!(output[..xmp_start], with_exif[..xmp_start]);
+		assert_eq!(output[xmp_start..xmp_start + 2], [0xFF, 0xE1]);
+		assert!(output[xmp_start + 4..].starts_with(XMP_HEADER));
+	}
+
+	#[test]
+	fn webp_simple_round_trip() {
+		let (output, json) = round_trip(WEBP_LOSSY, "webp");
+		assert_eq!(json.as_deref(), Some(METADATA));
+		assert_eq!(webp_chunks(&output), [*b"VP8X", *b"VP8 ", *b"XMP "]);
+		assert_eq!(output[20], WEBP_XMP_FLAG);
+	}
+
+	#[test]
+	fn webp_lossless_round_trip() {
+		let (output, json) = round_trip(WEBP_LOSSLESS, "webp");
+		assert_eq!(json.as_deref(), Some(METADATA));
+		assert_eq!(webp_chunks(&output), [*b"VP8X", *b"VP8L", *b"XMP "]);
+		assert_eq!(output[20], WEBP_XMP_FLAG | WEBP_ALPHA_FLAG);
+	}
+
+	#[test]
+	fn webp_extended_round_trip() {
+		let (output, json) = round_trip(WEBP_EXTENDED, "webp");
+		assert_eq!(json.as_deref(), Some(METADATA));
+		assert_eq!(webp_chunks(&output), [*b"VP8X", *b"ALPH", *b"VP8 ", *b"XMP "]);
+		assert_eq!(output[20], WEBP_XMP_FLAG | WEBP_ALPHA_FLAG);
+		assert_eq!(embed(&output, "webp", METADATA), None);
+	}
+}
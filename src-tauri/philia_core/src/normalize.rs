@@ -0,0 +1,284 @@
+use serde::{Deserialize, Serialize};
+use fxhash::{FxHashMap, FxHashSet};
+use philia::prelude::{Post, Tags};
+use crate::error::PhiliaError;
+use crate::throttle::Throttle;
+use std::path::Path;
+
+const TAG_TABLES_PATH: &str = "./tags";
+
+/// Bounds alias chains and implication depth, so that cyclic tables cannot loop forever.
+const MAX_DEPTH: usize = 16;
+/// Bounds the pages requested by `fetch_tag_tables` from a single url.
+const MAX_FETCH_PAGES: u32 = 500;
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct TagTable {
+	#[serde(default = "Default::default")]
+	pub aliases: FxHashMap<String, String>,
+	#[serde(default = "Default::default")]
+	pub implications: FxHashMap<String, Vec<String>>,
+}
+
+impl TagTable {
+	pub fn is_empty(&self) -> bool {
+		self.aliases.is_empty() && self.implications.is_empty()
+	}
+}
+
+/// The urls of the alias and implication lists of a source, declared by its script as
+/// `const TAG_ALIASES_URL = "...";` and `const TAG_IMPLICATIONS_URL = "...";`.
+/// Both must return JSON arrays of `{ "antecedent_name": ..., "consequent_name": ... }`.
+#[derive(Debug, Default, Clone)]
+pub struct TagTableUrls {
+	pub aliases: Option<String>,
+	pub implications: Option<String>,
+}
+
+/// Rewrites post tags so that images from different sources use the same names.
+///
+/// Tables are loaded from `./tags`: `aliases` and `implications` apply to every source,
+/// `{source}_aliases` and `{source}_implications` only to posts of that source and take precedence.
+/// Each table is either a CSV file of `antecedent,consequent` lines or a JSON file mapping
+/// each tag to its alias, or to the list of tags it implies.
+#[derive(Debug, Default)]
+pub struct TagNormalizer {
+	global: TagTable,
+	sources: FxHashMap<String, TagTable>,
+}
+
+impl TagNormalizer {
+	pub fn load() -> Self {
+		let mut normalizer = Self::default();
+		let Ok(entries) = std::fs::read_dir(TAG_TABLES_PATH) else {
+			return normalizer;
+		};
+
+		for path in entries.filter_map(Result::ok).map(|entry| entry.path()) {
+			let Some(stem) = path.file_stem().map(|s| s.to_string_lossy().to_lowercase()) else {
+				continue;
+			};
+
+			let (source, aliases) = match stem.strip_suffix("aliases") {
+				Some(source) => (source, true),
+				None => match stem.strip_suffix("implications") {
+					Some(source) => (source, false),
+					None => continue,
+				},
+			};
+
+			let source = source.trim_end_matches('_');
+
+			let table = match source.is_empty() {
+				true => &mut normalizer.global,
+				false => normalizer.sources.entry(source.to_string()).or_default(),
+			};
+
+			if let Err(err) = read_table(&path, aliases, table) {
+				eprintln!("Could not read tag table {:?}: {}", path, err);
+			}
+		}
+
+		normalizer
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.global.is_empty() && self.sources.values().all(TagTable::is_empty)
+	}
+
+	/// Replaces aliased tags and adds implied ones, keeping each tag once.
+	/// Implied tags are added to the category of the tag implying them.
+	pub fn normalize(&self, mut post: Post) -> Post {
+		if self.is_empty() {
+			return post;
+		}
+
+		let table = self.sources.get(&post.source.to_lowercase());
+		let mut seen = FxHashSet::default();
+		match &mut post.tags {
+			Tags::All(tags) => *tags = self.normalize_tags(table, tags, &mut seen),
+			Tags::Categorized(categories) => {
+				for tags in categories.values_mut() {
+					*tags = self.normalize_tags(table, tags, &mut seen);
+				}
+			},
+		}
+
+		post
+	}
+
+	fn normalize_tags(
+		&self, table: Option<&TagTable>, tags: &[String], seen: &mut FxHashSet<String>,
+	) -> Vec<String> {
+		let mut normalized = vec![];
+		for tag in tags {
+			let mut pending = vec![(self.alias(table, tag), 0)];
+			while let Some((tag, depth)) = pending.pop() {
+				if !seen.insert(tag.to_string()) {
+					continue;
+				}
+
+				normalized.push(tag.to_string());
+				if depth < MAX_DEPTH {
+					let implied = self.implications(table, tag).map(|tag| self.alias(table, tag));
+					pending.extend(implied.map(|tag| (tag, depth + 1)));
+				}
+			}
+		}
+
+		normalized
+	}
+
+	fn alias<'l>(&'l self, table: Option<&'l TagTable>, mut tag: &'l str) -> &'l str {
+		for _ in 0..MAX_DEPTH {
+			let alias = table
+				.and_then(|table| table.aliases.get(tag))
+				.or_else(|| self.global.aliases.get(tag));
+
+			match alias {
+				Some(alias) if alias != tag => tag = alias.as_str(),
+				_ => break,
+			}
+		}
+
+		tag
+	}
+
+	fn implications<'l>(
+		&'l self, table: Option<&'l TagTable>, tag: &str,
+	) -> impl Iterator<Item = &'l str> {
+		let source = table.and_then(|table| table.implications.get(tag));
+		let global = self.global.implications.get(tag);
+		source.into_iter().chain(global).flatten().map(String::as_str)
+	}
+}
+
+/// Downloads the tables advertised by a source script and saves them as
+/// `./tags/{source}_aliases.json` and `./tags/{source}_implications.json`.
+pub async fn fetch_tag_tables(
+	source: &str, urls: &TagTableUrls, throttle: &Throttle,
+) -> Result<TagTable, PhiliaError> {
+	if urls.aliases.is_none() && urls.implications.is_none() {
+		let message = format!("Source {:?} does not provide tag aliases or implications", source);
+		return Err(PhiliaError::Validation(message));
+	}
+
+	let mut table = TagTable::default();
+	if let Some(url) = &urls.aliases {
+		for (antecedent, consequent) in fetch_pairs(source, url, throttle).await? {
+			table.aliases.insert(antecedent, consequent);
+		}
+	}
+
+	if let Some(url) = &urls.implications {
+		for (antecedent, consequent) in fetch_pairs(source, url, throttle).await? {
+			table.implications.entry(antecedent).or_default().push(consequent);
+		}
+	}
+
+	std::fs::create_dir_all(TAG_TABLES_PATH)?;
+	let folder = Path::new(TAG_TABLES_PATH);
+	let aliases = serde_json::to_string_pretty(&table.aliases)?;
+	std::fs::write(folder.join(format!("{}_aliases.json", source)), aliases)?;
+	let implications = serde_json::to_string_pretty(&table.implications)?;
+	std::fs::write(folder.join(format!("{}_implications.json", source)), implications)?;
+
+	Ok(table)
+}
+
+#[derive(Deserialize)]
+struct TagPair {
+	antecedent_name: String,
+	consequent_name: String,
+}
+
+/// e621 returns an object such as `{ "tag_aliases": [] }` instead of an empty list.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TagPairs {
+	List(Vec<TagPair>),
+	Wrapped(FxHashMap<String, Vec<TagPair>>),
+}
+
+/// Requests `url` page by page until an empty page is returned.
+async fn fetch_pairs(
+	source: &str, url: &str, throttle: &Throttle,
+) -> Result<Vec<(String, String)>, PhiliaError> {
+	let separator = match url.contains('?') {
+		true => '&',
+		false => '?',
+	};
+
+	let mut pairs = vec![];
+	for page in 1..=MAX_FETCH_PAGES {
+		throttle.wait_turn(source).await;
+		let response = throttle
+			.client()
+			.get(format!("{}{}page={}", url, separator, page))
+			.send()
+			.await?
+			.error_for_status()?;
+
+		let page = match serde_json::from_str::<TagPairs>(&response.text().await?)? {
+			TagPairs::List(pairs) => pairs,
+			TagPairs::Wrapped(pairs) => pairs.into_values().flatten().collect(),
+		};
+
+		if page.is_empty() {
+			break;
+		}
+
+		pairs.extend(page.into_iter().map(|pair| (pair.antecedent_name, pair.consequent_name)));
+	}
+
+	Ok(pairs)
+}
+
+/// Reads an alias table if `aliases` is set, an implication table otherwise.
+fn read_table(path: &Path, aliases: bool, table: &mut TagTable) -> Result<(), PhiliaError> {
+	let data = std::fs::read_to_string(path)?;
+	let extension = path.extension().map(|e| e.to_string_lossy().to_lowercase());
+	match (extension.as_deref(), aliases) {
+		(Some("json"), true) => {
+			let aliases: FxHashMap<String, String> = serde_json::from_str(&data)?;
+			table.aliases.extend(aliases);
+		},
+		(Some("json"), false) => {
+			let implications: FxHashMap<String, Vec<String>> = serde_json::from_str(&data)?;
+			for (antecedent, consequents) in implications {
+				table.implications.entry(antecedent).or_default().extend(consequents);
+			}
+		},
+		(Some("csv"), _) => {
+			for line in data.lines().map(str::trim) {
+				if line.is_empty() || line.starts_with('#') {
+					continue;
+				}
+
+				let Some((antecedent, consequent)) = line.split_once(',') else {
+					let message = format!("Expected 'antecedent,consequent', found {:?}", line);
+					return Err(PhiliaError::Decode(message));
+				};
+
+				let (antecedent, consequent) = (unquote(antecedent), unquote(consequent));
+				if antecedent == "antecedent" || antecedent == "antecedent_name" {
+					continue;
+				}
+
+				match aliases {
+					true => {
+						table.aliases.insert(antecedent, consequent);
+					},
+					false => table.implications.entry(antecedent).or_default().push(consequent),
+				}
+			}
+		},
+		_ => {},
+	}
+
+	Ok(())
+}
+
+fn unquote(field: &str) -> String {
+	field.trim().trim_matches('"').to_string()
+}
@@ -0,0 +1,206 @@
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use crate::images::Image;
+use crate::library::Library;
+
+/// Filters, sorting and pagination applied by `Library::query`.
+/// Unset fields match every image.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ImageQuery {
+	/// Tags every image must have; a `-` prefix excludes the tag instead.
+	/// `*` matches any sequence of characters, e.g. `blue_*`.
+	#[serde(default = "Default::default")]
+	pub tags: Vec<String>,
+	#[serde(default = "Default::default")]
+	pub sources: Vec<String>,
+	/// Accepted ratings, compared case-insensitively, e.g. `["Safe", "General"]`.
+	#[serde(default = "Default::default")]
+	pub ratings: Vec<String>,
+	#[serde(default = "Default::default")]
+	pub min_score: Option<i64>,
+	#[serde(default = "Default::default")]
+	pub max_score: Option<i64>,
+	#[serde(default = "Default::default")]
+	pub min_dimensions: Option<(u32, u32)>,
+	#[serde(default = "Default::default")]
+	pub max_dimensions: Option<(u32, u32)>,
+	/// Name or index of a dataset the images must belong to.
+	#[serde(default = "Default::default")]
+	pub in_dataset: Option<String>,
+	/// Name or index of a dataset the images must not belong to.
+	#[serde(default = "Default::default")]
+	pub not_in_dataset: Option<String>,
+	#[serde(default = "Default::default")]
+	pub sort: ImageSort,
+	#[serde(default = "Default::default")]
+	pub descending: bool,
+	/// Zero-based page index.
+	#[serde(default = "Default::default")]
+	pub page: usize,
+	/// Images per page. Zero returns every matching image.
+	#[serde(default = "Default::default")]
+	pub limit: usize,
+}
+
+#[derive(Debug, Default, Copy, Clone, Serialize, Deserialize)]
+pub enum ImageSort {
+	#[default]
+	Path,
+	Id,
+	Score,
+	Resolution,
+	TagCount,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ImagePage {
+	pub images: Vec<Image>,
+	pub total: usize,
+}
+
+impl Library {
+	pub fn query(&self, query: &ImageQuery) -> ImagePage {
+		let in_dataset = query.in_dataset.as_deref().map(|key| self.find_dataset(key));
+		let not_in_dataset = query.not_in_dataset.as_deref().and_then(|key| self.find_dataset(key));
+
+		// An unknown dataset has no images.
+		if let Some(None) = in_dataset {
+			return ImagePage { images: vec![], total: 0 };
+		}
+
+		let (excluded, included): (Vec<_>, Vec<_>) =
+			query.tags.iter().partition(|tag| tag.starts_with('-'));
+		let excluded: Vec<_> = excluded.iter().map(|tag| &tag[1..]).collect();
+
+		let mut matches: Vec<_> = self
+			.images
+			.values()
+			.filter(|image| {
+				let post = &image.info;
+				let path = image.file_path.to_string_lossy();
+
+				let in_datasets = in_dataset.flatten().map_or(true, |d| d.images().contains(&*path))
+					&& !not_in_dataset.map_or(false, |d| d.images().contains(&*path));
+
+				let source = query.sources.is_empty() || query.sources.contains(&post.source);
+
+				let rating = format!("{:?}", post.rating);
+				let rating = query.ratings.is_empty()
+					|| query.ratings.iter().any(|r| r.eq_ignore_ascii_case(&rating));
+
+				let score = i64::from(post.score);
+				let score = query.min_score.map_or(true, |min| score >= min)
+					&& query.max_score.map_or(true, |max| score <= max);
+
+				let dimensions = match (query.min_dimensions, query.max_dimensions) {
+					(None, None) => true,
+					(min, max) => post.resource_dimensions.map_or(false, |(w, h)| {
+						min.map_or(true, |(min_w, min_h)| w >= min_w && h >= min_h)
+							&& max.map_or(true, |(max_w, max_h)| w <= max_w && h <= max_h)
+					}),
+				};
+
+				let tags = included
+					.iter()
+					.all(|pattern| post.tags.iter().any(|tag| glob_match(pattern, tag)))
+					&& !excluded
+						.iter()
+						.any(|pattern| post.tags.iter().any(|tag| glob_match(pattern, tag)));
+
+				in_datasets && source && rating && score && dimensions && tags
+			})
+			.collect();
+
+		matches.sort_by(|a, b| {
+			let ordering = compare(query.sort, a, b).then_with(|| a.file_path.cmp(&b.file_path));
+			match query.descending {
+				true => ordering.reverse(),
+				false => ordering,
+			}
+		});
+
+		let total = matches.len();
+		let images = match query.limit {
+			0 => matches.into_iter().cloned().collect(),
+			limit => {
+				let skip = query.page.saturating_mul(limit);
+				matches.into_iter().skip(skip).take(limit).cloned().collect()
+			},
+		};
+
+		ImagePage { images, total }
+	}
+}
+
+fn compare(sort: ImageSort, a: &Image, b: &Image) -> Ordering {
+	let resolution = |image: &Image| {
+		image.info.resource_dimensions.map_or(0, |(w, h)| w as u64 * h as u64)
+	};
+
+	match sort {
+		ImageSort::Path => a.file_path.cmp(&b.file_path),
+		ImageSort::Id => a.info.id.cmp(&b.info.id),
+		ImageSort::Score => a.info.score.cmp(&b.info.score),
+		ImageSort::Resolution => resolution(a).cmp(&resolution(b)),
+		ImageSort::TagCount => a.info.tags.iter().count().cmp(&b.info.tags.iter().count()),
+	}
+}
+
+/// Matches `text` against a pattern where `*` stands for any sequence of characters.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+	let Some((prefix, rest)) = pattern.split_once('*') else {
+		return pattern == text;
+	};
+
+	let Some(mut text) = text.strip_prefix(prefix) else {
+		return false;
+	};
+
+	let mut parts = rest.split('*').peekable();
+	while let Some(part) = parts.next() {
+		if parts.peek().is_none() {
+			return text.ends_with(part);
+		}
+
+		match text.find(part) {
+			Some(index) => text = &text[index + part.len()..],
+			None => return false,
+		}
+	}
+
+	true
+}
+
+#[cfg(test)]
+mod tests {
+	use super::glob_match;
+
+	#[test]
+	fn glob_without_wildcards() {
+		assert!(glob_match("cat", "cat"));
+		assert!(!glob_match("cat", "cats"));
+		assert!(!glob_match("cat", ""));
+		assert!(glob_match("", ""));
+	}
+
+	#[test]
+	fn glob_with_wildcards() {
+		assert!(glob_match("*", ""));
+		assert!(glob_match("*", "anything"));
+		assert!(glob_match("cat*", "cat_ears"));
+		assert!(glob_match("*_ears", "cat_ears"));
+		assert!(glob_match("*at*", "cat_ears"));
+		assert!(glob_match("c*_*s", "cat_ears"));
+		assert!(!glob_match("cat*", "dog_ears"));
+		assert!(!glob_match("*_tail", "cat_ears"));
+		assert!(!glob_match("c*t*t", "cat"));
+	}
+
+	#[test]
+	fn glob_parts_do_not_overlap() {
+		assert!(glob_match("ab*ba", "aba_ba"));
+		assert!(!glob_match("ab*ba", "aba"));
+		assert!(!glob_match("a*a*a", "aa"));
+		assert!(glob_match("a*a*a", "aaa"));
+	}
+}
@@ -0,0 +1,408 @@
+use crate::download::{DownloadReport, DownloadStatus, Downloader};
+use crate::events::{Event, EventSink};
+use crate::settings::StorageMode;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+use std::path::{Path, PathBuf};
+use fxhash::{FxHashMap, FxHashSet};
+use std::sync::{Arc, Mutex};
+use philia::prelude::Post;
+use tokio::sync::Notify;
+
+const QUEUE_PATH: &str = "./cache/download_queue.json";
+const SAVE_INTERVAL: Duration = Duration::from_secs(1);
+const MAX_RETRIES: u32 = 3;
+/// Finished items kept in the queue, beyond which the oldest ones are pruned.
+const MAX_FINISHED_ITEMS: usize = 500;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum QueueStatus {
+	Pending,
+	Downloading,
+	Completed,
+	Failed,
+	Cancelled,
+	/// Not downloaded because the library already has the same image.
+	Skipped,
+}
+
+impl QueueStatus {
+	pub fn is_finished(self) -> bool {
+		matches!(
+			self,
+			QueueStatus::Completed
+				| QueueStatus::Failed
+				| QueueStatus::Cancelled
+				| QueueStatus::Skipped
+		)
+	}
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueueItem {
+	/// `{source}_{id}`, unique within the queue.
+	pub key: String,
+	pub post: Post,
+	pub download_folder: PathBuf,
+	#[serde(default = "Default::default")]
+	pub storage: StorageMode,
+	pub status: QueueStatus,
+	#[serde(default = "Default::default")]
+	pub retries: u32,
+	#[serde(default = "Default::default")]
+	pub error: Option<String>,
+	#[serde(default = "Default::default")]
+	pub file_path: Option<PathBuf>,
+	#[serde(default = "Default::default")]
+	pub report: Option<DownloadReport>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct QueueState {
+	paused: bool,
+	items: Vec<QueueItem>,
+	#[serde(skip)]
+	index: FxHashMap<String, usize>,
+	/// Number of `DownloadQueue::wait` calls waiting on each key. These items are never pruned.
+	#[serde(skip)]
+	waiting: FxHashMap<String, usize>,
+	#[serde(skip)]
+	last_saved: Option<Instant>,
+}
+
+impl QueueState {
+	fn reindex(&mut self) {
+		let keys = self.items.iter().enumerate().map(|(i, item)| (item.key.clone(), i));
+		self.index = keys.collect();
+	}
+
+	fn get_mut(&mut self, key: &str) -> Option<&mut QueueItem> {
+		let index = *self.index.get(key)?;
+		self.items.get_mut(index)
+	}
+
+	/// Returns the positions of the given items, or of every item if `keys` is `None`.
+	fn positions(&self, keys: Option<&[String]>) -> Vec<usize> {
+		match keys {
+			None => (0..self.items.len()).collect(),
+			Some(keys) => keys.iter().filter_map(|key| self.index.get(key).copied()).collect(),
+		}
+	}
+
+	fn prune(&mut self) {
+		let finished = self.items.iter().filter(|i| i.status.is_finished()).count();
+		if finished <= MAX_FINISHED_ITEMS {
+			return;
+		}
+
+		let mut excess = finished - MAX_FINISHED_ITEMS;
+		let waiting = &self.waiting;
+		self.items.retain(|item| {
+			let prune = excess > 0 && item.status.is_finished() && !waiting.contains_key(&item.key);
+			if prune {
+				excess -= 1;
+			}
+			!prune
+		});
+
+		self.reindex();
+	}
+}
+
+struct Shared {
+	state: Mutex<QueueState>,
+	notify: Notify,
+	persistent: bool,
+}
+
+/// A download queue persisted to `./cache/download_queue.json`.
+/// Items left unfinished when the application closed are resumed by `DownloadQueue::run`.
+#[derive(Clone)]
+pub struct DownloadQueue {
+	shared: Arc<Shared>,
+}
+
+impl DownloadQueue {
+	pub fn load() -> Self {
+		let mut state = match std::fs::read(QUEUE_PATH) {
+			Err(_) => QueueState::default(),
+			Ok(json) => serde_json::from_slice(&json).unwrap_or_else(|err| {
+				eprintln!("Could not deserialize download queue: {err:?}");
+				QueueState::default()
+			}),
+		};
+
+		for item in &mut state.items {
+			if item.status == QueueStatus::Downloading {
+				item.status = QueueStatus::Pending;
+			}
+		}
+
+		state.reindex();
+		state.prune();
+
+		Self::new(state, true)
+	}
+
+	/// An empty queue that is never saved, leaving the persisted queue to the application.
+	pub fn in_memory() -> Self {
+		Self::new(QueueState::default(), false)
+	}
+
+	fn new(state: QueueState, persistent: bool) -> Self {
+		Self {
+			shared: Arc::new(Shared {
+				state: Mutex::new(state),
+				notify: Notify::new(),
+				persistent,
+			}),
+		}
+	}
+
+	pub fn items(&self) -> Vec<QueueItem> {
+		self.shared.state.lock().unwrap().items.clone()
+	}
+
+	pub fn is_paused(&self) -> bool {
+		self.shared.state.lock().unwrap().paused
+	}
+
+	/// Adds the posts to the queue and returns their keys.
+	/// Posts that are already queued are left untouched, finished ones are queued again.
+	pub fn enqueue(
+		&self, posts: Vec<Post>, download_folder: &Path, storage: StorageMode,
+	) -> Vec<String> {
+		self.modify(true, |state| {
+			let mut keys = Vec::with_capacity(posts.len());
+			for post in posts {
+				let key = format!("{}_{}", post.source, post.id);
+				let item = QueueItem {
+					key: key.clone(),
+					post,
+					download_folder: download_folder.to_path_buf(),
+					storage,
+					status: QueueStatus::Pending,
+					retries: 0,
+					error: None,
+					file_path: None,
+					report: None,
+				};
+
+				match state.get_mut(&key) {
+					None => {
+						state.index.insert(key.clone(), state.items.len());
+						state.items.push(item);
+					},
+					Some(existing) if existing.status.is_finished() => *existing = item,
+					Some(_) => {},
+				}
+
+				keys.push(key);
+			}
+			keys
+		})
+	}
+
+	pub fn pause(&self) {
+		self.modify(true, |state| state.paused = true);
+	}
+
+	pub fn resume(&self) {
+		self.modify(true, |state| state.paused = false);
+	}
+
+	/// Cancels the given items, or every unfinished item if `keys` is `None`.
+	/// Downloads already in progress are allowed to complete, but their result is discarded.
+	pub fn cancel(&self, keys: Option<&[String]>) {
+		self.modify(true, |state| {
+			for position in state.positions(keys) {
+				let item = &mut state.items[position];
+				if !item.status.is_finished() {
+					item.status = QueueStatus::Cancelled;
+				}
+			}
+		});
+	}
+
+	/// Queues the given failed items again, or every failed item if `keys` is `None`.
+	pub fn retry_failed(&self, keys: Option<&[String]>) {
+		self.modify(true, |state| {
+			for position in state.positions(keys) {
+				let item = &mut state.items[position];
+				if item.status == QueueStatus::Failed {
+					item.status = QueueStatus::Pending;
+					item.retries = 0;
+					item.error = None;
+				}
+			}
+		});
+	}
+
+	pub fn clear_finished(&self) {
+		self.modify(true, |state| {
+			state.items.retain(|i| !i.status.is_finished());
+			state.reindex();
+		});
+	}
+
+	/// Waits until every item in `keys` is finished and returns them.
+	/// Emits `Event::DownloadProgress` as items complete.
+	pub async fn wait(&self, keys: &[String], events: &dyn EventSink) -> Vec<QueueItem> {
+		let keys: FxHashSet<_> = keys.iter().cloned().collect();
+		let _guard = WaitGuard::new(self, &keys);
+		let mut last_finished = None;
+		loop {
+			let notified = self.shared.notify.notified();
+			let items: Vec<_> = {
+				let state = self.shared.state.lock().unwrap();
+				let positions = keys.iter().filter_map(|key| state.index.get(key));
+				positions.map(|position| state.items[*position].clone()).collect()
+			};
+
+			let finished = items.iter().filter(|i| i.status.is_finished()).count();
+			if last_finished != Some(finished) {
+				last_finished = Some(finished);
+				let percentage = ((finished as f32 / keys.len().max(1) as f32) * 100.0).trunc();
+				events.emit(Event::DownloadProgress(percentage));
+			}
+
+			if finished == items.len() {
+				return items;
+			}
+
+			notified.await;
+		}
+	}
+
+	/// Processes the queue until the runtime shuts down,
+	/// downloading as many items at once as `downloader`'s throttle allows.
+	pub async fn run(&self, downloader: Downloader, events: Arc<dyn EventSink>) {
+		let workers: Vec<_> = (0..downloader.throttle().concurrency())
+			.map(|_| tokio::spawn(self.clone().work(downloader.clone(), events.clone())))
+			.collect();
+
+		for worker in workers {
+			let _ = worker.await;
+		}
+	}
+
+	async fn work(self, downloader: Downloader, events: Arc<dyn EventSink>) {
+		loop {
+			let notified = self.shared.notify.notified();
+			let next = {
+				let mut state = self.shared.state.lock().unwrap();
+				match state.paused {
+					true => None,
+					false => state
+						.items
+						.iter_mut()
+						.find(|i| i.status == QueueStatus::Pending)
+						.map(|item| {
+							item.status = QueueStatus::Downloading;
+							item.clone()
+						}),
+				}
+			};
+
+			let Some(item) = next else {
+				notified.await;
+				continue;
+			};
+
+			events.emit(Event::QueueItemChanged(item.clone()));
+			let downloader = downloader.with_target(item.download_folder.clone(), item.storage);
+			let report = downloader.download_post(&item.post).await;
+			events.emit(Event::DownloadItemFinished(report.clone()));
+
+			let item = self.modify(false, |state| {
+				let entry = state.get_mut(&item.key)?;
+				if entry.status != QueueStatus::Downloading {
+					return None;
+				}
+
+				entry.error = report.message.clone();
+				entry.file_path = report.path.clone();
+				match report.status {
+					DownloadStatus::Saved | DownloadStatus::Exists => {
+						entry.status = QueueStatus::Completed;
+					},
+					DownloadStatus::Duplicate => {
+						entry.status = QueueStatus::Skipped;
+					},
+					DownloadStatus::Failed => {
+						entry.retries += 1;
+						entry.status = match entry.retries < MAX_RETRIES {
+							true => QueueStatus::Pending,
+							false => QueueStatus::Failed,
+						};
+					},
+				}
+
+				entry.report = Some(report);
+
+				Some(entry.clone())
+			});
+
+			if let Some(item) = item {
+				events.emit(Event::QueueItemChanged(item));
+			}
+		}
+	}
+
+	/// Applies `f` to the queue state, persists it and wakes up any waiting task.
+	/// Unless `force_save` is set, saves are throttled while downloads are in progress.
+	fn modify<R>(&self, force_save: bool, f: impl FnOnce(&mut QueueState) -> R) -> R {
+		let mut state = self.shared.state.lock().unwrap();
+		let result = f(&mut state);
+		state.prune();
+
+		let idle = state.items.iter().all(|i| i.status.is_finished());
+		let due = state.last_saved.map_or(true, |t| t.elapsed() >= SAVE_INTERVAL);
+		if self.shared.persistent && (force_save || idle || due) {
+			state.last_saved = Some(Instant::now());
+			match serde_json::to_string(&*state) {
+				Ok(json) => {
+					if let Err(err) = std::fs::write(QUEUE_PATH, json) {
+						eprintln!("Could not save download queue: {err:?}");
+					}
+				},
+				Err(err) => eprintln!("Could not serialize download queue: {err:?}"),
+			}
+		}
+
+		drop(state);
+		self.shared.notify.notify_waiters();
+		result
+	}
+}
+
+/// Keeps the items of a `DownloadQueue::wait` call from being pruned until it returns.
+struct WaitGuard<'l> {
+	queue: &'l DownloadQueue,
+	keys: Vec<String>,
+}
+
+impl<'l> WaitGuard<'l> {
+	fn new(queue: &'l DownloadQueue, keys: &FxHashSet<String>) -> Self {
+		let mut state = queue.shared.state.lock().unwrap();
+		for key in keys {
+			*state.waiting.entry(key.clone()).or_default() += 1;
+		}
+
+		Self { queue, keys: keys.iter().cloned().collect() }
+	}
+}
+
+impl Drop for WaitGuard<'_> {
+	fn drop(&mut self) {
+		let mut state = self.queue.shared.state.lock().unwrap();
+		for key in &self.keys {
+			if let Some(count) = state.waiting.get_mut(key) {
+				*count -= 1;
+				if *count == 0 {
+					state.waiting.remove(key);
+				}
+			}
+		}
+	}
+}
@@ -0,0 +1,127 @@
+use serde::{Deserialize, Serialize};
+use crate::error::PhiliaError;
+use dirs_next::download_dir;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Settings {
+	pub dark_mode: bool,
+	pub accent: String,
+
+	pub tag_search_result_limit: u32,
+	pub search_image_list_columns: u32,
+	pub full_resolution_preview: bool,
+
+	pub download_folder: PathBuf,
+	
+	#[serde(default = "Default::default")]
+	pub update_branch: UpdateBranch,
+
+	/// Maximum number of simultaneous downloads. Changes take effect after a restart.
+	#[serde(default = "default_download_concurrency")]
+	pub download_concurrency: u32,
+	/// Requests per second allowed for sources without a limit of their own. Zero disables the limit.
+	#[serde(default = "default_requests_per_second")]
+	pub requests_per_second: f32,
+	/// Per-source overrides, taking precedence over the limits declared by the source scripts.
+	#[serde(default = "Default::default")]
+	pub source_requests_per_second: HashMap<String, f32>,
+
+	#[serde(default = "Default::default")]
+	pub storage_mode: StorageMode,
+	/// Skip downloading posts that look the same as an image already in the library.
+	#[serde(default = "Default::default")]
+	pub skip_duplicate_downloads: bool,
+	/// Minutes between automatic subscription checks. Zero disables them.
+	#[serde(default = "Default::default")]
+	pub subscription_check_interval: u32,
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StorageMode {
+	/// Re-encode every image to PNG, with the metadata in an iTXt chunk.
+	#[default]
+	ConvertToPng,
+	/// Keep the original file untouched, with the metadata in a JSON sidecar.
+	OriginalWithSidecar,
+	/// Keep the original image data, embedding the metadata in the file
+	/// (PNG iTXt, JPEG and WebP XMP) or falling back to a JSON sidecar.
+	OriginalEmbedded,
+}
+
+fn default_download_concurrency() -> u32 {
+	6
+}
+
+fn default_requests_per_second() -> f32 {
+	4.0
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub enum UpdateBranch {
+	#[default]
+	Stable,
+	Nightly,
+}
+
+impl Settings {
+	pub fn load() -> Option<Self> {
+		let Ok(json) = std::fs::read("./settings.json") else {
+			eprintln!("Could not read 'settings.json'.");
+			return None;
+		};
+
+		let Ok(settings) = serde_json::from_slice(&json) else {
+			eprintln!("Could not deserialize 'settings.json'.");
+			return None;
+		};
+
+		Some(settings)
+	}
+
+	pub fn save(&self) -> Result<(), PhiliaError> {
+		let json = serde_json::to_string_pretty(self)?;
+		std::fs::write("./settings.json", json)?;
+		Ok(())
+	}
+}
+
+impl Default for Settings {
+	fn default() -> Self {
+		Self {
+			dark_mode: true,
+			accent: "#ffb446".to_string(),
+
+			tag_search_result_limit: 10,
+			search_image_list_columns: 6,
+			full_resolution_preview: false,
+			update_branch: UpdateBranch::Stable,
+			download_concurrency: default_download_concurrency(),
+			requests_per_second: default_requests_per_second(),
+			source_requests_per_second: Default::default(),
+			storage_mode: Default::default(),
+			skip_duplicate_downloads: false,
+			subscription_check_interval: 0,
+
+			download_folder: {
+				fn get_local_download_dir() -> Result<PathBuf, std::io::Error> {
+					std::fs::create_dir_all("./downloads")?;
+					dunce::canonicalize("./downloads")
+				}
+
+				fn get_user_download_dir() -> Option<PathBuf> {
+					let path = download_dir()?.join("Philia");
+					std::fs::create_dir_all(&path).ok()?;
+					Some(path)
+				}
+
+				// Falls back to the relative path, so that the error surfaces on first use.
+				get_local_download_dir()
+					.ok()
+					.or_else(get_user_download_dir)
+					.unwrap_or_else(|| PathBuf::from("./downloads"))
+			},
+		}
+	}
+}
@@ -0,0 +1,382 @@
+use philia::prelude::{Client, SearchOrder, Post, TagOrder};
+use crate::events::{Event, EventSink};
+use crate::normalize::TagTableUrls;
+use crate::error::PhiliaError;
+use crate::throttle;
+use serde::{Deserialize, Serialize};
+use philia::source::ScriptableSource;
+use fxhash::{FxHashMap, FxHashSet};
+use philia::source::FeatureFlags;
+use std::collections::HashSet;
+use std::cmp::{Ordering, Reverse};
+use std::path::Path;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SourceInfo {
+	name: String,
+	search: bool,
+	tag_list: bool,
+	/// Whether the script declares urls to fetch tag aliases and implications from.
+	tag_tables: bool,
+}
+
+#[derive(Default)]
+pub struct SourceRegistry {
+	clients: FxHashMap<String, Client>,
+	tags: FxHashMap<String, Option<FxHashSet<String>>>,
+	rate_limits: FxHashMap<String, f32>,
+	tag_tables: FxHashMap<String, TagTableUrls>,
+}
+
+impl SourceRegistry {
+	pub fn refresh(&mut self) {
+		let _ = std::fs::create_dir_all("./cache");
+		let _ = std::fs::create_dir_all("./sources");
+		let Ok(entries) = std::fs::read_dir("./sources") else {
+			eprintln!("Could not read 'sources' directory.");
+			return;
+		};
+
+		self.clients.clear();
+		self.tags.clear();
+		self.rate_limits.clear();
+		self.tag_tables.clear();
+		for entry in entries.filter_map(Result::ok) {
+			let path = entry.path();
+			if path.extension().map(|s| s.to_str()) != Some(Some("rhai")) {
+				eprintln!("Could not read source {path:?}");
+				continue;
+			}
+
+			let Ok(code) = std::fs::read_to_string(&path) else {
+				eprintln!("Could not read source {path:?}");
+				continue;
+			};
+
+			let Some(name) = path.file_stem().map(|s| s.to_string_lossy().to_string()) else {
+				continue;
+			};
+
+			let source = match ScriptableSource::new(&name, &code) {
+				Ok(source) => source,
+				Err(err) => {
+					eprintln!("Could not compile source {path:?}: {err:?}");
+					continue;
+				},
+			};
+
+			let tags = Path::new("./cache").join(format!("{}_tags.json", name));
+			let tags = match std::fs::read(tags) {
+				Err(_) => None,
+				Ok(file) => match serde_json::from_slice(&file) {
+					Err(_) => continue,
+					Ok(tags) => Some(tags),
+				},
+			};
+
+			if let Some(rate_limit) = parse_rate_limit(&code) {
+				self.rate_limits.insert(name.clone(), rate_limit);
+			}
+
+			let tag_tables = TagTableUrls {
+				aliases: parse_constant(&code, "TAG_ALIASES_URL").map(unquote),
+				implications: parse_constant(&code, "TAG_IMPLICATIONS_URL").map(unquote),
+			};
+			self.tag_tables.insert(name.clone(), tag_tables);
+
+			self.clients.insert(name.clone(), Client::new(source));
+			self.tags.insert(name, tags);
+		}
+	}
+
+	pub fn rate_limits(&self) -> &FxHashMap<String, f32> {
+		&self.rate_limits
+	}
+
+	pub fn tag_tables(&self, source: &str) -> Option<&TagTableUrls> {
+		self.tag_tables.get(source)
+	}
+
+	pub fn client(&self, source: &str) -> Option<Client> {
+		self.clients.get(source).cloned()
+	}
+
+	pub fn names(&self) -> Vec<String> {
+		let mut names: Vec<_> = self.clients.keys().cloned().collect();
+		names.sort();
+		names
+	}
+
+	pub fn info(&self) -> Vec<SourceInfo> {
+		let mut sources: Vec<_> = self
+			.clients
+			.iter()
+			.map(|(name, client)| {
+				let flags = client.source().feature_flags();
+				SourceInfo {
+					name: name.clone(),
+					search: (flags & FeatureFlags::SEARCH) != FeatureFlags::NONE,
+					tag_list: (flags & FeatureFlags::TAG_LIST) != FeatureFlags::NONE,
+					tag_tables: self.tag_tables.get(name).map_or(false, |urls| {
+						urls.aliases.is_some() || urls.implications.is_some()
+					}),
+				}
+			})
+			.collect();
+
+		sources.sort_by(|a, b| a.name.cmp(&b.name));
+		sources
+	}
+
+	pub fn tags(&self, source: &str) -> Option<Vec<String>> {
+		let tags = self.tags.get(source)?.as_ref()?;
+		let mut vec = Vec::from_iter(tags.iter().cloned());
+		vec.sort_by(sort_tags);
+		Some(vec)
+	}
+
+	pub fn set_tags(&mut self, source: &str, all_tags: &[String]) {
+		let Some(tags) = self.tags.get_mut(source) else {
+			return;
+		};
+
+		let tags = tags.insert(HashSet::from_iter(all_tags.iter().cloned()));
+		save_source_tags(source, tags);
+	}
+
+	/// Adds the tags of `posts` to the tag list of `source` and returns the updated, sorted list.
+	pub fn collect_tags(&mut self, source: &str, posts: &[Post]) -> Vec<String> {
+		let Some(tags) = self.tags.get_mut(source) else {
+			return vec![];
+		};
+
+		let tags = match tags {
+			Some(tags) => tags,
+			None => tags.insert(HashSet::default()),
+		};
+
+		tags.extend(posts.iter().map(|p| p.tags.iter().map(str::to_string)).flatten());
+		let mut tags = Vec::from_iter(tags.iter().cloned());
+		tags.sort_by(sort_tags);
+		tags
+	}
+}
+
+/// Tags prefixed with `-` are treated as exclusions.
+pub async fn search(
+	client: &Client, page: u32, limit: u32, order: SearchOrder, tags: Vec<String>,
+) -> Result<Vec<Post>, PhiliaError> {
+	let mut include = vec![];
+	let mut exclude = vec![];
+	for tag in tags.into_iter() {
+		if tag.starts_with('-') {
+			exclude.push((&tag[1..]).to_string());
+		} else {
+			include.push(tag);
+		}
+	}
+
+	client
+		.search_async(page, limit, order, include.into_iter(), exclude.into_iter())
+		.await
+		.map_err(|e| PhiliaError::SourceScript(e.to_string()))
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MergeOrder {
+	/// Alternates between the sources' results, keeping the order each source returned them in.
+	#[default]
+	Interleave,
+	Score,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SourceError {
+	pub source: String,
+	pub error: PhiliaError,
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct FederatedResults {
+	pub posts: Vec<Post>,
+	pub errors: Vec<SourceError>,
+}
+
+/// Searches every client concurrently with the same query and merges the results.
+/// Posts with the same MD5 hash are only kept once, from the source listed first.
+/// A failing source is reported in `FederatedResults::errors` instead of failing the search.
+pub async fn federated_search(
+	clients: Vec<(String, Client)>, page: u32, limit: u32, order: SearchOrder, tags: Vec<String>,
+	merge: MergeOrder,
+) -> FederatedResults {
+	let handles: Vec<_> = clients
+		.into_iter()
+		.map(|(source, client)| {
+			let (order, tags) = (order.clone(), tags.clone());
+			let handle = tokio::spawn(async move { search(&client, page, limit, order, tags).await });
+			(source, handle)
+		})
+		.collect();
+
+	let mut results = FederatedResults::default();
+	let mut lists = vec![];
+	for (source, handle) in handles {
+		let posts = match handle.await {
+			Ok(posts) => posts,
+			Err(err) => Err(PhiliaError::SourceScript(err.to_string())),
+		};
+
+		match posts {
+			Ok(mut posts) => {
+				for post in &mut posts {
+					post.hash = post.hash.to_lowercase();
+					if post.source.is_empty() {
+						post.source = source.clone();
+					}
+				}
+				lists.push(posts);
+			},
+			Err(error) => results.errors.push(SourceError { source, error }),
+		}
+	}
+
+	// Deduplicated before merging, so that the copy kept does not depend on the merge order.
+	let mut hashes = FxHashSet::default();
+	for posts in &mut lists {
+		posts.retain(|post| post.hash.is_empty() || hashes.insert(post.hash.clone()));
+	}
+
+	results.posts = match merge {
+		MergeOrder::Interleave => interleave(lists),
+		MergeOrder::Score => {
+			let mut posts: Vec<_> = lists.into_iter().flatten().collect();
+			posts.sort_by_key(|post| Reverse(i64::from(post.score)));
+			posts
+		},
+	};
+
+	results
+}
+
+fn interleave(lists: Vec<Vec<Post>>) -> Vec<Post> {
+	let mut merged = Vec::with_capacity(lists.iter().map(Vec::len).sum());
+	let mut iterators: Vec<_> = lists.into_iter().map(Vec::into_iter).collect();
+	while !iterators.is_empty() {
+		iterators.retain_mut(|posts| match posts.next() {
+			Some(post) => {
+				merged.push(post);
+				true
+			},
+			None => false,
+		});
+	}
+
+	merged
+}
+
+/// Fetches the source's full tag list, sorted for display.
+/// Emits `Event::FetchSourceTagsCount` after each page.
+pub async fn fetch_tags(
+	client: &Client, events: &dyn EventSink,
+) -> Result<Vec<String>, PhiliaError> {
+	let mut all_tags = vec![];
+	for i in 1..25 {
+		let tags = client
+			.get_tags_async(i, 1000, TagOrder::Count)
+			.await
+			.map_err(|e| PhiliaError::SourceScript(e.to_string()))?;
+		if tags.is_empty() {
+			break;
+		}
+		all_tags.extend(tags.into_iter().map(|tag| tag.name));
+		events.emit(Event::FetchSourceTagsCount(all_tags.len()));
+	}
+
+	all_tags.sort_by(sort_tags);
+	Ok(all_tags)
+}
+
+/// Writes the tag list to `./cache/{source}_tags.json`, where `SourceRegistry::refresh` picks it up.
+fn save_source_tags(source: &str, tags: &FxHashSet<String>) {
+	let path = Path::new("./cache").join(format!("{}_tags.json", source));
+	let result = serde_json::to_string(tags)
+		.map_err(PhiliaError::from)
+		.and_then(|json| Ok(std::fs::write(path, json)?));
+
+	if let Err(err) = result {
+		eprintln!("Could not save tags for source {source:?}: {err:?}");
+	}
+}
+
+fn parse_rate_limit(code: &str) -> Option<f32> {
+	let rate = parse_constant(code, "REQUESTS_PER_SECOND")?.parse().ok()?;
+	Some(throttle::clamp_rate(rate))
+}
+
+/// Finds the value of a top-level `const NAME = value;` declaration.
+fn parse_constant<'c>(code: &'c str, name: &str) -> Option<&'c str> {
+	code.lines().find_map(|line| {
+		let value = line.trim().strip_prefix("const ")?.trim_start().strip_prefix(name)?;
+		let value = value.trim_start().strip_prefix('=')?;
+		Some(value.trim().trim_end_matches(';').trim())
+	})
+}
+
+fn unquote(value: &str) -> String {
+	value.trim_matches('"').to_string()
+}
+
+fn sort_tags(a: &String, b: &String) -> Ordering {
+	let a = match a.chars().next().unwrap_or_default().is_alphabetic() {
+		true => a.as_str(),
+		false => "z",
+	};
+
+	let b = match b.chars().next().unwrap_or_default().is_alphabetic() {
+		true => b.as_str(),
+		false => "z",
+	};
+
+	a.cmp(b)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{parse_constant, parse_rate_limit};
+	use crate::throttle::MIN_REQUESTS_PER_SECOND;
+
+	const SCRIPT: &str = "
+		const NAME = \"Example\";
+		const REQUESTS_PER_SECOND = 2.5;
+		  const   ALIASES_URL =  \"https://example.com/aliases\" ;
+		// const COMMENTED = 1;
+		let NOT_CONSTANT = 3;
+		const NAME_SUFFIX = 4;
+	";
+
+	#[test]
+	fn constants() {
+		assert_eq!(parse_constant(SCRIPT, "NAME"), Some("\"Example\""));
+		assert_eq!(parse_constant(SCRIPT, "ALIASES_URL"), Some("\"https://example.com/aliases\""));
+		assert_eq!(parse_constant(SCRIPT, "NAME_SUFFIX"), Some("4"));
+		assert_eq!(parse_constant(SCRIPT, "COMMENTED"), None);
+		assert_eq!(parse_constant(SCRIPT, "NOT_CONSTANT"), None);
+		assert_eq!(parse_constant(SCRIPT, "MISSING"), None);
+	}
+
+	#[test]
+	fn rate_limits() {
+		assert_eq!(parse_rate_limit(SCRIPT), Some(2.5));
+		assert_eq!(parse_rate_limit("const REQUESTS_PER_SECOND = 10;"), Some(10.0));
+		assert_eq!(parse_rate_limit("const REQUESTS_PER_SECOND = fast;"), None);
+		assert_eq!(parse_rate_limit("const NAME = \"Example\";"), None);
+	}
+
+	#[test]
+	fn rate_limits_are_clamped() {
+		let tiny = parse_rate_limit("const REQUESTS_PER_SECOND = 1e-40;");
+		assert_eq!(tiny, Some(MIN_REQUESTS_PER_SECOND));
+		assert_eq!(parse_rate_limit("const REQUESTS_PER_SECOND = -1;"), Some(0.0));
+		assert_eq!(parse_rate_limit("const REQUESTS_PER_SECOND = inf;"), Some(0.0));
+	}
+}
@@ -0,0 +1,170 @@
+use crate::datasets::Dataset;
+use fxhash::{FxHashMap, FxHashSet};
+use crate::error::PhiliaError;
+use std::path::{Path, PathBuf};
+use crate::images::Image;
+use itertools::Itertools;
+use serde::Serialize;
+
+/// Number of most frequent tags whose co-occurrence is computed by default.
+pub const DEFAULT_TOP_TAGS: usize = 50;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TagFrequency {
+	pub tag: String,
+	pub category: Option<String>,
+	pub count: usize,
+	/// Fraction of the images tagged with `tag`.
+	pub frequency: f32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CategoryFrequency {
+	pub category: String,
+	/// Number of tags of this category over every image.
+	pub count: usize,
+	/// Number of distinct tags of this category.
+	pub tags: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TagPair {
+	pub first: String,
+	pub second: String,
+	pub count: usize,
+}
+
+/// Tag statistics of a dataset, computed from the captions its export would write.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct DatasetStats {
+	pub images: usize,
+	/// Sorted by decreasing count.
+	pub tags: Vec<TagFrequency>,
+	/// Sorted by decreasing count.
+	pub categories: Vec<CategoryFrequency>,
+	/// Co-occurrence of the `top` most frequent tags, sorted by decreasing count.
+	pub co_occurrence: Vec<TagPair>,
+}
+
+impl DatasetStats {
+	pub fn compute(dataset: &Dataset, images: &FxHashMap<PathBuf, Image>, top: usize) -> Self {
+		let captions = dataset
+			.images()
+			.iter()
+			.filter_map(|path| Some(dataset.caption_tags(path, &images.get(Path::new(path))?.info)))
+			.collect_vec();
+
+		let mut tags: FxHashMap<&str, (Option<&str>, usize)> = FxHashMap::default();
+		let mut categories: FxHashMap<&str, (usize, FxHashSet<&str>)> = FxHashMap::default();
+		for caption in &captions {
+			let mut seen = FxHashSet::default();
+			for tag in caption.iter().filter(|tag| seen.insert(tag.tag.as_str())) {
+				tags.entry(&tag.tag).or_insert((tag.category, 0)).1 += 1;
+				if let Some(category) = tag.category {
+					let (count, distinct) = categories.entry(category).or_default();
+					*count += 1;
+					distinct.insert(&tag.tag);
+				}
+			}
+		}
+
+		let image_count = captions.len().max(1) as f32;
+		let tags = tags
+			.into_iter()
+			.map(|(tag, (category, count))| TagFrequency {
+				tag: tag.to_string(),
+				category: category.map(str::to_string),
+				count,
+				frequency: count as f32 / image_count,
+			})
+			.sorted_by(|a, b| b.count.cmp(&a.count).then_with(|| a.tag.cmp(&b.tag)))
+			.collect_vec();
+
+		let categories = categories
+			.into_iter()
+			.map(|(category, (count, distinct))| CategoryFrequency {
+				category: category.to_string(),
+				count,
+				tags: distinct.len(),
+			})
+			.sorted_by(|a, b| b.count.cmp(&a.count).then_with(|| a.category.cmp(&b.category)))
+			.collect_vec();
+
+		let top_tags: FxHashMap<&str, usize> =
+			tags.iter().take(top).enumerate().map(|(i, tag)| (tag.tag.as_str(), i)).collect();
+
+		let mut pairs: FxHashMap<(usize, usize), usize> = FxHashMap::default();
+		for caption in &captions {
+			let indices = caption
+				.iter()
+				.filter_map(|tag| top_tags.get(tag.tag.as_str()).copied())
+				.sorted()
+				.dedup()
+				.collect_vec();
+
+			for (a, b) in indices.iter().tuple_combinations() {
+				*pairs.entry((*a, *b)).or_default() += 1;
+			}
+		}
+
+		let co_occurrence = pairs
+			.into_iter()
+			.sorted_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then(a.cmp(b)))
+			.map(|((first, second), count)| TagPair {
+				first: tags[first].tag.clone(),
+				second: tags[second].tag.clone(),
+				count,
+			})
+			.collect();
+
+		Self {
+			images: captions.len(),
+			tags,
+			categories,
+			co_occurrence,
+		}
+	}
+
+	/// Writes `tag_stats.json`, `tag_frequency.csv`, `category_frequency.csv`
+	/// and `tag_co_occurrence.csv` inside `folder`.
+	pub fn write(&self, folder: &Path) -> Result<(), PhiliaError> {
+		std::fs::write(folder.join("tag_stats.json"), serde_json::to_string_pretty(self)?)?;
+
+		let mut csv = String::from("tag,category,count,frequency\n");
+		for tag in &self.tags {
+			let category = tag.category.as_deref().unwrap_or_default();
+			csv += &format! {
+				"{},{},{},{}\n",
+				csv_field(&tag.tag),
+				csv_field(category),
+				tag.count,
+				tag.frequency,
+			};
+		}
+		std::fs::write(folder.join("tag_frequency.csv"), csv)?;
+
+		let mut csv = String::from("category,count,tags\n");
+		for category in &self.categories {
+			let name = csv_field(&category.category);
+			csv += &format!("{},{},{}\n", name, category.count, category.tags);
+		}
+		std::fs::write(folder.join("category_frequency.csv"), csv)?;
+
+		let mut csv = String::from("first,second,count\n");
+		for pair in &self.co_occurrence {
+			let (first, second) = (csv_field(&pair.first), csv_field(&pair.second));
+			csv += &format!("{},{},{}\n", first, second, pair.count);
+		}
+		std::fs::write(folder.join("tag_co_occurrence.csv"), csv)?;
+
+		Ok(())
+	}
+}
+
+/// Quotes `field` if it contains a separator, a quote or a line break.
+fn csv_field(field: &str) -> String {
+	match field.contains(|c: char| matches!(c, ',' | '"' | '\n' | '\r')) {
+		false => field.to_string(),
+		true => format!("\"{}\"", field.replace('"', "\"\"")),
+	}
+}
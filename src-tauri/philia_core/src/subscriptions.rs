@@ -0,0 +1,208 @@
+use philia::prelude::{Client, Post, SearchOrder};
+use serde::{Deserialize, Serialize};
+use crate::events::{Event, EventSink};
+use crate::error::PhiliaError;
+use crate::context::Context;
+use std::sync::Mutex;
+use crate::sources;
+
+const SUBSCRIPTIONS_PATH: &str = "./subscriptions.json";
+
+const PAGE_SIZE: u32 = 100;
+/// Upper bound on the pages fetched by a single check.
+const MAX_PAGES: u32 = 10;
+
+/// A saved search, checked periodically for posts newer than the last ones seen.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Subscription {
+	pub name: String,
+	pub source: String,
+	#[serde(default = "Default::default")]
+	pub tags: Vec<String>,
+	/// Always `SearchOrder::Newest`, since `last_seen_id` relies on new posts coming first.
+	pub order: SearchOrder,
+	/// The highest post ID seen so far. Only posts with a higher ID are downloaded.
+	#[serde(default = "Default::default")]
+	pub last_seen_id: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SubscriptionCheck {
+	pub name: String,
+	pub new_posts: usize,
+	pub error: Option<PhiliaError>,
+	/// Queue keys of the new posts.
+	#[serde(skip)]
+	pub keys: Vec<String>,
+}
+
+impl Subscription {
+	/// Searches the subscription's source for posts newer than `last_seen_id`.
+	/// Pages are fetched until one contains no new posts. A subscription that has never been
+	/// checked only takes the first page, instead of downloading the whole search history.
+	pub async fn fetch_new_posts(&self, client: &Client) -> Result<Vec<Post>, PhiliaError> {
+		let mut new_posts = vec![];
+		for page in 1..=MAX_PAGES {
+			let order = SearchOrder::Newest;
+			let posts = sources::search(client, page, PAGE_SIZE, order, self.tags.clone()).await?;
+			let count = posts.len();
+			let posts = posts.into_iter().filter(|post| self.is_new(post)).collect::<Vec<_>>();
+
+			let exhausted = posts.is_empty() || count < PAGE_SIZE as usize;
+			new_posts.extend(posts);
+			if exhausted || self.last_seen_id.is_none() {
+				break;
+			}
+		}
+
+		Ok(new_posts)
+	}
+
+	pub fn is_new(&self, post: &Post) -> bool {
+		self.last_seen_id.map_or(true, |last_seen_id| post.id as u64 > last_seen_id)
+	}
+
+	pub fn mark_seen(&mut self, posts: &[Post]) {
+		let highest = posts.iter().map(|post| post.id as u64).max();
+		self.last_seen_id = self.last_seen_id.max(highest);
+	}
+}
+
+/// The saved searches, persisted to `subscriptions.json` next to `settings.json`.
+#[derive(Default)]
+pub struct Subscriptions {
+	pub list: Vec<Subscription>,
+}
+
+impl Subscriptions {
+	pub fn load() -> Self {
+		let Ok(json) = std::fs::read(SUBSCRIPTIONS_PATH) else {
+			return Self::default();
+		};
+
+		match serde_json::from_slice(&json) {
+			Ok(list) => Self { list },
+			Err(err) => {
+				eprintln!("Could not deserialize '{}': {:?}", SUBSCRIPTIONS_PATH, err);
+				Self::default()
+			},
+		}
+	}
+
+	pub fn save(&self) -> Result<(), PhiliaError> {
+		let json = serde_json::to_string_pretty(&self.list)?;
+		std::fs::write(SUBSCRIPTIONS_PATH, json)?;
+		Ok(())
+	}
+
+	pub fn add(&mut self, subscription: Subscription) -> Result<(), PhiliaError> {
+		validate(&subscription)?;
+		if self.list.iter().any(|s| s.name == subscription.name) {
+			let message = format!("A subscription named {:?} already exists", subscription.name);
+			return Err(PhiliaError::Validation(message));
+		}
+
+		self.list.push(subscription);
+		self.save()
+	}
+
+	pub fn remove(&mut self, name: &str) -> Result<(), PhiliaError> {
+		let Some(index) = self.list.iter().position(|s| s.name == name) else {
+			return Err(subscription_not_found(name));
+		};
+
+		self.list.remove(index);
+		self.save()
+	}
+
+	/// Replaces the subscription named `name`, which may be renamed.
+	pub fn set(&mut self, name: &str, subscription: Subscription) -> Result<(), PhiliaError> {
+		validate(&subscription)?;
+		if subscription.name != name && self.list.iter().any(|s| s.name == subscription.name) {
+			let message = format!("A subscription named {:?} already exists", subscription.name);
+			return Err(PhiliaError::Validation(message));
+		}
+
+		let Some(existing) = self.list.iter_mut().find(|s| s.name == name) else {
+			return Err(subscription_not_found(name));
+		};
+
+		*existing = subscription;
+		self.save()
+	}
+
+	pub fn mark_seen(&mut self, name: &str, posts: &[Post]) -> Result<(), PhiliaError> {
+		let Some(subscription) = self.list.iter_mut().find(|s| s.name == name) else {
+			return Err(subscription_not_found(name));
+		};
+
+		subscription.mark_seen(posts);
+		self.save()
+	}
+}
+
+/// Queues the new posts of every subscription for download, emitting
+/// `Event::SubscriptionChecked` after each one. The queue persists and retries its items,
+/// so the posts count as seen once queued. `context` is not locked while searching.
+pub async fn check_all(context: &Mutex<Context>, events: &dyn EventSink) -> Vec<SubscriptionCheck> {
+	let subscriptions: Vec<_> = {
+		let context = context.lock().unwrap();
+		let sources = &context.sources;
+		context
+			.subscriptions
+			.list
+			.iter()
+			.map(|subscription| (subscription.clone(), sources.client(&subscription.source)))
+			.collect()
+	};
+
+	let mut checks = vec![];
+	for (subscription, client) in subscriptions {
+		let posts = match client {
+			Some(client) => subscription.fetch_new_posts(&client).await,
+			None => Err(PhiliaError::NotFound(format! {
+				"Source {:?} does not exist",
+				subscription.source,
+			})),
+		};
+
+		let check = match posts {
+			Err(error) => SubscriptionCheck {
+				name: subscription.name,
+				new_posts: 0,
+				error: Some(error),
+				keys: vec![],
+			},
+			Ok(posts) => {
+				let mut context = context.lock().unwrap();
+				let settings = &context.settings;
+				let (folder, storage) = (settings.download_folder.clone(), settings.storage_mode);
+				let keys = context.queue.enqueue(posts.clone(), &folder, storage);
+				let error = context.subscriptions.mark_seen(&subscription.name, &posts).err();
+
+				SubscriptionCheck {
+					name: subscription.name,
+					new_posts: posts.len(),
+					error,
+					keys,
+				}
+			},
+		};
+
+		events.emit(Event::SubscriptionChecked(check.clone()));
+		checks.push(check);
+	}
+
+	checks
+}
+
+fn validate(subscription: &Subscription) -> Result<(), PhiliaError> {
+	match subscription.order {
+		SearchOrder::Newest => Ok(()),
+		_ => Err(PhiliaError::Validation("Subscriptions must be ordered by newest".to_string())),
+	}
+}
+
+fn subscription_not_found(name: &str) -> PhiliaError {
+	PhiliaError::NotFound(format!("Subscription {:?} does not exist", name))
+}
@@ -0,0 +1,100 @@
+use philia::client::DEFAULT_USER_AGENT;
+use tokio::sync::{Semaphore, SemaphorePermit};
+use std::time::{Duration, Instant};
+use fxhash::FxHashMap;
+use std::sync::Mutex;
+
+/// The lowest rate limit, below which a request would wait for an unreasonable time.
+pub const MIN_REQUESTS_PER_SECOND: f32 = 0.01;
+
+/// Limits how hard downloads hit the sources.
+///
+/// Shares a single connection-pooled HTTP client, caps the number of concurrent requests
+/// and spaces out requests to the same source according to its requests-per-second limit.
+pub struct Throttle {
+	client: reqwest::Client,
+	permits: Semaphore,
+	concurrency: usize,
+	limits: Mutex<RateLimits>,
+	next_request: Mutex<FxHashMap<String, Instant>>,
+}
+
+#[derive(Default)]
+struct RateLimits {
+	default: f32,
+	sources: FxHashMap<String, f32>,
+}
+
+impl Throttle {
+	/// The concurrency cap is fixed for the lifetime of the throttle.
+	pub fn new(concurrency: usize) -> Self {
+		let concurrency = concurrency.max(1);
+		Self {
+			client: reqwest::Client::builder()
+				.user_agent(DEFAULT_USER_AGENT)
+				.build()
+				.expect("Could not create HTTP client"),
+			permits: Semaphore::new(concurrency),
+			concurrency,
+			limits: Default::default(),
+			next_request: Default::default(),
+		}
+	}
+
+	pub fn client(&self) -> &reqwest::Client {
+		&self.client
+	}
+
+	pub fn concurrency(&self) -> usize {
+		self.concurrency
+	}
+
+	/// Sources without an entry in `sources` use `default`. Non-positive values disable the limit.
+	pub fn configure(&self, default: f32, mut sources: FxHashMap<String, f32>) {
+		sources.values_mut().for_each(|rate| *rate = clamp_rate(*rate));
+		let default = clamp_rate(default);
+		*self.limits.lock().unwrap() = RateLimits { default, sources };
+	}
+
+	pub async fn acquire(&self) -> SemaphorePermit<'_> {
+		self.permits.acquire().await.expect("Throttle semaphore closed")
+	}
+
+	/// Waits until a request to `source` is allowed by its rate limit and any pending back-off.
+	pub async fn wait_turn(&self, source: &str) {
+		let rate = {
+			let limits = self.limits.lock().unwrap();
+			limits.sources.get(source).copied().unwrap_or(limits.default)
+		};
+
+		let interval = match rate > 0.0 {
+			true => Duration::try_from_secs_f64(1.0 / rate as f64).unwrap_or_default(),
+			false => Duration::ZERO,
+		};
+
+		let wait = {
+			let mut next_request = self.next_request.lock().unwrap();
+			let now = Instant::now();
+			let slot = next_request.get(source).map_or(now, |next| (*next).max(now));
+			next_request.insert(source.to_string(), slot + interval);
+			slot - now
+		};
+
+		tokio::time::sleep(wait).await;
+	}
+
+	pub fn back_off(&self, source: &str, delay: Duration) {
+		let mut next_request = self.next_request.lock().unwrap();
+		let resume = Instant::now() + delay;
+		let next = next_request.entry(source.to_string()).or_insert(resume);
+		*next = (*next).max(resume);
+	}
+}
+
+/// Disables non-positive, infinite and invalid limits, and raises tiny ones to the minimum.
+pub fn clamp_rate(rate: f32) -> f32 {
+	match rate.is_finite() && rate > 0.0 {
+		true => rate.max(MIN_REQUESTS_PER_SECOND),
+		false => 0.0,
+	}
+}
@@ -0,0 +1,64 @@
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use std::time::{Duration, Instant};
+use std::path::{Path, PathBuf};
+use fxhash::FxHashSet;
+use std::sync::mpsc;
+
+/// How long the folder must stay quiet before a batch of changes is delivered.
+const DEBOUNCE: Duration = Duration::from_millis(500);
+/// Upper bound on how long a batch can keep growing during sustained activity.
+const MAX_BATCH_DELAY: Duration = Duration::from_secs(3);
+
+/// Watches the files directly inside a folder. Stops when dropped.
+pub struct FolderWatcher {
+	_watcher: RecommendedWatcher,
+}
+
+impl FolderWatcher {
+	/// Calls `on_change` from a background thread with the paths created, modified or removed
+	/// inside `folder`, batched over bursts of activity.
+	/// Paths are joined onto `folder` as given, so they match those read by `Library::refresh_images`.
+	pub fn new(
+		folder: &Path, on_change: impl Fn(Vec<PathBuf>) + Send + 'static,
+	) -> notify::Result<Self> {
+		let (sender, receiver) = mpsc::channel();
+		let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+			match event {
+				Ok(event) => {
+					for path in event.paths {
+						let _ = sender.send(path);
+					}
+				},
+				Err(err) => eprintln!("Folder watcher error: {:?}", err),
+			}
+		})?;
+
+		watcher.watch(folder, RecursiveMode::NonRecursive)?;
+
+		let folder = folder.to_path_buf();
+		std::thread::spawn(move || {
+			// The channel disconnects once the watcher, which owns the sender, is dropped.
+			while let Ok(path) = receiver.recv() {
+				let start = Instant::now();
+				let mut paths = FxHashSet::default();
+				paths.insert(path);
+
+				while start.elapsed() < MAX_BATCH_DELAY {
+					match receiver.recv_timeout(DEBOUNCE) {
+						Ok(path) => paths.insert(path),
+						Err(_) => break,
+					};
+				}
+
+				let paths = paths
+					.into_iter()
+					.filter_map(|path| Some(folder.join(path.file_name()?)))
+					.collect();
+
+				on_change(paths);
+			}
+		});
+
+		Ok(Self { _watcher: watcher })
+	}
+}
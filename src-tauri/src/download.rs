@@ -1,159 +1,129 @@
-use philia::client::{DEFAULT_USER_AGENT, make_async_http_client};
-use png::{BitDepth, ColorType, Compression, Encoder};
-use crate::context::GlobalContext;
-use std::io::{BufWriter, Cursor};
+use crate::context::{GlobalContext, TauriEvents};
+use philia_core::bulk::{self, BulkDownloadProgress, BulkDownloadRequest};
+use philia_core::queue::{QueueItem, QueueStatus};
+use philia_core::download::DownloadStatus;
+use philia_core::{DownloadQueue, PhiliaError};
 use tauri::{AppHandle, Manager};
-use std::sync::{Arc, Mutex};
 use philia::prelude::Post;
-use image::ImageFormat;
-use std::fs::File;
 
+/// Downloads the posts through the queue and returns their items once finished.
 #[tauri::command]
-pub async fn download_posts(posts: Vec<Post>, handle: AppHandle) -> Result<Vec<String>, String> {
-	let download_folder = {
+pub async fn download_posts(
+	posts: Vec<Post>, handle: AppHandle,
+) -> Result<Vec<QueueItem>, PhiliaError> {
+	let (queue, keys) = {
 		let context = handle.state::<GlobalContext>();
 		let context = context.lock().unwrap();
-		context.settings.download_folder.clone()
+		let settings = &context.settings;
+		let keys = context.queue.enqueue(posts, &settings.download_folder, settings.storage_mode);
+		(context.queue.clone(), keys)
 	};
 
-	let count = posts.len();
-	let handle_2 = handle.clone();
-	let progress = Arc::new(Mutex::new(0f32));
-
-	let promises: Vec<_> = posts
-		.into_iter()
-		.map(move |post| {
-			let handle = handle.clone();
-			let progress = progress.clone();
-			let download_folder = download_folder.clone();
-			tauri::async_runtime::spawn(async move {
-				macro_rules! inc_ret {
-					($val: expr) => {{
-						let mut progress = progress.lock().unwrap();
-						let _ = handle
-							.emit_all("download_progress", ((*progress / count as f32) * 100.0).trunc());
-						*progress += 1.0;
-						return $val;
-					}};
-				}
-
-				let client = make_async_http_client(DEFAULT_USER_AGENT).unwrap();
-
-				let filename = format!("{}_{}.png", post.source, post.id);
-				let filepath = download_folder.join(filename);
-				if filepath.exists() {
-					inc_ret!(Err("File exists"));
-				}
-
-				let url = match post.resource_url.as_ref().map(String::as_str) {
-					Some(url) => url,
-					None => inc_ret!(Err("Missing resource url")),
-				};
-
-				if let Some(dot) = url.rfind('.') {
-					match &url[dot + 1..] {
-						"mp4" | "flv" | "ogg" | "webm" | "gif" => {
-							inc_ret!(Err("Unsupported file type"));
-						},
-						_ => {},
-					}
-				}
-
-				let response = match client.get(url).send().await {
-					Ok(response) => response,
-					Err(err) => {
-						eprintln!("{:?}", err);
-						inc_ret!(Err("Download failed"));
-					},
-				};
-
-				let mut data = match response.bytes().await {
-					Ok(data) => data.to_vec(),
-					Err(err) => {
-						eprintln!("{:?}", err);
-						inc_ret!(Err("Download failed"));
-					},
-				};
-
-				if let Err(err) = convert_to_png(&mut data) {
-					eprintln!("{:?}", err);
-					inc_ret!(Err("Png conversion failed"));
-				}
-
-				let image = image::load_from_memory(&data).unwrap();
-
-				let file = match File::create(&filepath) {
-					Ok(file) => file,
-					Err(err) => {
-						eprintln!("{:?}", err);
-						inc_ret!(Err("File creation failed"));
-					},
-				};
-
-				let buf_writer = BufWriter::new(file);
-				let mut encoder = Encoder::new(buf_writer, image.width(), image.height());
-				encoder.set_depth(BitDepth::Eight);
-				encoder.set_color(ColorType::Rgba);
-				encoder.set_compression(Compression::Best);
-
-				let post_metadata = serde_json::to_string(&post).unwrap();
-				if let Err(err) = encoder.add_itxt_chunk("post_metadata".into(), post_metadata) {
-					eprintln!("Could not write tags {:?}", err);
-					inc_ret!(Err("Metadata encoding failed"));
-				}
-
-				let mut writer = match encoder.write_header() {
-					Ok(writer) => writer,
-					Err(err) => {
-						eprintln!("{:?}", err);
-						inc_ret!(Err("Header creation failed"));
-					},
-				};
-
-				let pixel_bytes = image.as_bytes();
-				if let Err(err) = writer.write_image_data(&pixel_bytes) {
-					eprintln!("{:?}", err);
-					inc_ret!(Err("Save operation failed"));
-				}
-
-				inc_ret!(Ok(filepath));
-			})
-		})
-		.collect();
+	let mut items = queue.wait(&keys, &TauriEvents(handle.clone())).await;
+	for item in &mut items {
+		if let Some(path) = &item.file_path {
+			item.file_path = Some(path.to_string_lossy().replace('\\', "/").into());
+		}
+	}
 
-	let mut paths = Vec::with_capacity(promises.len());
-	for handle in promises {
-		if let Ok(Ok(path)) = handle.await {
-			paths.push(path.to_string_lossy().replace('\\', "/"));
+	let (mut saved, mut skipped) = (0, 0);
+	for item in &items {
+		let report = item.report.as_ref().map(|report| &report.status);
+		match (item.status, report) {
+			(QueueStatus::Completed, Some(DownloadStatus::Saved)) => saved += 1,
+			// Posts already in the library are completed or skipped without being downloaded.
+			(QueueStatus::Completed | QueueStatus::Skipped, _) => skipped += 1,
+			_ => {},
 		}
 	}
 
-	let id = &handle_2.config().tauri.bundle.identifier;
-	if paths.len() != count {
+	let failed = items.len() - saved - skipped;
+	let id = &handle.config().tauri.bundle.identifier;
+	if failed == 0 {
 		let _ = tauri::api::notification::Notification::new(id)
-			.title(format!("All posts have been downloaded."))
-			.body(format!("Successfully downloaded {} posts.", paths.len()))
+			.title("All posts have been downloaded.")
+			.body(format!("Downloaded {} posts, skipped {} already saved.", saved, skipped))
 			.show();
 	} else {
 		let _ = tauri::api::notification::Notification::new(id)
-			.title(format!("Downloaded {} posts.", paths.len()))
-			.body(format!{
-				"Successfully downloaded {} posts.\nFailed to download {} posts.",
-				paths.len(), count - paths.len()
+			.title(format!("Downloaded {} posts.", saved))
+			.body(format! {
+				"Downloaded {} posts, skipped {} already saved.\nFailed to download {} posts.",
+				saved, skipped, failed
 			})
 			.show();
 	}
 
-	Ok(paths)
+	Ok(items)
+}
+
+/// Queues every result of a search, page by page, emitting `bulk_download_progress` events.
+/// Returns once all pages are queued, the limits are reached or the download is cancelled.
+#[tauri::command]
+pub async fn download_search(
+	request: BulkDownloadRequest, handle: AppHandle,
+) -> Result<BulkDownloadProgress, PhiliaError> {
+	let (client, queue, downloader, bulk) = {
+		let context = handle.state::<GlobalContext>();
+		let mut context = context.lock().unwrap();
+		let Some(client) = context.sources.client(&request.source) else {
+			let message = format!("Source {:?} does not exist", request.source);
+			return Err(PhiliaError::NotFound(message));
+		};
+
+		let downloader = context.downloader();
+		(client, context.queue.clone(), downloader, context.bulk_downloads.start())
+	};
+
+	let events = TauriEvents(handle.clone());
+	let progress =
+		bulk::bulk_download(&client, &request, &queue, &downloader, &bulk, &events).await;
+
+	let context = handle.state::<GlobalContext>();
+	context.lock().unwrap().bulk_downloads.finish(bulk.id());
+	Ok(progress)
+}
+
+#[tauri::command]
+pub async fn cancel_bulk_download(id: Option<u64>, handle: AppHandle) {
+	let context = handle.state::<GlobalContext>();
+	context.lock().unwrap().bulk_downloads.cancel(id);
+}
+
+#[tauri::command]
+pub async fn get_download_queue(handle: AppHandle) -> Vec<QueueItem> {
+	queue(&handle).items()
+}
+
+#[tauri::command]
+pub async fn pause_downloads(handle: AppHandle) {
+	queue(&handle).pause();
 }
 
-pub fn convert_to_png(buffer: &mut Vec<u8>) -> Result<(), String> {
-	let mut new_buffer = vec![];
-	let image = image::load_from_memory(buffer).map_err(|e| e.to_string())?.to_rgba8();
+#[tauri::command]
+pub async fn resume_downloads(handle: AppHandle) {
+	queue(&handle).resume();
+}
+
+#[tauri::command]
+pub async fn cancel_downloads(keys: Option<Vec<String>>, handle: AppHandle) {
+	queue(&handle).cancel(keys.as_deref());
+}
+
+#[tauri::command]
+pub async fn retry_failed_downloads(keys: Option<Vec<String>>, handle: AppHandle) {
+	queue(&handle).retry_failed(keys.as_deref());
+}
+
+#[tauri::command]
+pub async fn clear_download_queue(handle: AppHandle) -> Vec<QueueItem> {
+	let queue = queue(&handle);
+	queue.clear_finished();
+	queue.items()
+}
 
-	image
-		.write_to(&mut Cursor::new(&mut new_buffer), ImageFormat::Png)
-		.unwrap();
-	*buffer = new_buffer;
-	Ok(())
+fn queue(handle: &AppHandle) -> DownloadQueue {
+	let context = handle.state::<GlobalContext>();
+	let context = context.lock().unwrap();
+	context.queue.clone()
 }
@@ -3,26 +3,37 @@
 	windows_subsystem = "windows"
 )]
 
-use crate::images::PreviewCache;
+use tauri::{AppHandle, command, Manager, WindowBuilder, WindowUrl};
+use philia_core::images::{ThumbnailStore, THUMBNAIL_PROTOCOL};
 use crate::update::check_for_updates;
 use std::time::{Duration, SystemTime};
-use crate::context::{Context, GlobalContext};
-use tauri::{AppHandle, command, Manager, WindowBuilder, WindowUrl};
+use crate::context::{watch_download_folder, DownloadFolderWatcher, GlobalContext, TauriEvents};
+use philia_core::Context;
+use std::sync::Arc;
 
 mod sources;
 mod download;
 mod settings;
 mod datasets;
 mod images;
+mod subscriptions;
 mod context;
 mod update;
 
 #[command]
 async fn initialize(app: AppHandle) {
 	let now = SystemTime::now();
-	let preview_cache = PreviewCache::default();
-	app.manage(preview_cache.clone());
-	app.manage(GlobalContext::new(Context::load_or_default(preview_cache)));
+	let context = Context::load_or_default();
+	let queue = context.queue.clone();
+	let downloader = context.downloader();
+	app.manage(Arc::new(ThumbnailStore::default()));
+	app.manage(GlobalContext::new(context));
+	app.manage(DownloadFolderWatcher::default());
+	watch_download_folder(&app);
+
+	let events = Arc::new(TauriEvents(app.clone()));
+	tauri::async_runtime::spawn(async move { queue.run(downloader, events).await });
+	tauri::async_runtime::spawn(subscriptions::check_periodically(app.clone()));
 	
 	if let Some(sleep) = Duration::from_secs(1).checked_sub(now.elapsed().unwrap()) {
 		tokio::time::sleep(sleep).await;
@@ -60,22 +71,44 @@ fn main() {
 			sources::fetch_source_tags,
 			sources::get_source_tags,
 			sources::search,
+			sources::federated_search,
+			sources::fetch_tag_tables,
+			sources::reload_tag_tables,
 			download::download_posts,
+			download::download_search,
+			download::cancel_bulk_download,
+			download::get_download_queue,
+			download::pause_downloads,
+			download::resume_downloads,
+			download::cancel_downloads,
+			download::retry_failed_downloads,
+			download::clear_download_queue,
 			datasets::get_datasets,
 			datasets::set_dataset,
 			datasets::new_dataset,
 			datasets::del_dataset,
 			datasets::export_dataset,
+			datasets::get_dataset_stats,
+			datasets::export_dataset_stats,
+			datasets::edit_dataset_tags,
+			datasets::get_dataset_captions,
 			images::get_images,
 			images::refresh_images,
+			images::query_images,
+			images::find_duplicates,
 			images::get_image_tags,
 			images::copy_post_tags,
 			images::copy_post_image_url,
 			images::get_image_categories,
-			images::generate_image_preview,
 			settings::get_settings,
 			settings::set_settings,
+			subscriptions::get_subscriptions,
+			subscriptions::add_subscription,
+			subscriptions::set_subscription,
+			subscriptions::del_subscription,
+			subscriptions::check_subscriptions,
 		])
+		.register_uri_scheme_protocol(THUMBNAIL_PROTOCOL, images::thumbnail_protocol)
 		.setup(|_app| {
 			#[cfg(debug_assertions)]
 			{
@@ -1,67 +1,7 @@
-use serde::{Deserialize, Serialize};
-use tauri::api::path::download_dir;
-use crate::context::GlobalContext;
+use crate::context::{watch_download_folder, GlobalContext, TauriEvents};
+use philia_core::{Event, EventSink, PhiliaError};
+use philia_core::settings::Settings;
 use tauri::{AppHandle, Manager};
-use std::path::PathBuf;
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Settings {
-	pub dark_mode: bool,
-	pub accent: String,
-
-	pub tag_search_result_limit: u32,
-	pub search_image_list_columns: u32,
-	pub full_resolution_preview: bool,
-
-	pub download_folder: PathBuf,
-	
-	#[serde(default = "Default::default")]
-	pub update_branch: UpdateBranch,
-}
-
-#[derive(Debug, Default, Clone, Serialize, Deserialize)]
-pub enum UpdateBranch {
-	#[default]
-	Stable,
-	Nightly,
-}
-
-impl Settings {
-	pub fn save(&self) -> Result<(), std::io::Error> {
-		let json = serde_json::to_string_pretty(self).unwrap();
-		std::fs::write("./settings.json", json)
-	}
-}
-
-impl Default for Settings {
-	fn default() -> Self {
-		Self {
-			dark_mode: true,
-			accent: "#ffb446".to_string(),
-
-			tag_search_result_limit: 10,
-			search_image_list_columns: 6,
-			full_resolution_preview: false,
-			update_branch: UpdateBranch::Stable,
-
-			download_folder: {
-				fn get_local_download_dir() -> Result<PathBuf, std::io::Error> {
-					std::fs::create_dir_all("./downloads")?;
-					dunce::canonicalize("./downloads")
-				}
-
-				match get_local_download_dir() {
-					Ok(path) => path,
-					Err(_) => {
-						let path = download_dir().expect("Missing download dir").join("Philia");
-						std::fs::create_dir_all(&path).expect("Could not create download dir");
-						path
-					},
-				}
-			},
-		}
-	}
-}
 
 #[tauri::command]
 pub async fn get_settings(handle: AppHandle) -> Settings {
@@ -71,9 +11,31 @@ pub async fn get_settings(handle: AppHandle) -> Settings {
 }
 
 #[tauri::command]
-pub async fn set_settings(settings: Settings, handle: AppHandle) {
-	let state = handle.state::<GlobalContext>();
-	let mut state = state.lock().unwrap();
-	let _ = settings.save();
-	state.settings = settings;
+pub async fn set_settings(settings: Settings, handle: AppHandle) -> Result<(), PhiliaError> {
+	let save = settings.save();
+	let folder_changed = {
+		let state = handle.state::<GlobalContext>();
+		let mut state = state.lock().unwrap();
+		let folder_changed = state.settings.download_folder != settings.download_folder;
+		state.settings = settings;
+		state.update_throttle();
+		state.update_known_hashes();
+		folder_changed
+	};
+
+	if folder_changed {
+		let changes = {
+			let state = handle.state::<GlobalContext>();
+			let mut state = state.lock().unwrap();
+			state.refresh_images()
+		};
+
+		watch_download_folder(&handle);
+		if !changes.is_empty() {
+			TauriEvents(handle.clone()).emit(Event::ImagesChanged(changes));
+		}
+	}
+
+	// The settings are applied even if they could not be persisted.
+	save
 }
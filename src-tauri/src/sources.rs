@@ -1,146 +1,140 @@
-use philia::prelude::{SearchOrder, Post, TagOrder};
-use serde::{Deserialize, Serialize};
-use crate::context::GlobalContext;
-use philia::source::FeatureFlags;
+use philia_core::sources::{FederatedResults, MergeOrder, SourceError, SourceInfo};
+use crate::context::{GlobalContext, TauriEvents};
+use philia::prelude::{SearchOrder, Post};
 use tauri::{AppHandle, Manager};
-use std::collections::HashSet;
-use std::cmp::Ordering;
-
-#[derive(Clone, Serialize, Deserialize)]
-pub struct SourceInfo {
-	name: String,
-	search: bool,
-	tag_list: bool,
-}
+use philia_core::{normalize, sources, PhiliaError};
+use std::collections::HashMap;
 
 #[tauri::command]
 pub async fn get_available_sources(handle: AppHandle) -> Vec<SourceInfo> {
 	let context = handle.state::<GlobalContext>();
 	let context = context.lock().unwrap();
-	let mut sources: Vec<_> = context
-		.sources
-		.iter()
-		.map(|(name, client)| {
-			let flags = client.source().feature_flags();
-			SourceInfo {
-				name: name.clone(),
-				search: (flags & FeatureFlags::SEARCH) != FeatureFlags::NONE,
-				tag_list: (flags & FeatureFlags::TAG_LIST) != FeatureFlags::NONE,
-			}
-		})
-		.collect();
-
-	sources.sort_by(|a, b| a.name.cmp(&b.name));
-	sources
+	context.sources.info()
 }
 
 #[tauri::command]
 pub async fn get_source_tags(source: String, handle: AppHandle) -> Option<Vec<String>> {
 	let context = handle.state::<GlobalContext>();
 	let context = context.lock().unwrap();
-	let tags = context.source_tags.get(&source)?;
-
-	match tags {
-		None => None,
-		Some(tags) => {
-			let mut vec = Vec::from_iter(tags.iter().cloned());
-			vec.sort_by(sort_tags);
-			Some(vec)
-		},
-	}
+	context.sources.tags(&source)
 }
 
 #[tauri::command]
-pub async fn fetch_source_tags(source: String, handle: AppHandle) -> Result<Vec<String>, String> {
+pub async fn fetch_source_tags(
+	source: String, handle: AppHandle,
+) -> Result<Vec<String>, PhiliaError> {
 	let client = {
 		let context = handle.state::<GlobalContext>();
 		let context = context.lock().unwrap();
-		let Some(client) = context.sources.get(&source) else {
-			return Err("Source not found".into());
+		let Some(client) = context.sources.client(&source) else {
+			return Err(source_not_found(&source));
 		};
 
-		client.clone()
+		client
 	};
 
-	let mut all_tags = vec![];
-	for i in 1..25 {
-		let tags = client
-			.get_tags_async(i, 1000, TagOrder::Count)
-			.await
-			.map_err(|e| e.to_string())?;
-		if tags.is_empty() {
-			break;
-		}
-		all_tags.extend(tags.into_iter().map(|tag| tag.name));
-		let _ = handle.emit_all("fetch_source_tags_count", all_tags.len());
-	}
+	let all_tags = sources::fetch_tags(&client, &TauriEvents(handle.clone())).await?;
 
 	let context = handle.state::<GlobalContext>();
 	let mut context = context.lock().unwrap();
-	let tags = context.source_tags.get_mut(&source).unwrap();
-
-	*tags = Some(HashSet::from_iter(all_tags.iter().cloned()));
-
-	all_tags.sort_by(sort_tags);
+	context.sources.set_tags(&source, &all_tags);
 	Ok(all_tags)
 }
 
+/// Downloads the tag aliases and implications of `source`, then normalizes the images again.
 #[tauri::command]
-pub async fn search(
-	source: String, page: u32, limit: u32, order: SearchOrder, tags: Vec<String>, handle: AppHandle,
-) -> Result<(Vec<Post>, Vec<String>), String> {
-	let client = {
+pub async fn fetch_tag_tables(source: String, handle: AppHandle) -> Result<(), PhiliaError> {
+	let (urls, throttle) = {
 		let context = handle.state::<GlobalContext>();
 		let context = context.lock().unwrap();
-		let Some(client) = context.sources.get(&source) else {
-			return Err("Source not found".into());
+		let Some(urls) = context.sources.tag_tables(&source).cloned() else {
+			return Err(source_not_found(&source));
 		};
 
-		client.clone()
+		(urls, context.throttle.clone())
 	};
 
-	let mut include = vec![];
-	let mut exclude = vec![];
-	for tag in tags.into_iter() {
-		if tag.starts_with('-') {
-			exclude.push((&tag[1..]).to_string());
-		} else {
-			include.push(tag);
-		}
-	}
+	normalize::fetch_tag_tables(&source, &urls, &throttle).await?;
 
-	let posts = client
-		.search_async(page, limit, order, include.into_iter(), exclude.into_iter())
-		.await
-		.map_err(|e| e.to_string())?;
+	let context = handle.state::<GlobalContext>();
+	let mut context = context.lock().unwrap();
+	context.refresh_tag_tables();
+	Ok(())
+}
 
+/// Reloads the tag aliases and implications from `./tags` and normalizes the images again.
+#[tauri::command]
+pub async fn reload_tag_tables(handle: AppHandle) {
 	let context = handle.state::<GlobalContext>();
 	let mut context = context.lock().unwrap();
-	let Some(tags) = context.source_tags.get_mut(&source) else {
-		return Ok((posts, vec![]));
-	};
+	context.refresh_tag_tables();
+}
+
+#[tauri::command]
+pub async fn search(
+	source: String, page: u32, limit: u32, order: SearchOrder, tags: Vec<String>, handle: AppHandle,
+) -> Result<(Vec<Post>, Vec<String>), PhiliaError> {
+	let client = {
+		let context = handle.state::<GlobalContext>();
+		let context = context.lock().unwrap();
+		let Some(client) = context.sources.client(&source) else {
+			return Err(source_not_found(&source));
+		};
 
-	let tags = match tags {
-		Some(tags) => tags,
-		None => tags.insert(HashSet::default()),
+		client
 	};
 
-	tags.extend(posts.iter().map(|p| p.tags.iter().map(str::to_string)).flatten());
-	let mut tags = Vec::from_iter(tags.iter().cloned());
-	tags.sort_by(sort_tags);
+	let posts = sources::search(&client, page, limit, order, tags).await?;
+
+	let context = handle.state::<GlobalContext>();
+	let mut context = context.lock().unwrap();
+	let tags = context.sources.collect_tags(&source, &posts);
 	Ok((posts, tags))
 }
 
-fn sort_tags(a: &String, b: &String) -> Ordering {
-	let a = match a.chars().next().unwrap_or_default().is_alphabetic() {
-		true => a.as_str(),
-		false => "z",
-	};
+/// Searches several sources concurrently and merges their results.
+/// Unknown or failing sources are listed in `FederatedResults::errors`.
+#[tauri::command]
+pub async fn federated_search(
+	source_names: Vec<String>, page: u32, limit: u32, order: SearchOrder, tags: Vec<String>,
+	merge: Option<MergeOrder>, handle: AppHandle,
+) -> FederatedResults {
+	let (clients, missing) = {
+		let context = handle.state::<GlobalContext>();
+		let context = context.lock().unwrap();
+		let mut clients = vec![];
+		let mut missing = vec![];
+		for source in source_names {
+			match context.sources.client(&source) {
+				Some(client) => clients.push((source, client)),
+				None => missing.push(source),
+			}
+		}
 
-	let b = match b.chars().next().unwrap_or_default().is_alphabetic() {
-		true => b.as_str(),
-		false => "z",
+		(clients, missing)
 	};
 
-	a.cmp(b)
+	let merge = merge.unwrap_or_default();
+	let mut results = sources::federated_search(clients, page, limit, order, tags, merge).await;
+	for source in missing {
+		let error = source_not_found(&source);
+		results.errors.push(SourceError { source, error });
+	}
+
+	let context = handle.state::<GlobalContext>();
+	let mut context = context.lock().unwrap();
+	let mut by_source: HashMap<&str, Vec<Post>> = HashMap::new();
+	for post in &results.posts {
+		by_source.entry(&post.source).or_default().push(post.clone());
+	}
+
+	for (source, posts) in by_source {
+		context.sources.collect_tags(source, &posts);
+	}
+
+	results
+}
+
+fn source_not_found(source: &str) -> PhiliaError {
+	PhiliaError::NotFound(format!("Source {:?} does not exist", source))
 }
@@ -0,0 +1,85 @@
+use philia_core::subscriptions::{self, Subscription, SubscriptionCheck};
+use crate::context::{GlobalContext, TauriEvents};
+use philia_core::PhiliaError;
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Manager};
+
+/// How often the periodic task looks at `subscription_check_interval`.
+const POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+#[tauri::command]
+pub async fn get_subscriptions(handle: AppHandle) -> Vec<Subscription> {
+	let context = handle.state::<GlobalContext>();
+	let context = context.lock().unwrap();
+	context.subscriptions.list.clone()
+}
+
+#[tauri::command]
+pub async fn add_subscription(
+	subscription: Subscription, handle: AppHandle,
+) -> Result<Vec<Subscription>, PhiliaError> {
+	let context = handle.state::<GlobalContext>();
+	let mut context = context.lock().unwrap();
+	context.subscriptions.add(subscription)?;
+	Ok(context.subscriptions.list.clone())
+}
+
+#[tauri::command]
+pub async fn set_subscription(
+	name: String, subscription: Subscription, handle: AppHandle,
+) -> Result<Vec<Subscription>, PhiliaError> {
+	let context = handle.state::<GlobalContext>();
+	let mut context = context.lock().unwrap();
+	context.subscriptions.set(&name, subscription)?;
+	Ok(context.subscriptions.list.clone())
+}
+
+#[tauri::command]
+pub async fn del_subscription(
+	name: String, handle: AppHandle,
+) -> Result<Vec<Subscription>, PhiliaError> {
+	let context = handle.state::<GlobalContext>();
+	let mut context = context.lock().unwrap();
+	context.subscriptions.remove(&name)?;
+	Ok(context.subscriptions.list.clone())
+}
+
+#[tauri::command]
+pub async fn check_subscriptions(handle: AppHandle) -> Vec<SubscriptionCheck> {
+	check_all(&handle).await
+}
+
+/// Checks the subscriptions every `subscription_check_interval` minutes, while it is non-zero.
+pub async fn check_periodically(handle: AppHandle) {
+	let mut last_check = Instant::now();
+	loop {
+		tokio::time::sleep(POLL_INTERVAL).await;
+
+		let interval = {
+			let context = handle.state::<GlobalContext>();
+			let context = context.lock().unwrap();
+			context.settings.subscription_check_interval
+		};
+
+		let interval = Duration::from_secs(interval as u64 * 60);
+		if interval.is_zero() || last_check.elapsed() < interval {
+			continue;
+		}
+
+		last_check = Instant::now();
+		let checks = check_all(&handle).await;
+		let new_posts: usize = checks.iter().map(|check| check.new_posts).sum();
+		if new_posts != 0 {
+			let id = &handle.config().tauri.bundle.identifier;
+			let _ = tauri::api::notification::Notification::new(id)
+				.title("New posts found")
+				.body(format!("Queued {} new posts from your subscriptions.", new_posts))
+				.show();
+		}
+	}
+}
+
+async fn check_all(handle: &AppHandle) -> Vec<SubscriptionCheck> {
+	let context = handle.state::<GlobalContext>();
+	subscriptions::check_all(&context, &TauriEvents(handle.clone())).await
+}
@@ -1,4 +1,4 @@
-use crate::settings::{Settings, UpdateBranch};
+use philia_core::settings::{Settings, UpdateBranch};
 use serde::Deserialize;
 use tempfile::TempDir;
 use std::error::Error;
@@ -4,6 +4,7 @@ import {Dataset, Datasets} from "./tabs/datasets";
 import React, {useEffect, useState} from "react";
 import {SavedImages} from "./bindings/images";
 import {Search, Source} from "./tabs/search";
+import {listen} from "@tauri-apps/api/event";
 import {invoke} from "@tauri-apps/api";
 import {Images} from "./tabs/images";
 import {Drawer} from "./drawer";
@@ -23,6 +24,10 @@ export default function App() {
         invoke<Settings>("get_settings").then(set_settings);
         invoke<Dataset[]>("get_datasets").then(set_datasets);
         SavedImages.fetch().then(set_images);
+
+        // Files added or removed outside of the app are picked up by the download folder watcher.
+        const unlisten = listen("images_changed", () => SavedImages.fetch().then(set_images));
+        return () => { unlisten.then(f => f()); };
     }, []);
     
     useEffect(() => {
@@ -0,0 +1,56 @@
+import {invoke} from "@tauri-apps/api";
+
+export interface TagFrequency {
+    tag: string,
+    category: string | null,
+    /** Number of images tagged with `tag`. */
+    count: number,
+    /** Fraction of the images tagged with `tag`. */
+    frequency: number,
+}
+
+export interface CategoryFrequency {
+    category: string,
+    count: number,
+    /** Number of distinct tags of the category. */
+    tags: number,
+}
+
+export interface TagPair {
+    first: string,
+    second: string,
+    count: number,
+}
+
+export interface DatasetStats {
+    images: number,
+    tags: TagFrequency[],
+    categories: CategoryFrequency[],
+    co_occurrence: TagPair[],
+}
+
+export function get_dataset_stats(index: number, top?: number): Promise<DatasetStats> {
+    return invoke("get_dataset_stats", {index, top: top ?? null});
+}
+
+export function export_dataset_stats(index: number, path: string): Promise<void> {
+    return invoke("export_dataset_stats", {index, path});
+}
+
+/** Edits of the caption of a single image, applied on export. */
+export interface CaptionOverride {
+    added_tags: string[],
+    removed_tags: string[],
+    replaced_tags: {[tag: string]: string},
+    /** Written verbatim instead of the generated caption when set. */
+    caption: string | null,
+}
+
+export const EMPTY_OVERRIDE: CaptionOverride = {
+    added_tags: [],
+    removed_tags: [],
+    replaced_tags: {},
+    caption: null,
+}
+
+export type TagEdit = {Add: string} | {Remove: string} | {Replace: [string, string]};
@@ -0,0 +1,46 @@
+import {Post} from "../tabs/search";
+import {PhiliaError} from "./errors";
+
+export type DownloadErrorKind =
+    "MissingUrl" | "Network" | "HttpStatus" | "ServerUnavailable" | "Decode" | "Io" | "Internal";
+
+export interface DownloadReport {
+    key: string,
+    status: "Saved" | "Exists" | "Duplicate" | "Failed",
+    error_kind: DownloadErrorKind | null,
+    message: string | null,
+    http_status: number | null,
+    bytes: number,
+    duration_ms: number,
+    path: string | null,
+}
+
+export interface QueueItem {
+    key: string,
+    post: Post,
+    status: "Pending" | "Downloading" | "Completed" | "Failed" | "Cancelled" | "Skipped",
+    retries: number,
+    error: string | null,
+    file_path: string | null,
+    report: DownloadReport | null,
+}
+
+export interface BulkDownloadRequest {
+    source: string,
+    tags: string[],
+    order: "Newest" | "Oldest" | "MostLiked" | "LeastLiked",
+    /** Zero queues every result. */
+    max_posts?: number,
+    /** Zero fetches pages until the results run out. */
+    max_pages?: number,
+    page_size?: number,
+}
+
+export interface BulkDownloadProgress {
+    id: number,
+    pages: number,
+    queued: number,
+    finished: boolean,
+    cancelled: boolean,
+    error: PhiliaError | null,
+}
@@ -0,0 +1,26 @@
+export type PhiliaErrorKind = "Io" | "Network" | "Decode" | "SourceScript" | "NotFound" | "Validation";
+
+/** The error returned by failed commands. */
+export interface PhiliaError {
+    kind: PhiliaErrorKind,
+    message: string,
+}
+
+export function is_philia_error(error: unknown): error is PhiliaError {
+    return typeof error === "object" && error !== null && "kind" in error && "message" in error;
+}
+
+export function describe_error(error: unknown): string {
+    if(!is_philia_error(error)) {
+        return String(error);
+    }
+
+    switch(error.kind) {
+        case "Io": return `Could not access a file: ${error.message}`;
+        case "Network": return `Network error: ${error.message}`;
+        case "Decode": return `Invalid data: ${error.message}`;
+        case "SourceScript": return `The source script failed: ${error.message}`;
+        case "NotFound": return `Not found: ${error.message}`;
+        case "Validation": return `Invalid input: ${error.message}`;
+    }
+}
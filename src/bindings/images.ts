@@ -4,7 +4,33 @@ import {invoke} from "@tauri-apps/api";
 export interface SavedImage {
     info: Post,
     file_path: string,
-    preview_data: string,
+    thumbnail: string,
+    media: "Image" | "Animation" | "Video",
+}
+
+export interface ImageQuery {
+    tags?: string[],
+    sources?: string[],
+    ratings?: string[],
+    min_score?: number | null,
+    max_score?: number | null,
+    min_dimensions?: [number, number] | null,
+    max_dimensions?: [number, number] | null,
+    in_dataset?: string | null,
+    not_in_dataset?: string | null,
+    sort?: "Path" | "Id" | "Score" | "Resolution" | "TagCount",
+    descending?: boolean,
+    page?: number,
+    limit?: number,
+}
+
+export interface ImagePage {
+    images: SavedImage[],
+    total: number,
+}
+
+export async function query_images(query: ImageQuery): Promise<ImagePage> {
+    return await invoke<ImagePage>("query_images", {query});
 }
 
 export class SavedImages {
@@ -0,0 +1,33 @@
+import {invoke} from "@tauri-apps/api";
+import {PhiliaError} from "./errors";
+
+export interface Subscription {
+    name: string,
+    source: string,
+    tags: string[],
+    order: "Newest" | "Oldest" | "MostLiked" | "LeastLiked",
+    /** The highest post ID seen so far; only newer posts are downloaded. */
+    last_seen_id: number | null,
+}
+
+export interface SubscriptionCheck {
+    name: string,
+    new_posts: number,
+    error: PhiliaError | null,
+}
+
+export function get_subscriptions(): Promise<Subscription[]> {
+    return invoke("get_subscriptions");
+}
+
+export function add_subscription(subscription: Subscription): Promise<Subscription[]> {
+    return invoke("add_subscription", {subscription});
+}
+
+export function del_subscription(name: string): Promise<Subscription[]> {
+    return invoke("del_subscription", {name});
+}
+
+export function check_subscriptions(): Promise<SubscriptionCheck[]> {
+    return invoke("check_subscriptions");
+}
@@ -8,9 +8,11 @@ import {
     TextField,
     Tooltip
 } from "@mui/material";
-import {invoke} from "@tauri-apps/api";
 import {SavedImage} from "../bindings/images";
 
+const MAX_THUMBNAIL_RETRIES = 20;
+const THUMBNAIL_RETRY_DELAY = 500;
+
 interface ImageProps {
     image: SavedImage,
     actionIcon?: ReactElement,
@@ -21,37 +23,34 @@ interface ImageProps {
     loadWhenVisible?: boolean,
 }
 
-enum LoadingStage {
-    NotLoaded,
-    Loading,
-    Loaded,
-    Error,
-}
-
 export function SavedImagePreview(props: ImageProps): ReactElement {
     const path = props.image.file_path;
-    const preview = props.image.preview_data;
+    const preview = `${props.image.thumbnail}?size=256`;
+    const full_size = `${props.image.thumbnail}?size=${props.previewSize || 512}`;
 
-    const [src, set_src] = useState(preview);
-    const [stage, set_stage] = useState(LoadingStage.NotLoaded);
+    // Thumbnails are served from the on-disk cache; larger ones are only requested once visible.
+    const [load_full_size, set_load_full_size] = useState(!props.loadWhenVisible);
     const [visible, ref] = props.loadWhenVisible
         ? useVisibility<HTMLImageElement>()
         : [false, null];
 
     useEffect(() => {
-        if(stage === LoadingStage.Loading && visible)
-            invoke<string>("generate_image_preview", {path, size: props.previewSize || 512})
-                .then(src => { set_src(src); set_stage(LoadingStage.Loaded); })
-                .catch(err => { console.error(err); set_stage(LoadingStage.Error); });
-    }, [stage, visible]);
+        if(visible) set_load_full_size(true);
+    }, [visible]);
+
+    // Missing thumbnails are generated in the background and fail to load until they are ready.
+    const [retries, set_retries] = useState(0);
+    const retry = () => {
+        if(retries < MAX_THUMBNAIL_RETRIES)
+            setTimeout(() => set_retries(retries + 1), THUMBNAIL_RETRY_DELAY);
+    };
+
+    const src = (load_full_size ? full_size : preview) + (retries ? `&retry=${retries}` : "");
 
     return (
         <ImageListItem key={path} className={props.scaleOnHover ? "hover_scale" : ""}>
             {props.topElement}
-            <img ref={ref} src={src} alt={path} loading="lazy" onLoad={() => {
-                if(stage === LoadingStage.NotLoaded && props.loadWhenVisible)
-                    set_stage(LoadingStage.Loading);
-            }}/>
+            <img ref={ref} src={src} alt={path} loading="lazy" onError={retry}/>
             <ImageListItemBar
                 title={path.split("/").pop()}
                 actionIcon={props.actionIcon}
@@ -69,6 +68,9 @@ interface ListProps {
     updateDependencies?: any[]
     loadWhenVisible?: boolean,
     fixedPageButtons?: boolean,
+    // When set, `images` only holds the current page and pages are fetched through `onPageChange`.
+    pageCount?: number,
+    onPageChange?: (page: number) => void,
     container?: MutableRefObject<any>
     
     actionIcon?: (image: SavedImage) => ReactElement,
@@ -84,16 +86,20 @@ export function PaginatedImageList(props: ListProps): ReactElement {
     const [page, set_page] = useState(0);
     const [target_page, set_target_page] = useState(0);
     
+    const paged = props.pageCount !== undefined;
+
     useEffect(() => {
+        if(paged) return;
         set_page(0);
         set_target_page(0);
     }, [props.images]);
     
     useEffect(() => {
         props.container?.current?.scrollTo(SCROLL_TOP);
+        props.onPageChange?.(page);
     }, [page])
 
-    const offset = page * props.imagesPerPage;
+    const offset = paged ? 0 : page * props.imagesPerPage;
     const images = [] as ReactElement[];
     for(let i = 0; i < props.imagesPerPage; i++) {
         const image = props.images[i + offset];
@@ -110,7 +116,7 @@ export function PaginatedImageList(props: ListProps): ReactElement {
     const buttons = [] as ReactElement[];
     {
         let middle_added = false;
-        const count = Math.ceil(props.images.length / props.imagesPerPage);
+        const count = paged ? props.pageCount! : Math.ceil(props.images.length / props.imagesPerPage);
         
         for(let i = 0; i < count; i++) {
             if(count > 6 && i >= 3 && i < count - 3) {
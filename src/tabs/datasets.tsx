@@ -11,7 +11,7 @@ import {
     Typography
 } from "@mui/material";
 import {
-    AddBox, Article,
+    AddBox, Article, BarChart, EditNote,
     Check,
     Close, CropDin,
     DataArray,
@@ -20,15 +20,48 @@ import {
     DoNotDisturb,
     Edit,
     ExpandMore,
-    FileUpload, Image, PhotoSizeSelectLarge,
-    Remove, Repeat, SelectAll, Textsms
+    FileUpload, Image, Movie, PhotoSizeSelectLarge, AspectRatio, Crop, FormatColorFill,
+    Remove, Repeat, SelectAll, Textsms, Sort, Category, Shuffle, PushPin, FormatListNumbered
 } from "@mui/icons-material";
 import {invoke} from "@tauri-apps/api";
 import {convertFileSrc} from "@tauri-apps/api/tauri";
-import {open} from "@tauri-apps/api/dialog";
+import {message, open} from "@tauri-apps/api/dialog";
+import {describe_error} from "../bindings/errors";
 import {Settings} from "./settings";
 import {PaginatedImageList} from "../components/images";
 import {SavedImage, SavedImages} from "../bindings/images";
+import {
+    CaptionOverride, DatasetStats, EMPTY_OVERRIDE, export_dataset_stats, get_dataset_stats, TagEdit
+} from "../bindings/datasets";
+
+export interface CaptionSettings {
+    /** Comma separated parts such as "{keyword}, {rating}, {character}, {general}". */
+    template: string,
+    order: "Source" | "Category" | "Frequency" | "Alphabetical" | "Shuffle",
+    category_order: string[],
+    seed: number,
+    keep_tokens: number,
+    max_tags: number,
+}
+
+/** Aspect-ratio bucketing. Zero values use 1024x1024 pixels, steps of 64 and a maximum ratio of 2. */
+export interface BucketSettings {
+    enabled: boolean,
+    area: number,
+    step: number,
+    max_aspect_ratio: number,
+}
+
+/** Options of the kohya-ss dataset config. Zero values are left to the trainer's defaults. */
+export interface KohyaSettings {
+    resolution: number,
+    batch_size: number,
+    enable_bucket: boolean,
+    min_bucket_reso: number,
+    max_bucket_reso: number,
+    bucket_reso_steps: number,
+    shuffle_caption: boolean,
+}
 
 export interface Dataset {
     name: string,
@@ -45,12 +78,32 @@ export interface Dataset {
             apply_letterboxing: boolean,
             target_format: "Png" | "Jpg" | "Bmp" | "Gif" | "Qoi" | "WebP",
             resize: [number, number],
+            frame_interval: number,
+            bucketing: BucketSettings,
+            letterbox: {
+                fill: "Solid" | "BlurredEdges" | "Transparent",
+                color: [number, number, number],
+            },
+            crop: {
+                mode: "Stretch" | "Center" | "Entropy" | "Tags",
+                top_tags: string[],
+                bottom_tags: string[],
+            },
         },
         training: {
             keyword: string,
             repetitions: number,
+        },
+        caption: CaptionSettings,
+        export: {
+            tag_stats: boolean,
+            format: "Folder" | "Kohya" | "ImageFolder" | "WebDataset",
+            kohya: KohyaSettings,
+            shard_size: number,
         }
-    }
+    },
+    /** Caption edits, by image path. */
+    overrides: {[path: string]: CaptionOverride},
 }
 
 interface Props {
@@ -63,6 +116,7 @@ interface Props {
 export function Datasets(props: Props): ReactElement {
     const [edit, set_edit] = useState(-1);
     const [to_delete, set_to_delete] = useState(-1);
+    const [stats, set_stats] = useState(-1);
     const [exporting, set_exporting] = useState(null as string | null);
     const [columns, set_columns] = useState(Math.floor(window.innerWidth / 270));
     
@@ -79,7 +133,7 @@ export function Datasets(props: Props): ReactElement {
     return (
         <Stack padding="0.5em" style={{overflow: "auto", width: "fit-content"}} marginX="auto">
             <ImageList cols={columns} gap={6} style={{overflow: "hidden"}}>
-                {props.datasets.map((dataset, i) => DatasetPreview(i, dataset, set_edit, set_to_delete, set_exporting))}
+                {props.datasets.map((dataset, i) => DatasetPreview(i, dataset, set_edit, set_to_delete, set_exporting, set_stats))}
                 <ImageListItem style={PREVIEW_STYLE} onClick={new_dataset}>
                     <Stack alignItems="center" justifyItems="center" className="hover_scale">
                         <AddBox style={{fontSize: 256}}/>
@@ -92,6 +146,8 @@ export function Datasets(props: Props): ReactElement {
                 index={edit} set_edit={set_edit} saved_images={props.saved_images}
                 datasets={props.datasets} set_datasets={props.set_datasets}
             />
+
+            <DatasetStatsDialog index={stats} datasets={props.datasets} close={() => set_stats(-1)}/>
             
             <Dialog open={exporting !== null} maxWidth="sm" fullWidth>
                 <DialogTitle>
@@ -145,6 +201,7 @@ function DatasetPreview(
     set_edit: (index: number) => void,
     set_to_delete: (index: number) => void,
     set_exporting: (name: string | null) => void,
+    set_stats: (index: number) => void,
 ): ReactElement {
     const export_dataset = async (index: number) => {
         try {
@@ -158,6 +215,8 @@ function DatasetPreview(
                 set_exporting(dataset.name);
                 await invoke("export_dataset", {index, path});
             }
+        } catch (e) {
+            await message(describe_error(e), {title: "Could not export dataset", type: "error"});
         } finally {
             set_exporting(null);
         }
@@ -178,6 +237,9 @@ function DatasetPreview(
                         <IconButton onClick={() => export_dataset(i)}>
                             <FileUpload/>
                         </IconButton>
+                        <IconButton onClick={() => set_stats(i)}>
+                            <BarChart/>
+                        </IconButton>
                         <IconButton onClick={() => set_edit(i)}>
                             <Edit/>
                         </IconButton>
@@ -214,12 +276,51 @@ const EMPTY_DATASET: Dataset = {
             apply_letterboxing: false,
             target_format: "Png",
             resize: [0, 0],
+            frame_interval: 0,
+            bucketing: {
+                enabled: false,
+                area: 0,
+                step: 0,
+                max_aspect_ratio: 0,
+            },
+            letterbox: {
+                fill: "Solid",
+                color: [0, 0, 0],
+            },
+            crop: {
+                mode: "Stretch",
+                top_tags: [],
+                bottom_tags: [],
+            },
         },
         training: {
             keyword: "",
             repetitions: 0,
+        },
+        caption: {
+            template: "",
+            order: "Source",
+            category_order: [],
+            seed: 0,
+            keep_tokens: 0,
+            max_tags: 0,
+        },
+        export: {
+            tag_stats: false,
+            format: "Folder",
+            kohya: {
+                resolution: 0,
+                batch_size: 0,
+                enable_bucket: false,
+                min_bucket_reso: 0,
+                max_bucket_reso: 0,
+                bucket_reso_steps: 0,
+                shuffle_caption: false,
+            },
+            shard_size: 0,
         }
-    }
+    },
+    overrides: {},
 }
 
 function EditDatasetDialog(props: EditProps): ReactElement {
@@ -237,9 +338,24 @@ function EditDatasetDialog(props: EditProps): ReactElement {
     const [resize, set_resize] = useState(dataset.settings.image.resize);
     const [format, set_format] = useState(dataset.settings.image.target_format);
     const [apply_letterboxing, set_apply_letterboxing] = useState(dataset.settings.image.apply_letterboxing);
+    const [frame_interval, set_frame_interval] = useState(dataset.settings.image.frame_interval);
+    const [bucketing, set_bucketing] = useState(dataset.settings.image.bucketing);
+    const [letterbox, set_letterbox] = useState(dataset.settings.image.letterbox);
+    const [crop, set_crop] = useState(dataset.settings.image.crop);
 
     const [keyword, set_keyword] = useState(dataset.settings.training.keyword);
     const [repetitions, set_repetitions] = useState(dataset.settings.training.repetitions);
+
+    const [caption, set_caption] = useState(dataset.settings.caption);
+
+    const [tag_stats, set_tag_stats] = useState(dataset.settings.export.tag_stats);
+    const [export_format, set_export_format] = useState(dataset.settings.export.format);
+    const [kohya, set_kohya] = useState(dataset.settings.export.kohya);
+    const [shard_size, set_shard_size] = useState(dataset.settings.export.shard_size);
+
+    const [overrides, set_overrides] = useState(dataset.overrides);
+    const [edit_caption, set_edit_caption] = useState(null as string | null);
+    const [bulk_edit, set_bulk_edit] = useState(false);
     
     const [manage_images, set_manage_images] = useState(false);
     
@@ -269,9 +385,22 @@ function EditDatasetDialog(props: EditProps): ReactElement {
         set_resize(dataset.settings.image.resize);
         set_format(dataset.settings.image.target_format);
         set_apply_letterboxing(dataset.settings.image.apply_letterboxing);
+        set_frame_interval(dataset.settings.image.frame_interval);
+        set_bucketing(dataset.settings.image.bucketing);
+        set_letterbox(dataset.settings.image.letterbox);
+        set_crop(dataset.settings.image.crop);
         
         set_keyword(dataset.settings.training.keyword);
         set_repetitions(dataset.settings.training.repetitions);
+
+        set_caption(dataset.settings.caption);
+
+        set_tag_stats(dataset.settings.export.tag_stats);
+        set_export_format(dataset.settings.export.format);
+        set_kohya(dataset.settings.export.kohya);
+        set_shard_size(dataset.settings.export.shard_size);
+
+        set_overrides(dataset.overrides);
     }, [dataset])
     
     useEffect(() => {
@@ -284,36 +413,59 @@ function EditDatasetDialog(props: EditProps): ReactElement {
     }, [ignore_categories]);
 
     const close = () => props.set_edit(-1);
-    const apply = async () => {
-        const datasets = await invoke<Dataset[]>("set_dataset", {
-            index: props.index,
-            dataset: {
-                name,
-                images,
-                thumbnail,
-                settings: {
-                    tags: {
-                        remove_underscores,
-                        escape_parentheses,
-                        ignore_categories,
-                        ignore_tags,
-                    },
-                    image: {
-                        resize,
-                        target_format: format,
-                        apply_letterboxing,
-                    },
-                    training: {
-                        keyword,
-                        repetitions
-                    }
-                }
+
+    /** The dataset as currently edited. */
+    const current = (): Dataset => ({
+        ...dataset,
+        name,
+        images,
+        thumbnail,
+        settings: {
+            ...dataset.settings,
+            tags: {
+                ...dataset.settings.tags,
+                remove_underscores,
+                escape_parentheses,
+                ignore_categories,
+                ignore_tags,
+            },
+            image: {
+                ...dataset.settings.image,
+                resize,
+                target_format: format,
+                apply_letterboxing,
+                frame_interval,
+                bucketing,
+                letterbox,
+                crop,
+            },
+            training: {
+                ...dataset.settings.training,
+                keyword,
+                repetitions
+            },
+            caption,
+            export: {
+                ...dataset.settings.export,
+                tag_stats,
+                format: export_format,
+                kohya,
+                shard_size,
             }
-        });
+        },
+        overrides,
+    });
 
+    const apply = async () => {
+        const datasets = await invoke<Dataset[]>("set_dataset", {index: props.index, dataset: current()});
         props.set_datasets(datasets);
         close();
     }
+
+    const apply_tag_edit = async (edit: TagEdit) => {
+        await invoke<Dataset[]>("set_dataset", {index: props.index, dataset: current()});
+        props.set_datasets(await invoke<Dataset[]>("edit_dataset_tags", {index: props.index, images: [], edit}));
+    };
     
     const container = useRef(null as any);
     
@@ -365,16 +517,26 @@ function EditDatasetDialog(props: EditProps): ReactElement {
                                     images={images_to_show}
                                     imagesPerPage={24}
                                     container={container}
-                                    updateDependencies={[thumbnail]}
+                                    updateDependencies={[thumbnail, overrides]}
                                     actionIcon={image => (
-                                        <IconButton
-                                            disabled={thumbnail === image.file_path}
-                                            onClick={() => set_thumbnail(image.file_path)}
-                                        >
-                                            <Image/>
-                                        </IconButton>
+                                        <Stack direction="row">
+                                            <IconButton onClick={() => set_edit_caption(image.file_path)}>
+                                                <EditNote color={overrides[image.file_path] ? "primary" : undefined}/>
+                                            </IconButton>
+                                            <IconButton
+                                                disabled={thumbnail === image.file_path}
+                                                onClick={() => set_thumbnail(image.file_path)}
+                                            >
+                                                <Image/>
+                                            </IconButton>
+                                        </Stack>
                                     )}
                                 />
+                                <CaptionOverrideDialog
+                                    path={edit_caption} tags={tags}
+                                    dataset={current} overrides={overrides}
+                                    set_overrides={set_overrides} close={() => set_edit_caption(null)}
+                                />
                                 <ManageImagesDialog
                                     settings={props.settings}
                                     open={manage_images} set_open={set_manage_images}
@@ -389,12 +551,22 @@ function EditDatasetDialog(props: EditProps): ReactElement {
                             <AccordionSummary expandIcon={<ExpandMore/>}>
                                 <Stack direction="row" alignItems="center" width="100%">
                                     <Typography width="84%">Tag settings</Typography>
-                                    {/*<Button variant="contained" disabled>*/}
-                                    {/*    Bulk edit tags*/}
-                                    {/*</Button>*/}
+                                    <Button
+                                        variant="contained"
+                                        onClick={e => {
+                                            set_bulk_edit(true);
+                                            e.stopPropagation();
+                                        }}
+                                    >
+                                        Bulk edit tags
+                                    </Button>
                                 </Stack>
                             </AccordionSummary>
                             <AccordionDetails>
+                                <BulkEditDialog
+                                    open={bulk_edit} tags={tags}
+                                    apply={apply_tag_edit} close={() => set_bulk_edit(false)}
+                                />
                                 <List>
                                     <ListItem>
                                         <ListItemIcon><Remove color="primary"/></ListItemIcon>
@@ -470,12 +642,47 @@ function EditDatasetDialog(props: EditProps): ReactElement {
                                         <ListItemIcon><CropDin color="primary"/></ListItemIcon>
                                         <ListItemText primary="Apply letterboxing"/>
                                         <Switch
+                                            disabled={bucketing.enabled}
                                             checked={apply_letterboxing} 
                                             onChange={(_, v) => {
                                                 set_apply_letterboxing(v)
                                             }}
                                         />
                                     </ListItem>
+                                    {
+                                        apply_letterboxing && !bucketing.enabled && (
+                                            <ListItem>
+                                                <ListItemIcon><FormatColorFill color="primary"/></ListItemIcon>
+                                                <ListItemText primary="Letterbox fill"/>
+                                                <Stack direction="row" spacing={4} alignItems="center">
+                                                    <TextField
+                                                        select
+                                                        style={{minWidth: 250}}
+                                                        size="small" variant="standard" label="Fill"
+                                                        value={letterbox.fill} onChange={(e) => {
+                                                            set_letterbox({...letterbox, fill: e.target.value as any})
+                                                        }}
+                                                    >
+                                                        <MenuItem value={"Solid"}>Solid color</MenuItem>
+                                                        <MenuItem value={"BlurredEdges"}>Blurred edges</MenuItem>
+                                                        <MenuItem value={"Transparent"}>Transparent</MenuItem>
+                                                    </TextField>
+                                                    <TextField
+                                                        style={{minWidth: 250}}
+                                                        type="color" label="Color"
+                                                        size="small" variant="standard"
+                                                        disabled={letterbox.fill !== "Solid"}
+                                                        value={"#" + letterbox.color.map(c => c.toString(16).padStart(2, "0")).join("")}
+                                                        onChange={(e) => {
+                                                            const hex = e.target.value.slice(1);
+                                                            const color = [0, 2, 4].map(i => parseInt(hex.slice(i, i + 2), 16));
+                                                            set_letterbox({...letterbox, color: color as [number, number, number]})
+                                                        }}
+                                                    />
+                                                </Stack>
+                                            </ListItem>
+                                        )
+                                    }
 
                                     <ListItem>
                                         <ListItemIcon><PhotoSizeSelectLarge color="primary"/></ListItemIcon>
@@ -485,6 +692,7 @@ function EditDatasetDialog(props: EditProps): ReactElement {
                                                 style={{minWidth: 250}}
                                                 size="small" variant="standard" label="Width"
                                                 type="number" inputProps={{min: 0, max: 2048, step: 64}}
+                                                disabled={bucketing.enabled}
                                                 value={resize[0]} onChange={(e) => {
                                                     set_resize([+e.target.value, resize[1]])
                                                 }}
@@ -493,6 +701,7 @@ function EditDatasetDialog(props: EditProps): ReactElement {
                                                 style={{minWidth: 250}}
                                                 size="small" variant="standard" label="Height"
                                                 type="number" inputProps={{min: 0, max: 2048, step: 64}}
+                                                disabled={bucketing.enabled}
                                                 value={resize[1]} onChange={(e) => {
                                                     set_resize([resize[0], +e.target.value])
                                                 }}
@@ -500,6 +709,104 @@ function EditDatasetDialog(props: EditProps): ReactElement {
                                         </Stack>
                                     </ListItem>
 
+                                    <ListItem>
+                                        <ListItemIcon><Crop color="primary"/></ListItemIcon>
+                                        <ListItemText
+                                            primary="Crop mode"
+                                            secondary="How images are fitted to a resize or bucket size with a different aspect ratio"
+                                        />
+                                        <TextField
+                                            select
+                                            style={{minWidth: 532}}
+                                            size="small" variant="standard" label="Mode"
+                                            value={crop.mode} onChange={(e) => {
+                                                set_crop({...crop, mode: e.target.value as any})
+                                            }}
+                                        >
+                                            <MenuItem value={"Stretch"}>Stretch (center crop for buckets)</MenuItem>
+                                            <MenuItem value={"Center"}>Center crop</MenuItem>
+                                            <MenuItem value={"Entropy"}>Keep the most detailed area</MenuItem>
+                                            <MenuItem value={"Tags"}>Keep the top or bottom according to tags</MenuItem>
+                                        </TextField>
+                                    </ListItem>
+                                    {
+                                        crop.mode === "Tags" && (
+                                            <List>
+                                            <ListItem>
+                                                <ListItemText primary="Keep the top of images tagged" secondary="Empty = common tags such as portrait or upper_body"/>
+                                                <Autocomplete
+                                                    options={[]}
+                                                    style={{minWidth: 532}}
+                                                    value={crop.top_tags}
+                                                    multiple freeSolo size="small"
+                                                    renderInput={(params) => (
+                                                        <TextField placeholder="Tags" variant="standard" {...params}/>
+                                                    )}
+                                                    onChange={(_, v) => set_crop({...crop, top_tags: v})}
+                                                />
+                                            </ListItem>
+                                            <ListItem>
+                                                <ListItemText primary="Keep the bottom of images tagged" secondary="Empty = common tags such as lower_body or feet"/>
+                                                <Autocomplete
+                                                    options={[]}
+                                                    style={{minWidth: 532}}
+                                                    value={crop.bottom_tags}
+                                                    multiple freeSolo size="small"
+                                                    renderInput={(params) => (
+                                                        <TextField placeholder="Tags" variant="standard" {...params}/>
+                                                    )}
+                                                    onChange={(_, v) => set_crop({...crop, bottom_tags: v})}
+                                                />
+                                            </ListItem>
+                                            </List>
+                                        )
+                                    }
+
+                                    <ListItem>
+                                        <ListItemIcon><AspectRatio color="primary"/></ListItemIcon>
+                                        <ListItemText
+                                            primary="Aspect-ratio bucketing"
+                                            secondary="Resize and crop each image to the bucket closest to its aspect ratio, instead of letterboxing and resizing"
+                                        />
+                                        <Switch
+                                            checked={bucketing.enabled}
+                                            onChange={(_, v) => set_bucketing({...bucketing, enabled: v})}
+                                        />
+                                    </ListItem>
+                                    {
+                                        bucketing.enabled && (
+                                            <ListItem>
+                                                <ListItemText secondary="0 = 1048576 pixels, steps of 64 and a maximum aspect ratio of 2"/>
+                                                <Stack direction="row" spacing={4} alignItems="center">
+                                                <TextField
+                                                    style={{minWidth: 250}}
+                                                    size="small" variant="standard" label="Area"
+                                                    type="number" inputProps={{min: 0, step: 4096}}
+                                                    value={bucketing.area} onChange={(e) => {
+                                                        set_bucketing({...bucketing, area: Math.max(0, +e.target.value)})
+                                                    }}
+                                                />
+                                                <TextField
+                                                    style={{minWidth: 250}}
+                                                    size="small" variant="standard" label="Step"
+                                                    type="number" inputProps={{min: 0, step: 8}}
+                                                    value={bucketing.step} onChange={(e) => {
+                                                        set_bucketing({...bucketing, step: Math.max(0, +e.target.value)})
+                                                    }}
+                                                />
+                                                <TextField
+                                                    style={{minWidth: 250}}
+                                                    size="small" variant="standard" label="Max aspect ratio"
+                                                    type="number" inputProps={{min: 0, step: 0.25}}
+                                                    value={bucketing.max_aspect_ratio} onChange={(e) => {
+                                                        set_bucketing({...bucketing, max_aspect_ratio: Math.max(0, +e.target.value)})
+                                                    }}
+                                                />
+                                                </Stack>
+                                            </ListItem>
+                                        )
+                                    }
+
                                     <ListItem>
                                         <ListItemIcon><Article color="primary"/></ListItemIcon>
                                         <ListItemText primary="Target image format"/>
@@ -521,6 +828,129 @@ function EditDatasetDialog(props: EditProps): ReactElement {
                                             <MenuItem value={"WebP"}>WebP</MenuItem>
                                         </TextField>
                                     </ListItem>
+
+                                    <ListItem>
+                                        <ListItemIcon><Movie color="primary"/></ListItemIcon>
+                                        <ListItemText
+                                            primary="Frame interval"
+                                            secondary="Export every Nth frame of animations and videos (0 = first frame only)"
+                                        />
+                                        <TextField
+                                            style={{minWidth: 250}}
+                                            size="small" variant="standard" label="Interval"
+                                            type="number" inputProps={{min: 0}}
+                                            value={frame_interval} onChange={(e) => {
+                                                set_frame_interval(Math.max(0, +e.target.value))
+                                            }}
+                                        />
+                                    </ListItem>
+                                </List>
+                            </AccordionDetails>
+                        </Accordion>
+                    </ListItem>
+
+                    <ListItem>
+                        <Accordion style={{width: "100%"}}>
+                            <AccordionSummary expandIcon={<ExpandMore/>}>
+                                <Typography>Caption settings</Typography>
+                            </AccordionSummary>
+                            <AccordionDetails>
+                                <List>
+                                    <ListItem>
+                                        <ListItemIcon><Textsms color="primary"/></ListItemIcon>
+                                        <ListItemText
+                                            primary="Template"
+                                            secondary="{keyword}, {rating}, {tags} or any tag category, e.g. {character}"
+                                        />
+                                        <TextField
+                                            style={{minWidth: 532}}
+                                            type="text" size="small" variant="standard" label="Template"
+                                            placeholder="{tags}"
+                                            value={caption.template} onChange={(e) => {
+                                                set_caption({...caption, template: e.target.value})
+                                            }}
+                                        />
+                                    </ListItem>
+                                    <ListItem>
+                                        <ListItemIcon><Sort color="primary"/></ListItemIcon>
+                                        <ListItemText primary="Tag order"/>
+                                        <TextField
+                                            select
+                                            style={{minWidth: 532}}
+                                            size="small" variant="standard" label="Order"
+                                            value={caption.order} onChange={(e) => {
+                                                set_caption({...caption, order: e.target.value as any})
+                                            }}
+                                        >
+                                            <MenuItem value={"Source"}>As provided by the source</MenuItem>
+                                            <MenuItem value={"Category"}>By category</MenuItem>
+                                            <MenuItem value={"Frequency"}>Most frequent first</MenuItem>
+                                            <MenuItem value={"Alphabetical"}>Alphabetical</MenuItem>
+                                            <MenuItem value={"Shuffle"}>Shuffled</MenuItem>
+                                        </TextField>
+                                    </ListItem>
+                                    {
+                                        caption.order === "Category" && (
+                                            <ListItem>
+                                                <ListItemIcon><Category color="primary"/></ListItemIcon>
+                                                <ListItemText primary="Category order"/>
+                                                <Autocomplete
+                                                    options={tag_categories}
+                                                    style={{minWidth: 532}}
+                                                    value={caption.category_order}
+                                                    filterSelectedOptions={true}
+                                                    multiple freeSolo size="small"
+                                                    renderInput={(params) => (
+                                                        <TextField placeholder="Categories" variant="standard" {...params}/>
+                                                    )}
+                                                    onChange={(_, v) => set_caption({...caption, category_order: v})}
+                                                />
+                                            </ListItem>
+                                        )
+                                    }
+                                    {
+                                        caption.order === "Shuffle" && (
+                                            <ListItem>
+                                                <ListItemIcon><Shuffle color="primary"/></ListItemIcon>
+                                                <ListItemText primary="Shuffle seed"/>
+                                                <TextField
+                                                    style={{minWidth: 532}}
+                                                    type="number" inputProps={{min: 0}}
+                                                    size="small" variant="standard" label="Seed"
+                                                    value={caption.seed} onChange={(e) => {
+                                                        set_caption({...caption, seed: Math.max(0, Math.floor(+e.target.value))})
+                                                    }}
+                                                />
+                                            </ListItem>
+                                        )
+                                    }
+                                    <ListItem>
+                                        <ListItemIcon><PushPin color="primary"/></ListItemIcon>
+                                        <ListItemText
+                                            primary="Keep tokens"
+                                            secondary="Leading tokens that are never reordered or dropped"
+                                        />
+                                        <TextField
+                                            style={{minWidth: 532}}
+                                            type="number" inputProps={{min: 0}}
+                                            size="small" variant="standard" label="Tokens"
+                                            value={caption.keep_tokens} onChange={(e) => {
+                                                set_caption({...caption, keep_tokens: Math.max(0, +e.target.value)})
+                                            }}
+                                        />
+                                    </ListItem>
+                                    <ListItem>
+                                        <ListItemIcon><FormatListNumbered color="primary"/></ListItemIcon>
+                                        <ListItemText primary="Maximum tags" secondary="0 = no limit"/>
+                                        <TextField
+                                            style={{minWidth: 532}}
+                                            type="number" inputProps={{min: 0}}
+                                            size="small" variant="standard" label="Tags"
+                                            value={caption.max_tags} onChange={(e) => {
+                                                set_caption({...caption, max_tags: Math.max(0, +e.target.value)})
+                                            }}
+                                        />
+                                    </ListItem>
                                 </List>
                             </AccordionDetails>
                         </Accordion>
@@ -560,12 +990,366 @@ function EditDatasetDialog(props: EditProps): ReactElement {
                             </AccordionDetails>
                         </Accordion>
                     </ListItem>
+
+                    <ListItem>
+                        <Accordion style={{width: "100%"}}>
+                            <AccordionSummary expandIcon={<ExpandMore/>}>
+                                <Typography>Export settings</Typography>
+                            </AccordionSummary>
+                            <AccordionDetails>
+                                <List>
+                                    <ListItem>
+                                        <ListItemIcon><BarChart color="primary"/></ListItemIcon>
+                                        <ListItemText
+                                            primary="Export tag statistics"
+                                            secondary="Write tag and category frequencies as CSV and JSON next to the exported folder"
+                                        />
+                                        <Switch checked={tag_stats} onChange={(_, v) => set_tag_stats(v)}/>
+                                    </ListItem>
+                                    <ListItem>
+                                        <ListItemIcon><FileUpload color="primary"/></ListItemIcon>
+                                        <ListItemText primary="Format"/>
+                                        <TextField
+                                            select
+                                            style={{minWidth: 532}}
+                                            size="small" variant="standard" label="Format"
+                                            value={export_format} onChange={(e) => {
+                                                set_export_format(e.target.value as any)
+                                            }}
+                                        >
+                                            <MenuItem value={"Folder"}>Image folder with .txt captions</MenuItem>
+                                            <MenuItem value={"Kohya"}>kohya-ss dataset config (.txt captions) and meta_cap.json</MenuItem>
+                                            <MenuItem value={"ImageFolder"}>Hugging Face imagefolder with metadata.jsonl</MenuItem>
+                                            <MenuItem value={"WebDataset"}>WebDataset tar shards</MenuItem>
+                                        </TextField>
+                                    </ListItem>
+                                    {
+                                        export_format === "Kohya" && (
+                                            <List>
+                                                <ListItem>
+                                                    <ListItemText primary="Resolution" secondary="0 = the resize setting, or 512"/>
+                                                    <TextField
+                                                        style={{minWidth: 250}}
+                                                        type="number" inputProps={{min: 0}}
+                                                        size="small" variant="standard" label="Resolution"
+                                                        value={kohya.resolution} onChange={(e) => {
+                                                            set_kohya({...kohya, resolution: Math.max(0, +e.target.value)})
+                                                        }}
+                                                    />
+                                                </ListItem>
+                                                <ListItem>
+                                                    <ListItemText primary="Batch size" secondary="0 = trainer default"/>
+                                                    <TextField
+                                                        style={{minWidth: 250}}
+                                                        type="number" inputProps={{min: 0}}
+                                                        size="small" variant="standard" label="Batch size"
+                                                        value={kohya.batch_size} onChange={(e) => {
+                                                            set_kohya({...kohya, batch_size: Math.max(0, +e.target.value)})
+                                                        }}
+                                                    />
+                                                </ListItem>
+                                                <ListItem>
+                                                    <ListItemText primary="Shuffle captions" secondary="Keeps the first tokens set in the caption settings"/>
+                                                    <Switch
+                                                        checked={kohya.shuffle_caption}
+                                                        onChange={(_, v) => set_kohya({...kohya, shuffle_caption: v})}
+                                                    />
+                                                </ListItem>
+                                                <ListItem>
+                                                    <ListItemText primary="Enable bucketing"/>
+                                                    <Switch
+                                                        checked={kohya.enable_bucket}
+                                                        onChange={(_, v) => set_kohya({...kohya, enable_bucket: v})}
+                                                    />
+                                                </ListItem>
+                                                <ListItem>
+                                                    <ListItemText primary="Minimum bucket resolution" secondary="0 = trainer default"/>
+                                                    <TextField
+                                                        style={{minWidth: 250}}
+                                                        type="number" inputProps={{min: 0}}
+                                                        size="small" variant="standard" label="Minimum bucket resolution"
+                                                        value={kohya.min_bucket_reso} onChange={(e) => {
+                                                            set_kohya({...kohya, min_bucket_reso: Math.max(0, +e.target.value)})
+                                                        }}
+                                                    />
+                                                </ListItem>
+                                                <ListItem>
+                                                    <ListItemText primary="Maximum bucket resolution" secondary="0 = trainer default"/>
+                                                    <TextField
+                                                        style={{minWidth: 250}}
+                                                        type="number" inputProps={{min: 0}}
+                                                        size="small" variant="standard" label="Maximum bucket resolution"
+                                                        value={kohya.max_bucket_reso} onChange={(e) => {
+                                                            set_kohya({...kohya, max_bucket_reso: Math.max(0, +e.target.value)})
+                                                        }}
+                                                    />
+                                                </ListItem>
+                                                <ListItem>
+                                                    <ListItemText primary="Bucket resolution steps" secondary="0 = trainer default"/>
+                                                    <TextField
+                                                        style={{minWidth: 250}}
+                                                        type="number" inputProps={{min: 0}}
+                                                        size="small" variant="standard" label="Bucket resolution steps"
+                                                        value={kohya.bucket_reso_steps} onChange={(e) => {
+                                                            set_kohya({...kohya, bucket_reso_steps: Math.max(0, +e.target.value)})
+                                                        }}
+                                                    />
+                                                </ListItem>
+                                            </List>
+                                        )
+                                    }
+                                    {
+                                        export_format === "WebDataset" && (
+                                            <ListItem>
+                                                <ListItemText primary="Shard size" secondary="Images per tar shard, 0 = 1000"/>
+                                                <TextField
+                                                    style={{minWidth: 250}}
+                                                    type="number" inputProps={{min: 0}}
+                                                    size="small" variant="standard" label="Shard size"
+                                                    value={shard_size} onChange={(e) => {
+                                                        set_shard_size(Math.max(0, +e.target.value))
+                                                    }}
+                                                />
+                                            </ListItem>
+                                        )
+                                    }
+                                </List>
+                            </AccordionDetails>
+                        </Accordion>
+                    </ListItem>
                 </List>
             </DialogContent>
         </Dialog>
     );
 }
 
+interface CaptionOverrideProps {
+    /** The image being edited, if any. */
+    path: string | null,
+    tags: string[],
+    dataset: () => Dataset,
+    overrides: {[path: string]: CaptionOverride},
+    set_overrides: (overrides: {[path: string]: CaptionOverride}) => void,
+    close: () => void,
+}
+
+function CaptionOverrideDialog(props: CaptionOverrideProps): ReactElement {
+    const [edits, set_edits] = useState(EMPTY_OVERRIDE);
+    const [preview, set_preview] = useState("");
+
+    useEffect(() => {
+        if(props.path !== null) set_edits(props.overrides[props.path] ?? EMPTY_OVERRIDE);
+    }, [props.path]);
+
+    const with_edits = () => {
+        const overrides = {...props.overrides};
+        if(props.path !== null) overrides[props.path] = edits;
+        return overrides;
+    };
+
+    useEffect(() => {
+        if(props.path === null) return;
+        const dataset = {...props.dataset(), overrides: with_edits()};
+        invoke<{[path: string]: string}>("get_dataset_captions", {dataset, imagePaths: [props.path]})
+            .then(captions => set_preview(captions[props.path!] ?? ""));
+    }, [props.path, edits]);
+
+    const save = () => {
+        props.set_overrides(with_edits());
+        props.close();
+    };
+
+    const reset = () => {
+        const overrides = {...props.overrides};
+        if(props.path !== null) delete overrides[props.path];
+        props.set_overrides(overrides);
+        props.close();
+    };
+
+    return (
+        <Dialog open={props.path !== null} maxWidth="md" fullWidth>
+            <DialogTitle>Edit caption</DialogTitle>
+            <DialogContent>
+                <Stack spacing={2} paddingTop={1}>
+                    <Autocomplete
+                        options={props.tags} value={edits.added_tags}
+                        multiple freeSolo size="small" ChipProps={{color: "success"}}
+                        renderInput={(params) => <TextField label="Added tags" variant="standard" {...params}/>}
+                        onChange={(_, added_tags) => set_edits({...edits, added_tags})}
+                    />
+                    <Autocomplete
+                        options={props.tags} value={edits.removed_tags}
+                        multiple freeSolo size="small" ChipProps={{color: "error"}}
+                        renderInput={(params) => <TextField label="Removed tags" variant="standard" {...params}/>}
+                        onChange={(_, removed_tags) => set_edits({...edits, removed_tags})}
+                    />
+                    <TextField
+                        multiline fullWidth size="small" variant="standard"
+                        label="Custom caption" placeholder="Leave empty to generate the caption from the tags"
+                        value={edits.caption ?? ""}
+                        onChange={(e) => set_edits({...edits, caption: e.target.value || null})}
+                    />
+                    <Typography variant="body2" color="text.secondary">{preview}</Typography>
+                </Stack>
+            </DialogContent>
+            <DialogActions>
+                <Button onClick={reset}>Reset</Button>
+                <Button onClick={props.close}>Cancel</Button>
+                <Button onClick={save}>Save</Button>
+            </DialogActions>
+        </Dialog>
+    );
+}
+
+interface BulkEditProps {
+    open: boolean,
+    tags: string[],
+    apply: (edit: TagEdit) => Promise<void>,
+    close: () => void,
+}
+
+function BulkEditDialog(props: BulkEditProps): ReactElement {
+    const [operation, set_operation] = useState("Add" as "Add" | "Remove" | "Replace");
+    const [tag, set_tag] = useState("");
+    const [replacement, set_replacement] = useState("");
+
+    const apply = async () => {
+        const edit: TagEdit = operation === "Add" ? {Add: tag}
+            : operation === "Remove" ? {Remove: tag}
+            : {Replace: [tag, replacement]};
+
+        await props.apply(edit);
+        props.close();
+    };
+
+    return (
+        <Dialog open={props.open} maxWidth="sm" fullWidth>
+            <DialogTitle>Bulk edit tags</DialogTitle>
+            <DialogContent>
+                <Stack spacing={2} paddingTop={1}>
+                    <TextField
+                        select size="small" variant="standard" label="Operation"
+                        value={operation} onChange={(e) => set_operation(e.target.value as any)}
+                    >
+                        <MenuItem value="Add">Add a tag to every image</MenuItem>
+                        <MenuItem value="Remove">Remove a tag from every image</MenuItem>
+                        <MenuItem value="Replace">Replace a tag on every image</MenuItem>
+                    </TextField>
+                    <Autocomplete
+                        options={props.tags} value={tag} freeSolo size="small"
+                        renderInput={(params) => <TextField label="Tag" variant="standard" {...params}/>}
+                        onInputChange={(_, v) => set_tag(v)}
+                    />
+                    {
+                        operation === "Replace" && (
+                            <TextField
+                                size="small" variant="standard" label="Replacement"
+                                value={replacement} onChange={(e) => set_replacement(e.target.value)}
+                            />
+                        )
+                    }
+                </Stack>
+            </DialogContent>
+            <DialogActions>
+                <Button onClick={props.close}>Cancel</Button>
+                <Button disabled={!tag || (operation === "Replace" && !replacement)} onClick={apply}>
+                    Apply
+                </Button>
+            </DialogActions>
+        </Dialog>
+    );
+}
+
+interface StatsProps {
+    index: number,
+    datasets: Dataset[],
+    close: () => void,
+}
+
+function DatasetStatsDialog(props: StatsProps): ReactElement {
+    const [stats, set_stats] = useState(null as DatasetStats | null);
+    const dataset = props.datasets[props.index];
+
+    useEffect(() => {
+        set_stats(null);
+        if(props.index === -1) return;
+        get_dataset_stats(props.index)
+            .then(set_stats)
+            .catch(async e => {
+                await message(describe_error(e), {title: "Could not compute statistics", type: "error"});
+                props.close();
+            });
+    }, [props.index]);
+
+    const export_stats = async () => {
+        try {
+            const path = await open({directory: true, multiple: false, title: "Choose export directory"});
+            if(path !== null) await export_dataset_stats(props.index, path as string);
+        } catch (e) {
+            await message(describe_error(e), {title: "Could not export statistics", type: "error"});
+        }
+    };
+
+    return (
+        <Dialog open={props.index !== -1} maxWidth="md" fullWidth>
+            <DialogTitle>
+                <Stack direction="row" alignItems="center">
+                    <Typography width="100%" fontSize="22px">
+                        {dataset ? `${dataset.name} statistics` : "Statistics"}
+                    </Typography>
+                    <IconButton disabled={stats === null} onClick={export_stats}>
+                        <FileUpload/>
+                    </IconButton>
+                    <IconButton onClick={props.close}>
+                        <Close/>
+                    </IconButton>
+                </Stack>
+            </DialogTitle>
+            <DialogContent>
+                {
+                    stats === null ? <LinearProgress/> : (
+                        <Stack spacing={2}>
+                            <Typography>
+                                {stats.images} images, {stats.tags.length} distinct tags.
+                            </Typography>
+                            <Typography variant="h6">Categories</Typography>
+                            <List dense>
+                                {stats.categories.map(c => (
+                                    <ListItem key={c.category}>
+                                        <ListItemText
+                                            primary={c.category}
+                                            secondary={`${c.count} tags, ${c.tags} distinct`}
+                                        />
+                                    </ListItem>
+                                ))}
+                            </List>
+                            <Typography variant="h6">Most frequent tags</Typography>
+                            <List dense>
+                                {stats.tags.slice(0, 50).map(tag => (
+                                    <ListItem key={tag.tag}>
+                                        <ListItemText
+                                            primary={tag.tag}
+                                            secondary={`${tag.category ?? "uncategorized"} · ${tag.count} images (${(tag.frequency * 100).toFixed(1)}%)`}
+                                        />
+                                    </ListItem>
+                                ))}
+                            </List>
+                            <Typography variant="h6">Most frequent pairs</Typography>
+                            <List dense>
+                                {stats.co_occurrence.slice(0, 20).map(pair => (
+                                    <ListItem key={`${pair.first} ${pair.second}`}>
+                                        <ListItemText primary={`${pair.first} + ${pair.second}`} secondary={`${pair.count} images`}/>
+                                    </ListItem>
+                                ))}
+                            </List>
+                        </Stack>
+                    )
+                }
+            </DialogContent>
+        </Dialog>
+    );
+}
+
 interface ManageImagesProps {
     settings: Settings,
     saved_images: SavedImages,
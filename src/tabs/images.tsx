@@ -1,15 +1,17 @@
-import React, {ReactElement, useMemo, useState} from "react";
+import React, {ReactElement, useEffect, useMemo, useState} from "react";
 import {
     Button,
     Dialog, DialogActions,
     DialogContent,
-    DialogTitle, IconButton, Stack
+    DialogTitle, IconButton, Stack, TextField
 } from "@mui/material";
 import {removeFile} from "@tauri-apps/api/fs";
 import { Settings } from "./settings";
 import {PaginatedImageList} from "../components/images";
 import {Delete} from "@mui/icons-material";
-import {SavedImages} from "../bindings/images";
+import {ImagePage, query_images, SavedImages} from "../bindings/images";
+
+const IMAGES_PER_PAGE = 128;
 
 interface Props {
     settings: Settings,
@@ -19,6 +21,15 @@ interface Props {
 
 export function Images(props: Props): ReactElement {
     const [to_delete, set_to_delete] = useState(null as null | string);
+    const [filter, set_filter] = useState("");
+    const [page, set_page] = useState(0);
+    const [result, set_result] = useState({images: [], total: 0} as ImagePage);
+
+    const tags = useMemo(() => filter.split(/\s+/).filter(t => t.length !== 0), [filter]);
+
+    useEffect(() => {
+        query_images({tags, page, limit: IMAGES_PER_PAGE}).then(set_result).catch(console.error);
+    }, [props.images, tags, page]);
 
     const delete_image = async (path: string) => {
         try {
@@ -33,8 +44,11 @@ export function Images(props: Props): ReactElement {
     const images = useMemo(() => {
         return (
             <PaginatedImageList
-                imagesPerPage={128}
-                images={props.images.get_all()} 
+                key={filter}
+                imagesPerPage={IMAGES_PER_PAGE}
+                images={result.images}
+                pageCount={Math.ceil(result.total / IMAGES_PER_PAGE)}
+                onPageChange={set_page}
                 fixedPageButtons={true} loadWhenVisible={true}
                 actionIcon={image => (
                     <IconButton onClick={() => delete_image(image.file_path)}>
@@ -43,10 +57,15 @@ export function Images(props: Props): ReactElement {
                 )}
             />
         );
-    }, [props.images]);
+    }, [result]);
     
     return (
         <Stack>
+            <TextField
+                size="small" variant="standard" label="Filter tags (-tag to exclude, * as wildcard)"
+                style={{margin: "0 .5em"}}
+                value={filter} onChange={e => { set_filter(e.target.value); set_page(0); }}
+            />
             {images}
             {/* DELETE DIALOG */}
             <Dialog open={to_delete !== null}>
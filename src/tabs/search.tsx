@@ -10,7 +10,7 @@ import {
     Stack,
     TextField, Typography
 } from "@mui/material";
-import {Check, Close, Download, Search as SearchIcon} from "@mui/icons-material";
+import {Check, Close, Download, Search as SearchIcon, Subscriptions} from "@mui/icons-material";
 import React, {CSSProperties, ReactElement, useEffect, useState} from "react";
 import {invoke} from "@tauri-apps/api";
 import {TransformComponent, TransformWrapper} from "react-zoom-pan-pinch";
@@ -18,17 +18,41 @@ import {listen} from "@tauri-apps/api/event";
 import {Dataset} from "./datasets";
 import {writeTextFile} from "@tauri-apps/api/fs";
 import {SavedImages} from "../bindings/images";
+import {BulkDownloadProgress, BulkDownloadRequest, QueueItem} from "../bindings/downloads";
+import {describe_error, PhiliaError} from "../bindings/errors";
+import {add_subscription} from "../bindings/subscriptions";
+import {message} from "@tauri-apps/api/dialog";
 
 export interface Source {
     name: string,
     search: boolean,
     tag_list: boolean,
+    tag_tables: boolean,
 }
 
 const EMPTY_SOURCE: Source = {
     name: "",
     search: false,
     tag_list: false,
+    tag_tables: false,
+}
+
+/** Searches every source that supports it, through `federated_search`. */
+const ALL_SOURCES: Source = {
+    name: "All sources",
+    search: true,
+    tag_list: false,
+    tag_tables: false,
+}
+
+interface SourceError {
+    source: string,
+    error: PhiliaError,
+}
+
+interface FederatedResults {
+    posts: Post[],
+    errors: SourceError[],
 }
 
 interface Props {
@@ -75,13 +99,17 @@ export function Search(props: Props): ReactElement[] {
     useEffect(() => {
         if(source === EMPTY_SOURCE) {
             set_tags([]);
+        } else if(source === ALL_SOURCES) {
+            const sources = props.sources.filter(s => s.search);
+            Promise.all(sources.map(s => invoke<string[] | null>("get_source_tags", {source: s.name})))
+                .then(lists => set_tags([...new Set(lists.flatMap(tags => tags ?? []))]));
         } else {
             invoke<string[] | null>("get_source_tags", {source: source.name}).then(set_tags);
         }
     }, [source])
     
     useEffect(() => {
-        if(source === EMPTY_SOURCE || tags === undefined || tags === null) return;
+        if(source === EMPTY_SOURCE || source === ALL_SOURCES || tags === undefined || tags === null) return;
         writeTextFile(`./cache/${source.name}_tags.json`, JSON.stringify(tags, null, 4)).catch(console.error);
     }, [tags])
     
@@ -89,9 +117,32 @@ export function Search(props: Props): ReactElement[] {
         set_selected([]);
     }, [page, results]);
 
+    const search_all = async () => {
+        const results = await invoke<FederatedResults>("federated_search", {
+            sourceNames: props.sources.filter(s => s.search).map(s => s.name),
+            page: page,
+            limit: per_page,
+            order: order,
+            tags: query,
+            merge: order === "MostLiked" || order === "LeastLiked" ? "Score" : "Interleave",
+        });
+        
+        set_results(results.posts);
+        set_searching(false);
+        if(results.errors.length !== 0) {
+            const errors = results.errors.map(e => `${e.source}: ${describe_error(e.error)}`);
+            await message(errors.join("\n"), {title: "Some sources could not be searched", type: "warning"});
+        }
+    };
+    
     const search = async () => {
         try {
             set_searching(true);
+            if(source === ALL_SOURCES) {
+                await search_all();
+                return results;
+            }
+            
             const [posts, tags] = await invoke<[Post[], string[]]>("search", {
                 source: source.name,
                 page: page,
@@ -109,6 +160,7 @@ export function Search(props: Props): ReactElement[] {
             console.error(e);
             set_results([]);
             set_searching(false);
+            await message(describe_error(e), {title: "Search failed", type: "error"});
         }
     };
     
@@ -131,7 +183,7 @@ export function Search(props: Props): ReactElement[] {
             set_saved_images={props.set_images}
             tags={tags} set_tags={set_tags}
             page={page} set_page={set_page}
-            order={order} set_order={set_order}
+            order={order} set_order={set_order} query={query}
             per_page={per_page} set_per_page={set_per_page}
             search={search} results={results} searching={searching}
             source={source} set_source={set_source} sources={props.sources}
@@ -479,6 +531,7 @@ interface ControlsProps {
 
     order: Order,
     set_order: (order: Order) => void,
+    query: string[],
 
     results: Post[],
     
@@ -496,6 +549,21 @@ export function SearchControls(props: ControlsProps): ReactElement {
     const [open_download, set_download_open] = useState(false);
     const close_download = () => set_download_open(false);
     
+    const subscribe = async () => {
+        const name = props.query.length ? `${props.source.name}: ${props.query.join(" ")}` : props.source.name;
+        try {
+            await add_subscription({
+                name, source: props.source.name, tags: props.query, order: "Newest", last_seen_id: null,
+            });
+            await message(`New posts for "${name}" will be downloaded when subscriptions are checked.`, {
+                title: "Subscribed",
+            });
+        }
+        catch (e) {
+            await message(describe_error(e), {title: "Could not subscribe", type: "error"});
+        }
+    };
+    
     return (
         <Stack
             width="100%"
@@ -530,8 +598,9 @@ export function SearchControls(props: ControlsProps): ReactElement {
                 color="primary"
                 variant="standard"
                 value={props.sources.indexOf(props.source)}
-                onChange={(e) => props.set_source(props.sources[+e.target.value])}
+                onChange={(e) => props.set_source(props.sources[+e.target.value] ?? ALL_SOURCES)}
             >
+                <MenuItem value={-1}>{ALL_SOURCES.name}</MenuItem>
                 {props.sources.map((s, i) => <MenuItem key={i} value={i}>{s.name}</MenuItem>)}
             </TextField>
             
@@ -562,6 +631,15 @@ export function SearchControls(props: ControlsProps): ReactElement {
                 Search
             </Button>
             
+            <Button
+                disabled={props.searching || !props.source.search || props.source === ALL_SOURCES}
+                color="primary" variant="contained"
+                startIcon={<Subscriptions/>}
+                onClick={subscribe}
+            >
+                Subscribe
+            </Button>
+            
             <Button
                 disabled={props.searching || props.results.length === 0}
                 color="primary" variant="contained" 
@@ -607,8 +685,11 @@ function DownloadDialog(props: DialogProps): ReactElement {
 
     const [downloading, set_downloading] = useState(false);
     const [download_completion, set_download_completion] = useState(0);
+    const [failed, set_failed] = useState<QueueItem[]>([]);
+    const [max_posts, set_max_posts] = useState(1000);
+    const [bulk, set_bulk] = useState<BulkDownloadProgress | null>(null);
 
-    const download = async () => {
+    const download = async (retry?: Post[]) => {
         let unlisten = await listen<number>("download_progress", event => {
             set_download_completion(event.payload)
         });
@@ -617,12 +698,12 @@ function DownloadDialog(props: DialogProps): ReactElement {
             set_downloading(true);
             set_download_completion(0);
             
-            let posts = props.posts;
-            if(props.selected.length) {
+            let posts = retry ?? props.posts;
+            if(!retry && props.selected.length) {
                 posts = props.selected.map(i => props.posts[i]);
             }
             
-            const new_images = await invoke<string[]>("download_posts", {
+            const items = await invoke<QueueItem[]>("download_posts", {
                 posts, source: props.controls.source,
                 options: {
                     dataset,
@@ -630,6 +711,12 @@ function DownloadDialog(props: DialogProps): ReactElement {
                 }
             });
             
+            const new_images = items
+                .filter(item => item.status === "Completed" && item.file_path)
+                .map(item => item.file_path as string);
+            const failed_items = items.filter(item => item.status === "Failed");
+            set_failed(failed_items);
+            
             if(dataset !== -1) {
                 const selected = props.datasets[dataset];
                 Array.prototype.push.apply(selected.images, new_images);
@@ -639,17 +726,77 @@ function DownloadDialog(props: DialogProps): ReactElement {
             const images = await SavedImages.refresh();
             props.set_saved_images(images);
             props.set_selected([]);
+            
+            if(!failed_items.length) {
+                props.close();
+            }
         }
         catch (e) {
             console.error(e);
+            close();
         }
         finally {
             set_downloading(false);
-            props.close();
             unlisten();
         }
     };
     
+    const close = () => {
+        set_failed([]);
+        props.close();
+    };
+    
+    const download_all = async () => {
+        const unlisten = await listen<BulkDownloadProgress>("bulk_download_progress", event => {
+            set_bulk(event.payload);
+        });
+        
+        try {
+            set_bulk({id: 0, pages: 0, queued: 0, finished: false, cancelled: false, error: null});
+            const request: BulkDownloadRequest = {
+                source: props.controls.source.name,
+                tags: props.controls.query,
+                order: props.controls.order,
+                max_posts,
+            };
+            
+            const progress = await invoke<BulkDownloadProgress>("download_search", {request});
+            if(progress.error !== null) {
+                await message(describe_error(progress.error), {title: "Stopped fetching results", type: "warning"});
+            }
+        }
+        catch (e) {
+            await message(describe_error(e), {title: "Could not download results", type: "error"});
+        }
+        finally {
+            set_bulk(null);
+            unlisten();
+            close();
+        }
+    };
+    
+    if(bulk !== null) {
+        return (
+            <Dialog open={props.is_open} maxWidth="sm" fullWidth>
+                <DialogTitle>Queueing search results...</DialogTitle>
+                <DialogContent>
+                    <Stack spacing={2}>
+                        <Typography>Pages: {bulk.pages} · Queued posts: {bulk.queued}</Typography>
+                        <LinearProgress
+                            variant={max_posts ? "determinate" : "indeterminate"}
+                            value={max_posts ? Math.min(100, bulk.queued / max_posts * 100) : undefined}
+                        />
+                    </Stack>
+                </DialogContent>
+                <DialogActions>
+                    <Button onClick={() => invoke("cancel_bulk_download", {id: bulk.id || null})}>
+                        Cancel
+                    </Button>
+                </DialogActions>
+            </Dialog>
+        );
+    }
+    
     if(downloading) {
         return (
             <Dialog open={props.is_open} maxWidth="sm" fullWidth>
@@ -663,6 +810,30 @@ function DownloadDialog(props: DialogProps): ReactElement {
             </Dialog>
         );
     }
+    else if(failed.length) {
+        return (
+            <Dialog open={props.is_open} onClose={close} maxWidth="sm" fullWidth>
+                <DialogTitle>Failed to download {failed.length} posts</DialogTitle>
+                <DialogContent>
+                    <Stack spacing={1}>
+                        {failed.map(item => (
+                            <Typography key={item.key} variant="body2">
+                                {item.key}: {item.report?.message ?? item.error ?? "Unknown error"}
+                            </Typography>
+                        ))}
+                    </Stack>
+                </DialogContent>
+                <DialogActions>
+                    <Button onClick={() => download(failed.map(item => item.post))}>
+                        Retry failed
+                    </Button>
+                    <Button onClick={close}>
+                        Close
+                    </Button>
+                </DialogActions>
+            </Dialog>
+        );
+    }
     else {
         return (
             <Dialog open={props.is_open} onClose={props.close} maxWidth="sm" fullWidth>
@@ -683,6 +854,16 @@ function DownloadDialog(props: DialogProps): ReactElement {
                             ))}
                         </TextField>
 
+                        <TextField
+                            type="number" fullWidth
+                            label="Maximum posts for 'Download all results' (0 for no limit)"
+                            color="primary"
+                            variant="standard"
+                            inputProps={{min: 0}}
+                            value={max_posts}
+                            onChange={e => set_max_posts(Math.max(parseInt(e.target.value) || 0, 0))}
+                        />
+
                         {/*<TextField*/}
                         {/*    disabled */}
                         {/*    select fullWidth*/}
@@ -697,9 +878,12 @@ function DownloadDialog(props: DialogProps): ReactElement {
                     </Stack>
                 </DialogContent>
                 <DialogActions>
-                    <Button onClick={download}>
+                    <Button onClick={() => download()}>
                         Download
                     </Button>
+                    <Button disabled={props.controls.source === ALL_SOURCES} onClick={download_all}>
+                        Download all results
+                    </Button>
                     <Button onClick={props.close}>
                         Cancel
                     </Button>
@@ -730,6 +914,9 @@ function NoTagsDialog(
             const tags = await invoke<string[]>("fetch_source_tags", {source: source.name});
             set_tags(tags);
         }
+        catch (e) {
+            await message(describe_error(e), {title: "Could not fetch tags", type: "error"});
+        }
         finally {
             set_fetching(-1);
             unlisten();
@@ -1,16 +1,24 @@
-import React, {ReactElement, useRef} from "react";
+import React, {ReactElement, useEffect, useRef, useState} from "react";
 import {
+    IconButton,
     List,
     ListItem,
     ListItemButton,
     ListItemIcon,
     ListItemText, ListSubheader, MenuItem, Stack, Switch, TextField, Typography
 } from "@mui/material";
-import {DarkMode, Folder, FormatListNumbered, Image, Update, ViewColumn} from "@mui/icons-material";
+import {
+    DarkMode, Folder, FormatListNumbered, Image, Save, Update, ViewColumn, FilterNone,
+    Delete, Schedule, Subscriptions, Sync, LocalOffer, Refresh
+} from "@mui/icons-material";
 import {Source} from "./search"
 import {open} from "@tauri-apps/api/dialog";
 import {invoke} from "@tauri-apps/api";
 import {SavedImages} from "../bindings/images";
+import {
+    check_subscriptions, del_subscription, get_subscriptions, Subscription
+} from "../bindings/subscriptions";
+import {describe_error} from "../bindings/errors";
 
 export interface Settings {
     dark_mode: boolean,
@@ -22,6 +30,13 @@ export interface Settings {
 
     download_folder: string,
     update_branch: "Stable" | "Nightly",
+
+    download_concurrency: number,
+    requests_per_second: number,
+    source_requests_per_second: {[source: string]: number},
+    storage_mode: "ConvertToPng" | "OriginalWithSidecar" | "OriginalEmbedded",
+    skip_duplicate_downloads: boolean,
+    subscription_check_interval: number,
 }
 
 export const SETTINGS_PLACEHOLDER: Settings = {
@@ -32,6 +47,12 @@ export const SETTINGS_PLACEHOLDER: Settings = {
     full_resolution_preview: false,
     download_folder: "",
     update_branch: "Stable",
+    download_concurrency: 6,
+    requests_per_second: 4,
+    source_requests_per_second: {},
+    storage_mode: "ConvertToPng",
+    skip_duplicate_downloads: false,
+    subscription_check_interval: 0,
 }
 
 interface Props {
@@ -49,6 +70,8 @@ export function Settings(props: Props): ReactElement {
             {GeneralSettings(props)}
             {SearchSettings(props)}
             {DownloadSettings(props)}
+            <SubscriptionSettings {...props}/>
+            <TagTableSettings {...props}/>
         </Stack>
     );
 }
@@ -207,6 +230,176 @@ function DownloadSettings(props: Props): ReactElement {
                 <ListItemText primary="Download folder"/>
                 <Typography>{props.settings.download_folder}</Typography>
             </ListItemButton>
+
+            <ListItem>
+                <ListItemIcon><Save color="primary"/></ListItemIcon>
+                <ListItemText
+                    primary="Storage format"
+                    secondary="How downloaded images and their tags are saved."
+                />
+                <TextField
+                    select
+                    label="Format"
+                    color="primary"
+                    variant="standard"
+                    value={props.settings.storage_mode}
+                    onChange={(e) => {
+                        const settings = {...props.settings};
+                        settings.storage_mode = e.target.value as any;
+                        props.set_settings(settings);
+                    }}
+                    style={W_250_STYLE}
+                >
+                    <MenuItem value={"ConvertToPng"}>Convert to PNG</MenuItem>
+                    <MenuItem value={"OriginalWithSidecar"}>Original + JSON sidecar</MenuItem>
+                    <MenuItem value={"OriginalEmbedded"}>Original + embedded metadata</MenuItem>
+                </TextField>
+            </ListItem>
+
+            <ListItem>
+                <ListItemIcon><FilterNone color="primary"/></ListItemIcon>
+                <ListItemText
+                    primary="Skip duplicates"
+                    secondary="Don't download posts that look the same as an image you already have."
+                />
+                <Switch
+                    checked={props.settings.skip_duplicate_downloads}
+                    onChange={(_, checked) => {
+                        const settings = {...props.settings};
+                        settings.skip_duplicate_downloads = checked;
+                        props.set_settings(settings);
+                    }}
+                />
+            </ListItem>
         </List>
     );
-}
\ No newline at end of file
+}
+
+function SubscriptionSettings(props: Props): ReactElement {
+    const [subscriptions, set_subscriptions] = useState([] as Subscription[]);
+    const [checking, set_checking] = useState(false);
+    const [status, set_status] = useState("");
+
+    useEffect(() => {
+        get_subscriptions().then(set_subscriptions);
+    }, []);
+
+    const check = async () => {
+        try {
+            set_checking(true);
+            const checks = await check_subscriptions();
+            const new_posts = checks.reduce((sum, check) => sum + check.new_posts, 0);
+            const failed = checks.filter(check => check.error !== null);
+            set_status(failed.length
+                ? `Queued ${new_posts} posts. ${failed.map(c => `${c.name}: ${describe_error(c.error)}`).join("; ")}`
+                : `Queued ${new_posts} posts.`
+            );
+            set_subscriptions(await get_subscriptions());
+        }
+        finally {
+            set_checking(false);
+        }
+    };
+
+    return (
+        <List>
+            <ListSubheader>Subscriptions</ListSubheader>
+
+            <ListItem>
+                <ListItemIcon><Schedule color="primary"/></ListItemIcon>
+                <ListItemText
+                    primary="Check interval"
+                    secondary="Minutes between automatic checks for new posts. Zero disables them."
+                />
+                <TextField
+                    type="number" inputProps={{min: 0}}
+                    size="small" variant="standard" label="Minutes"
+                    value={props.settings.subscription_check_interval}
+                    onChange={(e) => {
+                        const settings = {...props.settings};
+                        settings.subscription_check_interval = Math.max(parseInt(e.target.value) || 0, 0);
+                        props.set_settings(settings);
+                    }}
+                    style={W_250_STYLE}
+                />
+            </ListItem>
+
+            <ListItemButton disabled={checking} onClick={check}>
+                <ListItemIcon><Sync color="primary"/></ListItemIcon>
+                <ListItemText
+                    primary={checking ? "Checking subscriptions..." : "Check subscriptions now"}
+                    secondary={status || "Queue the posts newer than the last ones seen."}
+                />
+            </ListItemButton>
+
+            {subscriptions.map(subscription => (
+                <ListItem
+                    key={subscription.name}
+                    secondaryAction={
+                        <IconButton
+                            edge="end"
+                            onClick={async () => set_subscriptions(await del_subscription(subscription.name))}
+                        >
+                            <Delete/>
+                        </IconButton>
+                    }
+                >
+                    <ListItemIcon><Subscriptions color="primary"/></ListItemIcon>
+                    <ListItemText
+                        primary={subscription.name}
+                        secondary={`${subscription.source} · ${subscription.order} · last seen: ${subscription.last_seen_id ?? "never checked"}`}
+                    />
+                </ListItem>
+            ))}
+        </List>
+    );
+}
+
+function TagTableSettings(props: Props): ReactElement {
+    const [busy, set_busy] = useState(false);
+    const [status, set_status] = useState("");
+
+    const run = async (description: string, command: string, args?: any) => {
+        try {
+            set_busy(true);
+            set_status(description);
+            await invoke(command, args);
+            props.set_saved_images(await SavedImages.refresh());
+            set_status("");
+        }
+        catch (e) {
+            set_status(describe_error(e));
+        }
+        finally {
+            set_busy(false);
+        }
+    };
+
+    return (
+        <List>
+            <ListSubheader>Tag normalization</ListSubheader>
+
+            <ListItemButton disabled={busy} onClick={() => run("Reloading...", "reload_tag_tables")}>
+                <ListItemIcon><Refresh color="primary"/></ListItemIcon>
+                <ListItemText
+                    primary="Reload tag tables"
+                    secondary={status || "Apply the aliases and implications found in the tags folder."}
+                />
+            </ListItemButton>
+
+            {props.sources.filter(source => source.tag_tables).map(source => (
+                <ListItemButton
+                    key={source.name}
+                    disabled={busy}
+                    onClick={() => run(`Fetching ${source.name} tag tables...`, "fetch_tag_tables", {source: source.name})}
+                >
+                    <ListItemIcon><LocalOffer color="primary"/></ListItemIcon>
+                    <ListItemText
+                        primary={`Fetch ${source.name} aliases and implications`}
+                        secondary="Replaces the tables previously fetched from this source."
+                    />
+                </ListItemButton>
+            ))}
+        </List>
+    );
+}